@@ -1,38 +1,192 @@
+#![cfg_attr(feature = "nightly", feature(test))]
+
 #[allow(dead_code)]
 mod day10;
 
+use std::{cmp, fmt, thread};
+use std::io::{self, Write};
+use std::ops::Range;
+
+
+/// Disjoint set forest for tracking connected elements
+struct DisjointSet {
+    parents: Vec<usize>,
+}
+
+impl DisjointSet {
+    /// Create new disjoint set with the given number of single-element sets
+    fn new(size: usize) -> DisjointSet {
+        DisjointSet { parents: (0..size).collect() }
+    }
+
+    /// Returns the representative element of the set containing the given element
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parents[i] != i {
+            self.parents[i] = self.parents[self.parents[i]];
+            i = self.parents[i];
+        }
+        i
+    }
+
+    /// Merge the sets containing the two given elements
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        self.parents[a] = b;
+    }
+}
+
+
+/// Summary of the fragmentation of a disk
+#[derive(Debug, PartialEq)]
+struct FragReport {
+    /// Number of used blocks
+    used: usize,
+    /// Number of separate regions
+    regions: usize,
+    /// Number of blocks in the largest region
+    largest_region: usize,
+    /// Average number of blocks per region
+    mean_region_size: f64,
+    /// Number of regions consisting of a single block
+    isolated_blocks: usize,
+}
+
 
 /// A disk usage map tracking free and used blocks
 struct DiskUsage {
-    grid: [[bool; 128]; 128],
+    grid: Vec<u128>,
+}
+
+impl fmt::Display for DiskUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.render(0..self.height(), 0..self.width()))
+    }
 }
 
 impl DiskUsage {
     /// Create new disk usage state from given key using knot hashing
     fn new(key: &str) -> DiskUsage {
-        let mut grid = [[false; 128]; 128];
-        for y in 0..128 {
-            let mut hasher = day10::KnotHasher::new();
-            hasher.write(&format!("{}-{}", key, y));
-            let hash = hasher.finish();
-            for x in 0..128 {
-                grid[y][x] = hash[x / 8] & 0x80 >> (x % 8) > 0;
+        DiskUsage::with_hasher(key, 128, DiskUsage::knot_hash)
+    }
+
+    /// Create new disk usage state with the given number of rows from given key, using the given
+    /// function to hash the key of each row
+    fn with_hasher<F: Fn(&str) -> [u8; 16]>(key: &str, rows: usize, hasher: F) -> DiskUsage {
+        DiskUsage::from_hashes((0..rows).map(|y| hasher(&DiskUsage::row_key(key, y))))
+    }
+
+    /// Create new disk usage state from given key, hashing rows in parallel using the given
+    /// number of threads
+    fn new_parallel(key: &str, threads: usize) -> DiskUsage {
+        if threads <= 1 {
+            return DiskUsage::new(key);
+        }
+        let chunk_size = 128_usize.div_ceil(threads);
+        let workers: Vec<_> = (0..threads).map(|i| {
+            let key = key.to_string();
+            thread::spawn(move || {
+                (i * chunk_size .. cmp::min((i + 1) * chunk_size, 128))
+                    .map(|y| DiskUsage::knot_hash(&DiskUsage::row_key(&key, y)))
+                    .collect::<Vec<_>>()
+            })
+        }).collect();
+        DiskUsage::from_hashes(workers.into_iter().flat_map(|worker| worker.join().unwrap()))
+    }
+
+    /// Key of the given row for the given key
+    fn row_key(key: &str, y: usize) -> String {
+        format!("{}-{}", key, y)
+    }
+
+    /// Knot hash of the given row key
+    fn knot_hash(row_key: &str) -> [u8; 16] {
+        let mut hasher = day10::KnotHasher::new();
+        hasher.write(row_key);
+        hasher.finish()
+    }
+
+    /// Create new disk usage state from row hashes in row order
+    fn from_hashes<I: Iterator<Item=[u8; 16]>>(hashes: I) -> DiskUsage {
+        DiskUsage { grid: hashes.map(u128::from_be_bytes).collect() }
+    }
+
+    /// Bit mask of the given block within its row
+    fn mask(x: usize) -> u128 {
+        1 << (127 - x)
+    }
+
+    /// Returns true if the block at the given position is used
+    fn get(&self, y: usize, x: usize) -> bool {
+        self.grid[y] & DiskUsage::mask(x) != 0
+    }
+
+    /// Mark the block at the given position as used or free
+    fn set(&mut self, y: usize, x: usize, used: bool) {
+        if used {
+            self.grid[y] |= DiskUsage::mask(x);
+        } else {
+            self.grid[y] &= !DiskUsage::mask(x);
+        }
+    }
+
+    /// Number of blocks per row
+    fn width(&self) -> usize {
+        128
+    }
+
+    /// Number of rows
+    fn height(&self) -> usize {
+        self.grid.len()
+    }
+
+    /// Render the given part of the grid, using `#` for used and `.` for free blocks
+    fn render(&self, rows: Range<usize>, cols: Range<usize>) -> String {
+        let mut s = String::new();
+        for y in rows {
+            s.extend(cols.clone().map(|x| if self.get(y, x) { '#' } else { '.' }));
+            s.push('\n');
+        }
+        s
+    }
+
+    /// Write the grid as a plain PBM (P1) bitmap with used blocks in black
+    #[allow(dead_code)]
+    fn write_pbm<W: Write>(&self, mut w: W) -> io::Result<()> {
+        try!(writeln!(w, "P1"));
+        try!(writeln!(w, "{} {}", self.width(), self.height()));
+        for y in 0..self.height() {
+            // Plain PBM lines should not be longer than 70 characters
+            for x0 in (0..self.width()).step_by(64) {
+                let line: String = (x0..cmp::min(x0 + 64, self.width()))
+                    .map(|x| if self.get(y, x) { '1' } else { '0' })
+                    .collect();
+                try!(writeln!(w, "{}", line));
             }
         }
-        DiskUsage { grid: grid }
+        Ok(())
     }
 
     /// Returns the number of used blocks
     fn used(&self) -> usize {
-        self.grid.iter().map(|row| row.iter().filter(|b| **b).count()).sum()
+        self.grid.iter().map(|row| row.count_ones() as usize).sum()
+    }
+
+    /// Iterator over the positions of used blocks as (row, col) in row-major order
+    fn used_blocks(&self) -> impl Iterator<Item=(usize, usize)> + '_ {
+        (0..self.height()).flat_map(move |y| self.used_in_row(y).map(move |x| (y, x)))
+    }
+
+    /// Iterator over the columns of used blocks in the given row
+    fn used_in_row(&self, y: usize) -> impl Iterator<Item=usize> + '_ {
+        (0..self.width()).filter(move |&x| self.get(y, x))
     }
 
     /// Returns the number of separate regions
     fn regions(mut self) -> usize {
         let mut count = 0;
-        for y in 0..128 {
-            for x in 0..128 {
-                if self.grid[y][x] {
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                if self.get(y, x) {
                     self.clear_region(y, x);
                     count += 1;
                 }
@@ -41,14 +195,93 @@ impl DiskUsage {
         count
     }
 
+    /// Returns the number of separate regions using a disjoint set instead of flood filling
+    #[allow(dead_code)]
+    fn regions_uf(&self) -> usize {
+        let width = self.width();
+        let mut set = self.connected_blocks();
+        self.used_blocks().filter(|&(y, x)| set.find(y * width + x) == y * width + x).count()
+    }
+
+    /// Disjoint set of all blocks (indexed row-major) with used blocks of a region connected
+    fn connected_blocks(&self) -> DisjointSet {
+        let width = self.width();
+        let mut set = DisjointSet::new(self.height() * width);
+        for (y, x) in self.used_blocks() {
+            if x > 0 && self.get(y, x-1) { set.union(y * width + x, y * width + x-1); }
+            if y > 0 && self.get(y-1, x) { set.union(y * width + x, (y-1) * width + x); }
+        }
+        set
+    }
+
+    /// Region label of every block (indexed row-major), None for free blocks. Regions are
+    /// labeled in order of their first block in row-major order
+    fn region_labels(&self) -> Vec<Option<u32>> {
+        let width = self.width();
+        let mut set = self.connected_blocks();
+        let mut labels = vec![None; self.height() * width];
+        let mut count = 0;
+        for (y, x) in self.used_blocks() {
+            let root = set.find(y * width + x);
+            if labels[root].is_none() {
+                labels[root] = Some(count);
+                count += 1;
+            }
+            labels[y * width + x] = labels[root];
+        }
+        labels
+    }
+
+    /// Analyze how fragmented the used blocks are
+    #[allow(dead_code)]
+    fn fragmentation_report(&self) -> FragReport {
+        let mut sizes = Vec::new();
+        for label in self.region_labels().into_iter().flatten() {
+            let label = label as usize;
+            if label >= sizes.len() {
+                sizes.resize(label + 1, 0);
+            }
+            sizes[label] += 1;
+        }
+        let used = self.used();
+        FragReport {
+            used: used,
+            regions: sizes.len(),
+            largest_region: sizes.iter().cloned().max().unwrap_or(0),
+            mean_region_size: if sizes.is_empty() { 0.0 } else { used as f64 / sizes.len() as f64 },
+            isolated_blocks: sizes.iter().filter(|&&size| size == 1).count(),
+        }
+    }
+
+    /// Returns sorted pairs of distinct region labels whose blocks touch diagonally only
+    #[allow(dead_code)]
+    fn region_adjacency(&self) -> Vec<(u32, u32)> {
+        let width = self.width();
+        let labels = self.region_labels();
+        let mut pairs = Vec::new();
+        for (y, x) in self.used_blocks().filter(|&(y, _)| y > 0) {
+            let label = labels[y * width + x].unwrap();
+            let above_left = if x > 0 { labels[(y-1) * width + x-1] } else { None };
+            let above_right = if x < width - 1 { labels[(y-1) * width + x+1] } else { None };
+            for other in above_left.into_iter().chain(above_right) {
+                if other != label {
+                    pairs.push((cmp::min(label, other), cmp::max(label, other)));
+                }
+            }
+        }
+        pairs.sort();
+        pairs.dedup();
+        pairs
+    }
+
     /// Clear all blocks of a region starting at the given block position
     fn clear_region(&mut self, y: usize, x: usize) {
-        if self.grid[y][x] {
-            self.grid[y][x] = false;
+        if self.get(y, x) {
+            self.set(y, x, false);
             if x >   0 { self.clear_region(y, x-1); }
-            if x < 127 { self.clear_region(y, x+1); }
+            if x < self.width() - 1 { self.clear_region(y, x+1); }
             if y >   0 { self.clear_region(y-1, x); }
-            if y < 127 { self.clear_region(y+1, x); }
+            if y < self.height() - 1 { self.clear_region(y+1, x); }
         }
     }
 }
@@ -56,7 +289,7 @@ impl DiskUsage {
 
 fn main() {
     const INPUT: &str = "hfdlxzhv";
-    let disk = DiskUsage::new(INPUT);
+    let disk = DiskUsage::new_parallel(INPUT, 4);
     println!("Used squares: {}", disk.used());
     println!("Known regions: {}", disk.regions());
 }
@@ -64,19 +297,115 @@ fn main() {
 
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "nightly")]
+    extern crate test;
+
+    use std::cell::RefCell;
     use super::*;
 
+    /// First 8 blocks of the given row
+    fn row_start(disk: &DiskUsage, y: usize) -> Vec<bool> {
+        (0..8).map(|x| disk.get(y, x)).collect()
+    }
+
     #[test]
     fn creating() {
         let disk = DiskUsage::new("flqrgnkx");
-        assert_eq!(disk.grid[0][0..8], [ true,  true, false,  true, false,  true, false, false]);
-        assert_eq!(disk.grid[1][0..8], [false,  true, false,  true, false,  true, false,  true]);
-        assert_eq!(disk.grid[2][0..8], [false, false, false, false,  true, false,  true, false]);
-        assert_eq!(disk.grid[3][0..8], [ true, false,  true, false,  true,  true, false,  true]);
-        assert_eq!(disk.grid[4][0..8], [false,  true,  true, false,  true, false, false, false]);
-        assert_eq!(disk.grid[5][0..8], [ true,  true, false, false,  true, false, false,  true]);
-        assert_eq!(disk.grid[6][0..8], [false,  true, false, false, false,  true, false, false]);
-        assert_eq!(disk.grid[7][0..8], [ true,  true, false,  true, false,  true,  true, false]);
+        assert_eq!(row_start(&disk, 0), [ true,  true, false,  true, false,  true, false, false]);
+        assert_eq!(row_start(&disk, 1), [false,  true, false,  true, false,  true, false,  true]);
+        assert_eq!(row_start(&disk, 2), [false, false, false, false,  true, false,  true, false]);
+        assert_eq!(row_start(&disk, 3), [ true, false,  true, false,  true,  true, false,  true]);
+        assert_eq!(row_start(&disk, 4), [false,  true,  true, false,  true, false, false, false]);
+        assert_eq!(row_start(&disk, 5), [ true,  true, false, false,  true, false, false,  true]);
+        assert_eq!(row_start(&disk, 6), [false,  true, false, false, false,  true, false, false]);
+        assert_eq!(row_start(&disk, 7), [ true,  true, false,  true, false,  true,  true, false]);
+    }
+
+    #[test]
+    fn rendering() {
+        let disk = DiskUsage::new("flqrgnkx");
+        assert_eq!(disk.render(0..8, 0..8), "##.#.#..\n.#.#.#.#\n....#.#.\n#.#.##.#\n.##.#...\n##..#..#\n.#...#..\n##.#.##.\n");
+        assert_eq!(disk.to_string().lines().count(), 128);
+        assert!(disk.to_string().lines().all(|line| line.len() == 128));
+    }
+
+    #[test]
+    fn writing_pbm() {
+        let mut disk = DiskUsage { grid: vec![0; 2] };
+        disk.set(0, 0, true);
+        disk.set(1, 127, true);
+        let mut pbm = Vec::new();
+        disk.write_pbm(&mut pbm).unwrap();
+        let zeros = "0".repeat(63);
+        assert_eq!(String::from_utf8(pbm).unwrap(), format!("P1\n128 2\n1{0}\n0{0}\n0{0}\n{0}1\n", zeros));
+    }
+
+    #[test]
+    fn iterating_used_blocks() {
+        let disk = DiskUsage::new("flqrgnkx");
+        assert_eq!(disk.used_blocks().take(4).collect::<Vec<_>>(), [(0, 0), (0, 1), (0, 3), (0, 5)]);
+        assert_eq!(disk.used_in_row(1).take(4).collect::<Vec<_>>(), [1, 3, 5, 7]);
+        assert_eq!(disk.used_blocks().count(), disk.used());
+        assert!(disk.used_blocks().all(|(y, x)| disk.get(y, x)));
+    }
+
+    #[test]
+    fn custom_hasher() {
+        let disk = DiskUsage::with_hasher("flqrgnkx", 5, |_| [0xff; 16]);
+        assert_eq!(disk.used(), 5 * 128);
+        assert_eq!(disk.regions(), 1);
+        let disk = DiskUsage::with_hasher("flqrgnkx", 128, DiskUsage::knot_hash);
+        assert_eq!(disk.used(), 8108);
+        assert_eq!(disk.regions(), 1242);
+        let keys = RefCell::new(Vec::new());
+        DiskUsage::with_hasher("flqrgnkx", 3, |key| { keys.borrow_mut().push(key.to_string()); [0; 16] });
+        assert_eq!(*keys.borrow(), ["flqrgnkx-0", "flqrgnkx-1", "flqrgnkx-2"]);
+    }
+
+    /// Create disk usage from a literal grid using `#` for used and `.` for free blocks
+    fn literal(rows: &[&str]) -> DiskUsage {
+        let mut disk = DiskUsage { grid: vec![0; rows.len()] };
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                disk.set(y, x, c == '#');
+            }
+        }
+        disk
+    }
+
+    #[test]
+    fn union_find_regions() {
+        let disk = DiskUsage::new("flqrgnkx");
+        assert_eq!(disk.regions_uf(), 1242);
+        let checkerboard = literal(&["#.#.#.", ".#.#.#", "#.#.#.", ".#.#.#"]);
+        assert_eq!(checkerboard.regions_uf(), 12);
+        assert_eq!(checkerboard.regions(), 12);
+        let spiral = literal(&["#######", "......#", "#####.#", "#...#.#", "#.###.#", "#.....#", "#######"]);
+        assert_eq!(spiral.regions_uf(), 1);
+        assert_eq!(spiral.regions(), 1);
+        let broken_spiral = literal(&["#######", "......#", "####..#", "#...#.#", "#.###.#", "#.....#", "#######"]);
+        assert_eq!(broken_spiral.regions_uf(), 2);
+        assert_eq!(broken_spiral.regions(), 2);
+        let u_shape = literal(&["#..#", "#..#", "####"]);
+        assert_eq!(u_shape.regions_uf(), 1);
+        assert_eq!(u_shape.regions(), 1);
+    }
+
+    #[test]
+    fn fragmentation() {
+        let disk = DiskUsage::new("flqrgnkx");
+        let report = disk.fragmentation_report();
+        assert_eq!(report.used, 8108);
+        assert_eq!(report.regions, 1242);
+        assert_eq!(report.largest_region, 244);
+        assert_eq!(report.mean_region_size, 8108.0 / 1242.0);
+        assert_eq!(report.isolated_blocks, 588);
+        assert_eq!(disk.region_adjacency().len(), 1612);
+        let disk = literal(&["##..", "..#.", "...#", "...#"]);
+        assert_eq!(disk.fragmentation_report(), FragReport { used: 5, regions: 3, largest_region: 2, mean_region_size: 5.0 / 3.0, isolated_blocks: 1 });
+        assert_eq!(disk.region_adjacency(), [(0, 1), (1, 2)]);
+        let disk = literal(&["##..", "..#.", "..##", "#..."]);
+        assert_eq!(disk.region_adjacency(), [(0, 1)]);
     }
 
     #[test]
@@ -90,4 +419,99 @@ mod tests {
         let disk = DiskUsage::new("flqrgnkx");
         assert_eq!(disk.regions(), 1242);
     }
+
+    #[test]
+    fn parallel() {
+        let disk = DiskUsage::new("flqrgnkx");
+        for threads in 1..10 {
+            assert!(DiskUsage::new_parallel("flqrgnkx", threads).grid[..] == disk.grid[..]);
+        }
+    }
+
+    /// Straightforward bool grid used as a reference for the packed representation
+    struct Reference {
+        grid: Vec<[bool; 128]>,
+    }
+
+    impl Reference {
+        fn new(hashes: &[[u8; 16]]) -> Reference {
+            Reference { grid: hashes.iter().map(|hash| {
+                let mut row = [false; 128];
+                for x in 0..128 {
+                    row[x] = hash[x / 8] & 0x80 >> (x % 8) > 0;
+                }
+                row
+            }).collect() }
+        }
+
+        fn used(&self) -> usize {
+            self.grid.iter().map(|row| row.iter().filter(|b| **b).count()).sum()
+        }
+
+        fn regions(mut self) -> usize {
+            let mut count = 0;
+            for y in 0..self.grid.len() {
+                for x in 0..128 {
+                    if self.grid[y][x] {
+                        let mut stack = vec![(y, x)];
+                        while let Some((y, x)) = stack.pop() {
+                            if y < self.grid.len() && x < 128 && self.grid[y][x] {
+                                self.grid[y][x] = false;
+                                stack.extend(&[(y, x.wrapping_sub(1)), (y, x + 1), (y.wrapping_sub(1), x), (y + 1, x)]);
+                            }
+                        }
+                        count += 1;
+                    }
+                }
+            }
+            count
+        }
+    }
+
+    #[test]
+    fn packed_equivalence() {
+        // Simple xorshift generator to get reproducible pseudo-random grids
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for round in 0..20 {
+            let hashes: Vec<[u8; 16]> = (0..16).map(|_| {
+                let mut hash = [0; 16];
+                for b in hash.iter_mut() {
+                    // Vary the density of used blocks between rounds
+                    *b = (0..round % 3 + 1).fold(0xff, |b, _| b & random() as u8);
+                }
+                hash
+            }).collect();
+            let reference = Reference::new(&hashes);
+            let disk = DiskUsage::from_hashes(hashes.iter().cloned());
+            for y in 0..16 {
+                for x in 0..128 {
+                    assert_eq!(disk.get(y, x), reference.grid[y][x]);
+                }
+            }
+            assert_eq!(disk.used(), reference.used());
+            assert_eq!(disk.regions(), reference.regions());
+        }
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_new(b: &mut test::Bencher) {
+        b.iter(|| {
+            DiskUsage::new("hfdlxzhv")
+        })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_new_parallel(b: &mut test::Bencher) {
+        b.iter(|| {
+            DiskUsage::new_parallel("hfdlxzhv", 4)
+        })
+    }
 }
@@ -1,3 +1,66 @@
+#![cfg_attr(feature = "nightly", feature(test))]
+
+use std::{cmp, iter, thread};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+
+/// Factor of generator A
+const FACTOR_A: u32 = 16807;
+/// Factor of generator B
+const FACTOR_B: u32 = 48271;
+/// Modulus of generated values
+const MODULUS: u64 = 2147483647;
+
+
+/// Error parsing the starting values of the generators
+#[derive(Debug, PartialEq)]
+enum ParseError {
+    /// Line of the named generator is missing
+    Missing(char),
+    /// Line of the named generator is malformed
+    Malformed(char),
+}
+
+
+/// Starting values of generators A and B
+#[derive(Debug, PartialEq)]
+struct GeneratorPair {
+    a: u32,
+    b: u32,
+}
+
+impl FromStr for GeneratorPair {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines().filter(|line| !line.trim().is_empty());
+        let a = try!(GeneratorPair::parse_line(lines.next(), 'a'));
+        let b = try!(GeneratorPair::parse_line(lines.next(), 'b'));
+        Ok(GeneratorPair { a: a, b: b })
+    }
+}
+
+impl GeneratorPair {
+    /// Parse the starting value of the named generator from a line like
+    /// "Generator A starts with 634"
+    fn parse_line(line: Option<&str>, name: char) -> Result<u32, ParseError> {
+        let line = try!(line.ok_or(ParseError::Missing(name.to_ascii_uppercase()))).to_lowercase();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["generator", n, "starts", "with", value] if n == name.to_string() =>
+                value.parse().map_err(|_| ParseError::Malformed(name.to_ascii_uppercase())),
+            _ => Err(ParseError::Malformed(name.to_ascii_uppercase())),
+        }
+    }
+
+    /// Create generators A and B with the starting values
+    fn generators(&self) -> (Generator, Generator) {
+        (Generator::new(FACTOR_A, self.a), Generator::new(FACTOR_B, self.b))
+    }
+}
+
+
 /// Number generator
 #[derive(Debug)]
 struct Generator {
@@ -9,7 +72,14 @@ impl Iterator for Generator {
     type Item = u32;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.value = ((self.value as u64 * self.factor as u64) % 2147483647) as u32;
+        // The modulus is the Mersenne prime 2^31-1, so the remainder can be calculated by
+        // folding the upper bits onto the lower bits instead of dividing
+        let product = self.value as u64 * self.factor as u64;
+        let mut value = (product & MODULUS) + (product >> 31);
+        if value >= MODULUS {
+            value -= MODULUS;
+        }
+        self.value = value as u32;
         Some(self.value)
     }
 }
@@ -19,6 +89,83 @@ impl Generator {
     fn new(factor: u32, value: u32) -> Generator {
         Generator { factor: factor, value: value }
     }
+
+    /// Create number generator with the given factor, resuming from the given state
+    #[allow(dead_code)]
+    fn from_state(factor: u32, state: u32) -> Generator {
+        Generator::new(factor, state)
+    }
+
+    /// Current state of the generator, i.e. the last generated value
+    #[allow(dead_code)]
+    fn state(&self) -> u32 {
+        self.value
+    }
+
+    /// Fill the given buffer with the next generated values
+    fn fill(&mut self, buf: &mut [u32]) {
+        for v in buf.iter_mut() {
+            *v = self.next().unwrap();
+        }
+    }
+
+    /// Advance the generator by n steps without generating intermediate values, leaving it in
+    /// the same state as n calls to `next` would. Since `Iterator::skip` shadows this method for
+    /// method call syntax, call it as `Generator::skip`
+    fn skip(&mut self, n: u64) {
+        self.value = self.peek_after(n);
+    }
+
+    /// Returns the nth (zero-based) upcoming output without advancing the generator, i.e. the
+    /// value the (n+1)th call to `next` would return
+    #[allow(dead_code)]
+    fn peek_nth(&self, n: u64) -> u32 {
+        self.peek_after(n + 1)
+    }
+
+    /// Returns the value after n steps
+    fn peek_after(&self, n: u64) -> u32 {
+        ((self.value as u64 * mod_pow(self.factor as u64, n)) % MODULUS) as u32
+    }
+
+    /// Turn into a picky generator that only outputs multiples of the given number. Panics
+    /// if the given multiple is zero
+    fn picky(self, multiple: u32) -> PickyGenerator {
+        assert!(multiple != 0, "multiple of picky generator must not be zero");
+        PickyGenerator { generator: self, multiple: multiple }
+    }
+}
+
+
+/// Number generator that only outputs multiples of a given number
+#[derive(Debug)]
+struct PickyGenerator {
+    generator: Generator,
+    multiple: u32,
+}
+
+impl Iterator for PickyGenerator {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let multiple = self.multiple;
+        self.generator.find(|v| v % multiple == 0)
+    }
+}
+
+
+/// Calculate base^exp modulo the generator modulus
+fn mod_pow(mut base: u64, mut exp: u64) -> u64 {
+    let mut result = 1;
+    base %= MODULUS;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = result * base % MODULUS;
+        }
+        base = base * base % MODULUS;
+        exp >>= 1;
+    }
+    result
 }
 
 
@@ -28,29 +175,295 @@ fn compare_generators<I, J>(a: &mut I, b: &mut J, n: u32) -> usize
     where I: Iterator<Item=u32>,
           J: Iterator<Item=u32>,
 {
-    (0..n).map(|_|
-        (a.next().unwrap(), b.next().unwrap())
-    ).filter(|&(a, b)|
-        a & 0xffff == b & 0xffff
-    ).count()
+    Judge::new(a, b).count_matches(n as usize)
+}
+
+/// Compare next n outputs of the given two generators like `compare_generators`, but
+/// generate values in blocks
+fn compare_generators_batched(a: &mut Generator, b: &mut Generator, n: u32) -> usize {
+    const BATCH_SIZE: u32 = 4096;
+    let mut buf_a = [0; BATCH_SIZE as usize];
+    let mut buf_b = [0; BATCH_SIZE as usize];
+    let mut count = 0;
+    let mut remaining = n;
+    while remaining > 0 {
+        let len = cmp::min(remaining, BATCH_SIZE) as usize;
+        a.fill(&mut buf_a[..len]);
+        b.fill(&mut buf_b[..len]);
+        count += buf_a[..len].iter().zip(&buf_b[..len]).filter(|&(a, b)| a & 0xffff == b & 0xffff).count();
+        remaining -= len as u32;
+    }
+    count
+}
+
+/// Compare the first n outputs of generators A and B with the given starting values using
+/// the given number of threads. Each thread skips ahead to its own block of outputs
+fn compare_generators_parallel(a_seed: u32, b_seed: u32, n: u32, threads: usize) -> usize {
+    let mut generator_a = Generator::new(FACTOR_A, a_seed);
+    let mut generator_b = Generator::new(FACTOR_B, b_seed);
+    if threads <= 1 {
+        return compare_generators_batched(&mut generator_a, &mut generator_b, n);
+    }
+    let chunk_size = (n as usize).div_ceil(threads);
+    let workers: Vec<_> = (0..threads).map(|i| {
+        let start = cmp::min(i * chunk_size, n as usize);
+        let end = cmp::min(start + chunk_size, n as usize);
+        thread::spawn(move || {
+            let mut generator_a = Generator::new(FACTOR_A, a_seed);
+            let mut generator_b = Generator::new(FACTOR_B, b_seed);
+            Generator::skip(&mut generator_a, start as u64);
+            Generator::skip(&mut generator_b, start as u64);
+            compare_generators_batched(&mut generator_a, &mut generator_b, (end - start) as u32)
+        })
+    }).collect();
+    workers.into_iter().map(|worker| worker.join().unwrap()).sum()
+}
+
+
+/// Bit mask of the given number (1 to 32) of least significant bits
+fn mask(bits: u32) -> u32 {
+    assert!((1..=32).contains(&bits), "number of compared bits must be between 1 and 32");
+    !0u32 >> (32 - bits)
+}
+
+
+/// Judge comparing the outputs of two generators
+#[derive(Debug)]
+struct Judge<A, B> {
+    a: A,
+    b: B,
+    /// Number of pairs compared so far
+    compared: usize,
+    /// Number of matching pairs so far
+    matched: usize,
+    /// Bit mask of compared bits
+    mask: u32,
+}
+
+impl Judge<Generator, Generator> {
+    /// Create new judge for generators A and B with the given starting values
+    #[allow(dead_code)]
+    fn from_seeds(a_seed: u32, b_seed: u32) -> Judge<Generator, Generator> {
+        Judge::new(Generator::new(FACTOR_A, a_seed), Generator::new(FACTOR_B, b_seed))
+    }
+
+    /// Capture the current state of the judge and its generators
+    #[allow(dead_code)]
+    fn snapshot(&self) -> JudgeSnapshot {
+        JudgeSnapshot {
+            a: self.a.state(),
+            b: self.b.state(),
+            compared: self.compared as u64,
+            matched: self.matched as u64,
+            mask: self.mask,
+        }
+    }
+
+    /// Create judge for generators A and B resuming from the given snapshot
+    #[allow(dead_code)]
+    fn restore(snapshot: &JudgeSnapshot) -> Judge<Generator, Generator> {
+        Judge {
+            a: Generator::from_state(FACTOR_A, snapshot.a),
+            b: Generator::from_state(FACTOR_B, snapshot.b),
+            compared: snapshot.compared as usize,
+            matched: snapshot.matched as usize,
+            mask: snapshot.mask,
+        }
+    }
+}
+
+
+/// Saved state of a judge comparing generators A and B
+#[derive(Debug, PartialEq)]
+struct JudgeSnapshot {
+    a: u32,
+    b: u32,
+    compared: u64,
+    matched: u64,
+    mask: u32,
+}
+
+impl JudgeSnapshot {
+    /// Encode the snapshot as bytes (little-endian)
+    #[allow(dead_code)]
+    fn to_bytes(&self) -> [u8; 28] {
+        let mut bytes = [0; 28];
+        for i in 0..4 {
+            bytes[i] = (self.a >> (i * 8)) as u8;
+            bytes[4 + i] = (self.b >> (i * 8)) as u8;
+            bytes[24 + i] = (self.mask >> (i * 8)) as u8;
+        }
+        for i in 0..8 {
+            bytes[8 + i] = (self.compared >> (i * 8)) as u8;
+            bytes[16 + i] = (self.matched >> (i * 8)) as u8;
+        }
+        bytes
+    }
+
+    /// Decode a snapshot from bytes (little-endian)
+    #[allow(dead_code)]
+    fn from_bytes(bytes: &[u8; 28]) -> JudgeSnapshot {
+        let read = |offset: usize, len: usize| {
+            bytes[offset..offset + len].iter().rev().fold(0u64, |value, &b| value << 8 | b as u64)
+        };
+        JudgeSnapshot {
+            a: read(0, 4) as u32,
+            b: read(4, 4) as u32,
+            compared: read(8, 8),
+            matched: read(16, 8),
+            mask: read(24, 4) as u32,
+        }
+    }
+}
+
+impl<A: Iterator<Item=u32>, B: Iterator<Item=u32>> Judge<A, B> {
+    /// Create new judge for the given generators
+    fn new(a: A, b: B) -> Judge<A, B> {
+        Judge { a: a, b: b, compared: 0, matched: 0, mask: 0xffff }
+    }
+
+    /// Compare the given number of least significant bits (1 to 32) instead of 16 bits
+    #[allow(dead_code)]
+    fn with_mask_bits(mut self, bits: u32) -> Judge<A, B> {
+        self.mask = mask(bits);
+        self
+    }
+
+    /// Compare the next pair of outputs. Returns the pair and true if the compared bits are
+    /// matching
+    fn judge_next(&mut self) -> ((u32, u32), bool) {
+        let (a, b) = (self.a.next().unwrap(), self.b.next().unwrap());
+        self.compared += 1;
+        if a & self.mask == b & self.mask {
+            self.matched += 1;
+            ((a, b), true)
+        } else {
+            ((a, b), false)
+        }
+    }
+
+    /// Compare the next pair of outputs. Returns true if the compared bits are matching
+    fn compare_next(&mut self) -> bool {
+        self.judge_next().1
+    }
+
+    /// Iterator over the compared pairs of outputs
+    #[allow(dead_code)]
+    fn pairs(&mut self) -> Pairs<'_, A, B> {
+        Pairs { judge: self }
+    }
+
+    /// Compare the next n pairs of outputs. Returns the number of matching pairs
+    fn count_matches(&mut self, n: usize) -> usize {
+        (0..n).filter(|_| self.compare_next()).count()
+    }
+
+    /// Iterator over the zero-based indices of matching pairs
+    #[allow(dead_code)]
+    fn matches(&mut self) -> impl Iterator<Item=usize> + '_ {
+        iter::repeat(()).filter_map(move |_| {
+            let index = self.compared;
+            if self.compare_next() { Some(index) } else { None }
+        })
+    }
+
+    /// Compare the next n pairs of outputs using each of the given numbers of compared bits.
+    /// Returns the number of matching pairs for each number of bits
+    #[allow(dead_code)]
+    fn match_counts_for_masks(&mut self, n: usize, bits_range: RangeInclusive<u32>) -> Vec<(u32, usize)> {
+        let masks: Vec<u32> = bits_range.clone().map(mask).collect();
+        let mut counts = vec![0; masks.len()];
+        for _ in 0..n {
+            let (a, b) = (self.a.next().unwrap(), self.b.next().unwrap());
+            self.compared += 1;
+            if a & self.mask == b & self.mask {
+                self.matched += 1;
+            }
+            for (mask, count) in masks.iter().zip(counts.iter_mut()) {
+                if a & mask == b & mask {
+                    *count += 1;
+                }
+            }
+        }
+        bits_range.zip(counts).collect()
+    }
+}
+
+
+/// Iterator over the pairs of outputs compared by a judge
+struct Pairs<'a, A: 'a, B: 'a> {
+    judge: &'a mut Judge<A, B>,
+}
+
+impl<'a, A: Iterator<Item=u32>, B: Iterator<Item=u32>> Iterator for Pairs<'a, A, B> {
+    type Item = (u32, u32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.judge.judge_next().0)
+    }
+}
+
+impl<'a, A: Iterator<Item=u32>, B: Iterator<Item=u32>> Pairs<'a, A, B> {
+    /// Only yield matching pairs and stop after n of them
+    #[allow(dead_code)]
+    fn take_matches(self, n: usize) -> TakeMatches<'a, A, B> {
+        TakeMatches { judge: self.judge, remaining: n }
+    }
+}
+
+
+/// Iterator over the first n matching pairs of outputs compared by a judge
+struct TakeMatches<'a, A: 'a, B: 'a> {
+    judge: &'a mut Judge<A, B>,
+    remaining: usize,
+}
+
+impl<'a, A: Iterator<Item=u32>, B: Iterator<Item=u32>> Iterator for TakeMatches<'a, A, B> {
+    type Item = (u32, u32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        loop {
+            if let (pair, true) = self.judge.judge_next() {
+                self.remaining -= 1;
+                return Some(pair);
+            }
+        }
+    }
 }
 
 
 fn main() {
-    const INPUT: (u32, u32) = (634, 301);
-    let mut generator_a = Generator::new(16807, INPUT.0);
-    let mut generator_b = Generator::new(48271, INPUT.1);
-    println!("Final count after 40 million pairs: {}", compare_generators(&mut generator_a, &mut generator_b, 40_000_000));
-    let mut generator_a = Generator::new(16807, INPUT.0).filter(|v| v % 4 == 0);
-    let mut generator_b = Generator::new(48271, INPUT.1).filter(|v| v % 8 == 0);
+    let seeds: GeneratorPair = include_str!("day15.txt").parse().unwrap();
+    println!("Final count after 40 million pairs: {}", compare_generators_parallel(seeds.a, seeds.b, 40_000_000, 4));
+    let (generator_a, generator_b) = seeds.generators();
+    let (mut generator_a, mut generator_b) = (generator_a.picky(4), generator_b.picky(8));
     println!("Final count after 5 million pairs: {}", compare_generators(&mut generator_a, &mut generator_b, 5_000_000));
 }
 
 
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "nightly")]
+    extern crate test;
+
     use super::*;
 
+    #[test]
+    fn parsing() {
+        assert_eq!(GeneratorPair::from_str("Generator A starts with 65\nGenerator B starts with 8921\n"), Ok(GeneratorPair { a: 65, b: 8921 }));
+        assert_eq!(GeneratorPair::from_str("  generator a  STARTS with 65 \n\n GENERATOR B starts   with 8921"), Ok(GeneratorPair { a: 65, b: 8921 }));
+        assert_eq!(GeneratorPair::from_str("Generator A starts with 65\n"), Err(ParseError::Missing('B')));
+        assert_eq!(GeneratorPair::from_str(""), Err(ParseError::Missing('A')));
+        assert_eq!(GeneratorPair::from_str("Generator B starts with 65\nGenerator B starts with 8921"), Err(ParseError::Malformed('A')));
+        assert_eq!(GeneratorPair::from_str("Generator A starts with 65\nGenerator B starts with x"), Err(ParseError::Malformed('B')));
+        let (mut generator_a, mut generator_b) = GeneratorPair { a: 65, b: 8921 }.generators();
+        assert_eq!(generator_a.next(), Some(1092455));
+        assert_eq!(generator_b.next(), Some(430625591));
+    }
+
     #[test]
     fn samples1a() {
         let mut generator_a = Generator::new(16807, 65);
@@ -67,6 +480,71 @@ mod tests {
         assert_eq!(generator_b.next(), Some(285222916));
     }
 
+    /// Next value of a generator using plain modulo division
+    fn reference_next(value: u32, factor: u32) -> u32 {
+        ((value as u64 * factor as u64) % 2147483647) as u32
+    }
+
+    #[test]
+    fn fast_modulo() {
+        for &seed in &[1, 65, 8921, 634, 301, 2147483646, 1_234_567_890] {
+            for &factor in &[16807, 48271] {
+                let mut generator = Generator::new(factor, seed);
+                let mut value = seed;
+                for _ in 0..500_000 {
+                    value = reference_next(value, factor);
+                    assert_eq!(generator.next(), Some(value));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn skipping() {
+        let mut generator = Generator::new(16807, 65);
+        Generator::skip(&mut generator, 0);
+        assert_eq!(generator.peek_nth(0), 1092455);
+        assert_eq!(generator.peek_nth(4), 1352636452);
+        Generator::skip(&mut generator, 3);
+        assert_eq!(generator.next(), Some(1744312007));
+        let mut generator = Generator::new(16807, 65);
+        Generator::skip(&mut generator, 5);
+        assert_eq!(generator.next(), Some(498961622));
+        // Simple xorshift generator to get reproducible pseudo-random seeds
+        let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+        for &factor in &[16807, 48271] {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let seed = (state % (MODULUS - 1)) as u32 + 1;
+            let mut stepping = Generator::new(factor, seed);
+            for offset in 0..5000 {
+                let mut skipping = Generator::new(factor, seed);
+                assert_eq!(skipping.peek_nth(offset), stepping.peek_after(1));
+                Generator::skip(&mut skipping, offset);
+                assert_eq!(skipping.value, stepping.value);
+                stepping.next();
+            }
+        }
+    }
+
+    #[test]
+    fn resuming() {
+        let mut judge = Judge::from_seeds(65, 8921);
+        judge.count_matches(2_000_000);
+        let snapshot = judge.snapshot();
+        assert_eq!(snapshot.compared, 2_000_000);
+        let bytes = snapshot.to_bytes();
+        assert_eq!(JudgeSnapshot::from_bytes(&bytes), snapshot);
+        let mut judge = Judge::restore(&JudgeSnapshot::from_bytes(&bytes));
+        judge.count_matches(38_000_000);
+        assert_eq!((judge.compared, judge.matched), (40_000_000, 588));
+        let snapshot = JudgeSnapshot { a: 0x04030201, b: 0x08070605, compared: 0x100f0e0d0c0b0a09, matched: 0x1817161514131211, mask: 0x1c1b1a19 };
+        let bytes = snapshot.to_bytes();
+        assert!(bytes.iter().enumerate().all(|(i, &b)| b as usize == i + 1));
+        assert_eq!(JudgeSnapshot::from_bytes(&bytes), snapshot);
+    }
+
     #[test]
     fn samples1b() {
         let mut generator_a = Generator::new(16807, 65);
@@ -74,10 +552,85 @@ mod tests {
         assert_eq!(compare_generators(&mut generator_a, &mut generator_b, 40_000_000), 588);
     }
 
+    #[test]
+    fn batched() {
+        let mut generator = Generator::new(16807, 65);
+        let mut buf = [0; 5];
+        generator.fill(&mut buf);
+        assert_eq!(buf, [1092455, 1181022009, 245556042, 1744312007, 1352636452]);
+        for &n in &[0, 1, 5, 4095, 4096, 4097, 100_000] {
+            let expected = compare_generators(&mut Generator::new(16807, 65), &mut Generator::new(48271, 8921), n);
+            assert_eq!(compare_generators_batched(&mut Generator::new(16807, 65), &mut Generator::new(48271, 8921), n), expected);
+        }
+    }
+
+    #[test]
+    fn parallel() {
+        for &n in &[0, 1, 5, 12_345, 100_000] {
+            let expected = compare_generators(&mut Generator::new(16807, 65), &mut Generator::new(48271, 8921), n);
+            for threads in 1..6 {
+                assert_eq!(compare_generators_parallel(65, 8921, n, threads), expected);
+            }
+        }
+        assert_eq!(compare_generators_parallel(65, 8921, 40_000_000, 4), 588);
+    }
+
+    #[test]
+    fn judging() {
+        let mut judge = Judge::from_seeds(65, 8921);
+        assert_eq!(judge.matches().next(), Some(2));
+        assert_eq!(judge.count_matches(40_000_000 - 3), 588 - 1);
+        let mut judge = Judge::from_seeds(65, 8921);
+        assert_eq!(judge.count_matches(40_000_000), 588);
+        assert_eq!((judge.compared, judge.matched), (40_000_000, 588));
+        let generator_a = Generator::new(16807, 65).picky(4);
+        let generator_b = Generator::new(48271, 8921).picky(8);
+        let mut judge = Judge::new(generator_a, generator_b);
+        assert_eq!(judge.matches().next(), Some(1055));
+    }
+
+    #[test]
+    fn iterating_pairs() {
+        let mut judge = Judge::from_seeds(65, 8921);
+        assert_eq!(judge.pairs().take(2).collect::<Vec<_>>(), [(1092455, 430625591), (1181022009, 1233683848)]);
+        assert_eq!(judge.pairs().take_matches(1).collect::<Vec<_>>(), [(245556042, 1431495498)]);
+        assert_eq!((judge.compared, judge.matched), (3, 1));
+        let mut judge = Judge::new(Generator::new(16807, 65).picky(4), Generator::new(48271, 8921).picky(8));
+        assert_eq!(judge.pairs().take_matches(1).collect::<Vec<_>>(), [(1023762912, 896885216)]);
+        assert_eq!(judge.compared, 1056);
+        let mut judge = Judge::from_seeds(65, 8921).with_mask_bits(1);
+        assert_eq!(judge.pairs().take_matches(3).count(), 3);
+        assert_eq!(judge.compared, 4);
+    }
+
+    #[test]
+    fn mask_bits() {
+        assert_eq!(mask(1), 0x1);
+        assert_eq!(mask(16), 0xffff);
+        assert_eq!(mask(32), 0xffffffff);
+        assert_eq!(Judge::from_seeds(65, 8921).with_mask_bits(32).count_matches(5), 0);
+        assert_eq!(Judge::from_seeds(65, 8921).with_mask_bits(16).count_matches(5), 1);
+        assert_eq!(Judge::from_seeds(65, 8921).with_mask_bits(1).count_matches(5), 4);
+        let counts = Judge::from_seeds(65, 8921).match_counts_for_masks(5, 1..=32);
+        assert_eq!(counts.len(), 32);
+        assert_eq!(counts[0], (1, 4));
+        assert_eq!(counts[15], (16, 1));
+        assert_eq!(counts[31], (32, 0));
+        for &(bits, count) in &Judge::from_seeds(65, 8921).match_counts_for_masks(10_000, 1..=32) {
+            assert_eq!(Judge::from_seeds(65, 8921).with_mask_bits(bits).count_matches(10_000), count);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn invalid_mask_bits() {
+        Judge::from_seeds(65, 8921).with_mask_bits(33);
+    }
+
     #[test]
     fn samples2a() {
-        let mut generator_a = Generator::new(16807, 65).filter(|v| v % 4 == 0);
-        let mut generator_b = Generator::new(48271, 8921).filter(|v| v % 8 == 0);
+        let mut generator_a = Generator::new(16807, 65).picky(4);
+        let mut generator_b = Generator::new(48271, 8921).picky(8);
         assert_eq!(generator_a.next(), Some(1352636452));
         assert_eq!(generator_b.next(), Some(1233683848));
         assert_eq!(generator_a.next(), Some(1992081072));
@@ -90,10 +643,51 @@ mod tests {
         assert_eq!(generator_b.next(), Some(412269392));
     }
 
+    #[test]
+    #[should_panic]
+    fn picky_zero() {
+        Generator::new(16807, 65).picky(0);
+    }
+
     #[test]
     fn samples2b() {
-        let mut generator_a = Generator::new(16807, 65).filter(|v| v % 4 == 0);
-        let mut generator_b = Generator::new(48271, 8921).filter(|v| v % 8 == 0);
+        let mut generator_a = Generator::new(16807, 65).picky(4);
+        let mut generator_b = Generator::new(48271, 8921).picky(8);
         assert_eq!(compare_generators(&mut generator_a, &mut generator_b, 5_000_000), 309);
     }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_generator(b: &mut test::Bencher) {
+        b.iter(|| {
+            Generator::new(16807, 634).take(1_000_000).fold(0, |x, v| x ^ v)
+        })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_reference_generator(b: &mut test::Bencher) {
+        b.iter(|| {
+            (0..1_000_000).fold((0, 634), |(x, v), _| {
+                let v = reference_next(v, 16807);
+                (x ^ v, v)
+            }).0
+        })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_compare_generators(b: &mut test::Bencher) {
+        b.iter(|| {
+            compare_generators(&mut Generator::new(16807, 634), &mut Generator::new(48271, 301), 1_000_000)
+        })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_compare_generators_parallel(b: &mut test::Bencher) {
+        b.iter(|| {
+            compare_generators_parallel(634, 301, 1_000_000, 4)
+        })
+    }
 }
@@ -3,15 +3,53 @@
 #[macro_use]
 extern crate nom;
 
+use std::fmt;
 use std::str::FromStr;
-use nom::{digit, anychar};
+use nom::{digit, alphanumeric};
 
 
-#[derive(Debug, PartialEq)]
+/// Names of a group of dancers
+#[derive(Debug, Clone, PartialEq)]
+struct Alphabet {
+    names: Vec<String>,
+}
+
+impl Alphabet {
+    /// Default names for the given number of dancers. Up to 26 dancers are named `a` to `z`,
+    /// up to 62 dancers are additionally named `A` to `Z` and `0` to `9`. Larger groups are
+    /// named `p00`, `p01` and so on
+    fn new(group_size: usize) -> Alphabet {
+        let chars = (b'a'..b'z' + 1).chain(b'A'..b'Z' + 1).chain(b'0'..b'9' + 1);
+        if group_size <= chars.clone().count() {
+            Alphabet { names: chars.take(group_size).map(|c| (c as char).to_string()).collect() }
+        } else {
+            let width = (group_size - 1).to_string().len();
+            Alphabet { names: (0..group_size).map(|i| format!("p{:01$}", i, width)).collect() }
+        }
+    }
+
+    /// Number of dancers
+    fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Index of the dancer with the given name
+    fn index(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+
+    /// Render the order of the given dancers by concatenating their names
+    fn render(&self, dancers: &[usize]) -> String {
+        dancers.iter().map(|&d| &self.names[d][..]).collect()
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
 enum Move {
     Spin(usize),
     Exchange(usize, usize),
-    Partner(char, char),
+    Partner(String, String),
 }
 
 impl FromStr for Move {
@@ -19,81 +57,385 @@ impl FromStr for Move {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         named!(number<&str, usize>, map_res!(digit, str::parse));
-        complete!(s, alt!(
+        complete!(s, terminated!(alt!(
             do_parse!(
                 tag!("s") >> size: number >> (Move::Spin(size))
             ) | do_parse!(
                 tag!("x") >> pos1: number >> tag!("/") >> pos2: number >> (Move::Exchange(pos1, pos2))
             ) | do_parse!(
-                tag!("p") >> name1: anychar >> tag!("/") >> name2: anychar >> (Move::Partner(name1, name2))
+                tag!("p") >> name1: alphanumeric >> tag!("/") >> name2: alphanumeric >>
+                (Move::Partner(name1.to_string(), name2.to_string()))
             )
-        )).to_result()
+        ), eof!())).to_result()
+    }
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Move::Spin(a) => write!(f, "s{}", a),
+            Move::Exchange(a, b) => write!(f, "x{}/{}", a, b),
+            Move::Partner(ref a, ref b) => write!(f, "p{}/{}", a, b),
+        }
     }
 }
 
 impl Move {
-    /// Applies the move to the given group of dancers
-    fn apply(&self, dancers: &mut [char]) {
-        let len = dancers.len();
+    /// Applies the move to the given group of dancers (indices into the given alphabet)
+    fn apply(&self, dancers: &mut [usize], alphabet: &Alphabet) {
+        if let Err(e) = self.try_apply(dancers, alphabet) {
+            panic!("Invalid move {}: {:?}", self, e);
+        }
+    }
+
+    /// Applies the move to the given group of dancers (indices into the given alphabet). Fails
+    /// without changing the dancers if the move isn't possible for the group
+    fn try_apply(&self, dancers: &mut [usize], alphabet: &Alphabet) -> Result<(), MoveError> {
+        try!(self.check(alphabet));
         match *self {
-            Move::Spin(a) => {
-                for (i, &d) in dancers[len-a..].to_owned().iter().chain(dancers[..len-a].to_owned().iter()).enumerate() {
-                    dancers[i] = d;
-                }
-            },
-            Move::Exchange(a, b) => {
-                dancers.swap(a, b);
-            },
-            Move::Partner(a, b) => {
-                match (dancers.iter().position(|&d| d==a), dancers.iter().position(|&d| d==b)) {
+            Move::Partner(ref a, ref b) => {
+                let position = |name: &str| alphabet.index(name).and_then(|n| dancers.iter().position(|&d| d == n));
+                match (position(a), position(b)) {
                     (Some(a), Some(b)) => dancers.swap(a, b),
-                    _ => panic!("Unknown dancer to partner with"),
+                    (None, _) => return Err(MoveError::UnknownPartner(a.clone())),
+                    (_, None) => return Err(MoveError::UnknownPartner(b.clone())),
                 }
             },
+            _ => self.apply_to_positions(dancers),
+        }
+        Ok(())
+    }
+
+    /// Check that the move is possible for dancers named by the given alphabet
+    fn check(&self, alphabet: &Alphabet) -> Result<(), MoveError> {
+        match *self {
+            Move::Spin(a) if a > alphabet.len() => Err(MoveError::SpinTooLarge(a)),
+            Move::Exchange(a, _) if a >= alphabet.len() => Err(MoveError::PositionOutOfRange(a)),
+            Move::Exchange(_, b) if b >= alphabet.len() => Err(MoveError::PositionOutOfRange(b)),
+            Move::Partner(ref a, _) if alphabet.index(a).is_none() => Err(MoveError::UnknownPartner(a.clone())),
+            Move::Partner(_, ref b) if alphabet.index(b).is_none() => Err(MoveError::UnknownPartner(b.clone())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies a Spin or Exchange move to the given positions. Partner moves don't depend on
+    /// positions and are ignored
+    fn apply_to_positions<T>(&self, positions: &mut [T]) {
+        match *self {
+            Move::Spin(a) => positions.rotate_right(a),
+            Move::Exchange(a, b) => positions.swap(a, b),
+            Move::Partner(..) => (),
         }
     }
 }
 
 
+/// Error in a single move
+#[derive(Debug, PartialEq)]
+enum MoveError {
+    /// Position of an Exchange move is out of range of the group
+    PositionOutOfRange(usize),
+    /// Size of a Spin move is larger than the group
+    SpinTooLarge(usize),
+    /// Partner move names a dancer that isn't part of the group
+    UnknownPartner(String),
+}
+
+
+/// Error in a dance
+#[derive(Debug, PartialEq)]
+enum DanceError {
+    /// Initial order contains the given dancer more than once
+    DuplicateDancer(String),
+    /// Move with the given zero-based index is not possible
+    InvalidMove(usize, MoveError),
+}
+
+
+/// Error parsing a dance, referring to the zero-based index and text of the failing move
+#[derive(Debug, PartialEq)]
+struct ParseError {
+    index: usize,
+    token: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "move {}: could not parse '{}'", self.index, self.token)
+    }
+}
+
+
 #[derive(Debug, PartialEq)]
 struct Dance {
     moves: Vec<Move>,
 }
 
 impl FromStr for Dance {
-    type Err = nom::ErrorKind;
+    type Err = ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Dance { moves: try!(s.split(',').map(str::parse).collect()) })
+        let tokens: Vec<&str> = s.split(',').map(str::trim).collect();
+        // Ignore a trailing comma or whitespace after the last move
+        let len = if tokens.last() == Some(&"") { tokens.len() - 1 } else { tokens.len() };
+        let moves = try!(tokens[..len].iter().enumerate().map(|(i, token)|
+            token.parse().map_err(|_| ParseError { index: i, token: token.to_string() })
+        ).collect());
+        Ok(Dance { moves: moves })
+    }
+}
+
+impl fmt::Display for Dance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, moove) in self.moves.iter().enumerate() {
+            if i > 0 {
+                try!(write!(f, ","));
+            }
+            try!(write!(f, "{}", moove));
+        }
+        Ok(())
     }
 }
 
 impl Dance {
-    /// Perform the dance
-    fn perform(&self, group_size: usize, mut iterations: usize) -> String {
-        let mut dancers: Vec<char> = (0..group_size).map(|i| ('a' as usize + i) as u8 as char).collect();
-        let initial_dancers = dancers.clone();
-        let mut i = 0;
-        while i < iterations {
-            for moove in &self.moves {
-                moove.apply(&mut dancers);
+    /// Create a dance from the given moves
+    #[allow(dead_code)]
+    fn new(moves: Vec<Move>) -> Dance {
+        Dance { moves: moves }
+    }
+
+    /// Append the given move to the dance
+    #[allow(dead_code)]
+    fn push(&mut self, moove: Move) {
+        self.moves.push(moove);
+    }
+
+    /// Check that all moves are possible for the given number of dancers
+    #[allow(dead_code)]
+    fn validate(&self, group_size: usize) -> Result<(), DanceError> {
+        self.validate_with(&Alphabet::new(group_size))
+    }
+
+    /// Check that all moves are possible for dancers named by the given alphabet
+    fn validate_with(&self, alphabet: &Alphabet) -> Result<(), DanceError> {
+        for (i, moove) in self.moves.iter().enumerate() {
+            try!(moove.check(alphabet).map_err(|e| DanceError::InvalidMove(i, e)));
+        }
+        Ok(())
+    }
+
+    /// Perform the dance starting with the given order of dancers, each named by a single
+    /// character
+    #[allow(dead_code)]
+    fn perform_from(&self, initial: &str, iterations: usize) -> Result<String, DanceError> {
+        let mut names: Vec<String> = Vec::new();
+        for ch in initial.chars() {
+            let name = ch.to_string();
+            if names.contains(&name) {
+                return Err(DanceError::DuplicateDancer(name));
             }
-            i += 1;
-            // Check if dancers moved back to their initial order and
-            // take a shortcut by skipping the repeating sequences
-            if dancers == initial_dancers {
-                iterations = i + iterations % i;
+            names.push(name);
+        }
+        self.perform_with(&Alphabet { names: names }, iterations)
+    }
+
+    /// Perform the dance with the given number of dancers named by the default alphabet
+    fn perform(&self, group_size: usize, iterations: usize) -> Result<String, DanceError> {
+        self.perform_with(&Alphabet::new(group_size), iterations)
+    }
+
+    /// Perform the dance with dancers named by the given alphabet. Only the initial and the
+    /// current order are kept in memory, but up to twice the number of performances needed
+    /// to get back to the initial order are done.
+    fn perform_with(&self, alphabet: &Alphabet, iterations: usize) -> Result<String, DanceError> {
+        // Whether a move is possible only depends on the group, not on the order of dancers,
+        // so checking all moves once upfront guarantees that performing them can't fail
+        try!(self.validate_with(alphabet));
+        let mut states = self.states_with(alphabet.clone());
+        let initial = states.current().to_string();
+        for i in 1..iterations + 1 {
+            let order = states.next().unwrap();
+            // Every performance rearranges the dancers the same way, so once they're back in
+            // the initial order, the orders repeat and only the remainder needs to be done
+            if i == iterations || order == initial {
+                return Ok(match iterations % i {
+                    0 => order,
+                    remaining => states.nth(remaining - 1).unwrap(),
+                });
             }
         }
-        dancers.iter().collect()
+        Ok(initial)
+    }
+
+    /// Iterator over the index of each move, the move and the order of the given number of
+    /// dancers after applying it, for a single performance. Panics if a move isn't possible
+    #[allow(dead_code)]
+    fn steps<'a>(&'a self, group_size: usize) -> impl Iterator<Item=(usize, &'a Move, String)> + 'a {
+        let alphabet = Alphabet::new(group_size);
+        let mut dancers: Vec<usize> = (0..group_size).collect();
+        self.moves.iter().enumerate().map(move |(i, moove)| {
+            moove.apply(&mut dancers, &alphabet);
+            (i, moove, alphabet.render(&dancers))
+        })
+    }
+
+    /// Iterator over the orders of the given number of dancers after each performance
+    #[allow(dead_code)]
+    fn states(&self, group_size: usize) -> States<'_> {
+        self.states_with(Alphabet::new(group_size))
+    }
+
+    /// Iterator over the orders of dancers named by the given alphabet after each performance
+    fn states_with(&self, alphabet: Alphabet) -> States<'_> {
+        let dancers: Vec<usize> = (0..alphabet.len()).collect();
+        let current = alphabet.render(&dancers);
+        States { dance: self, alphabet: alphabet, dancers: dancers, current: current }
+    }
+
+    /// Optimize the dance for the given number of dancers by replacing every run of Spin and
+    /// Exchange moves with the minimal number of Exchange moves having the same effect, if that
+    /// needs less moves. Partner moves are kept as they are. Panics if a move isn't possible
+    /// for the group
+    #[allow(dead_code)]
+    fn optimize(&self, group_size: usize) -> Dance {
+        let alphabet = Alphabet::new(group_size);
+        let mut moves = Vec::new();
+        let mut run = Vec::new();
+        for moove in &self.moves {
+            assert!(moove.check(&alphabet).is_ok(), "Invalid move {}", moove);
+            if let Move::Partner(..) = *moove {
+                Dance::push_run(&mut moves, &run, group_size);
+                run.clear();
+                moves.push(moove.clone());
+            } else {
+                run.push(moove);
+            }
+        }
+        Dance::push_run(&mut moves, &run, group_size);
+        Dance { moves: moves }
+    }
+
+    /// Append the given run of Spin and Exchange moves, or Exchange moves with the same effect
+    /// if they're less. Needs at most one Exchange less than the number of dancers
+    fn push_run(moves: &mut Vec<Move>, run: &[&Move], group_size: usize) {
+        let mut positions: Vec<usize> = (0..group_size).collect();
+        for moove in run {
+            moove.apply_to_positions(&mut positions);
+        }
+        let mut exchanges = Vec::new();
+        let mut current: Vec<usize> = (0..group_size).collect();
+        // Current position of every dancer
+        let mut index = current.clone();
+        for (i, &p) in positions.iter().enumerate() {
+            if current[i] != p {
+                let j = index[p];
+                index[current[i]] = j;
+                index[p] = i;
+                current.swap(i, j);
+                exchanges.push(Move::Exchange(i, j));
+            }
+        }
+        if exchanges.len() < run.len() {
+            moves.extend(exchanges);
+        } else {
+            moves.extend(run.iter().map(|&m| m.clone()));
+        }
+    }
+
+    /// Compile the dance for the given number of dancers into permutations of positions and
+    /// names. Spin and Exchange moves only depend on positions while Partner moves only depend
+    /// on names, so both can be tracked independently
+    fn compile(&self, group_size: usize) -> Result<CompiledDance, DanceError> {
+        let alphabet = Alphabet::new(group_size);
+        try!(self.validate_with(&alphabet));
+        let mut positions: Vec<usize> = (0..group_size).collect();
+        // Original name of the dancer currently carrying each name
+        let mut owners: Vec<usize> = (0..group_size).collect();
+        for moove in &self.moves {
+            match *moove {
+                Move::Partner(ref a, ref b) => owners.swap(alphabet.index(a).unwrap(), alphabet.index(b).unwrap()),
+                _ => moove.apply_to_positions(&mut positions),
+            }
+        }
+        let mut names = vec![0; group_size];
+        for (name, &owner) in owners.iter().enumerate() {
+            names[owner] = name;
+        }
+        Ok(CompiledDance { alphabet: alphabet, positions: positions, names: names })
+    }
+}
+
+
+/// Iterator over the orders of dancers after each performance of a dance
+struct States<'a> {
+    dance: &'a Dance,
+    alphabet: Alphabet,
+    dancers: Vec<usize>,
+    current: String,
+}
+
+impl<'a> Iterator for States<'a> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for moove in &self.dance.moves {
+            moove.apply(&mut self.dancers, &self.alphabet);
+        }
+        self.current = self.alphabet.render(&self.dancers);
+        Some(self.current.clone())
+    }
+}
+
+impl<'a> States<'a> {
+    /// Current order of dancers
+    fn current(&self) -> &str {
+        &self.current
     }
 }
 
 
+/// A dance compiled into permutations of positions and names
+#[derive(Debug, PartialEq)]
+struct CompiledDance {
+    /// Names of the dancers
+    alphabet: Alphabet,
+    /// Original position of the dancer at each position after one dance
+    positions: Vec<usize>,
+    /// Name that each original name turns into after one dance
+    names: Vec<usize>,
+}
+
+impl CompiledDance {
+    /// Perform the dance the given number of times
+    fn apply(&self, iterations: usize) -> String {
+        let positions = power(&self.positions, iterations);
+        let names = power(&self.names, iterations);
+        let dancers: Vec<usize> = positions.iter().map(|&p| names[p]).collect();
+        self.alphabet.render(&dancers)
+    }
+}
+
+
+/// Raise the given permutation to the nth power by repeated squaring
+fn power(permutation: &[usize], mut n: usize) -> Vec<usize> {
+    let compose = |p: &[usize], q: &[usize]| -> Vec<usize> { q.iter().map(|&i| p[i]).collect() };
+    let mut result: Vec<usize> = (0..permutation.len()).collect();
+    let mut base = permutation.to_vec();
+    while n > 0 {
+        if n & 1 == 1 {
+            result = compose(&result, &base);
+        }
+        base = compose(&base, &base);
+        n >>= 1;
+    }
+    result
+}
+
+
 fn main() {
     let dance: Dance = include_str!("day16.txt").parse().unwrap();
-    println!("Order of programs after 1 dance: {}", dance.perform(16, 1));
-    println!("Order of programs after 1,000,000,000 dances: {}", dance.perform(16, 1_000_000_000));
+    println!("Order of programs after 1 dance: {}", dance.perform(16, 1).unwrap());
+    println!("Order of programs after 1,000,000,000 dances: {}", dance.compile(16).unwrap().apply(1_000_000_000));
 }
 
 
@@ -108,15 +450,210 @@ mod tests {
     fn parsing() {
         assert_eq!(Move::from_str("s1"), Ok(Move::Spin(1)));
         assert_eq!(Move::from_str("x3/4"), Ok(Move::Exchange(3, 4)));
-        assert_eq!(Move::from_str("pe/b"), Ok(Move::Partner('e', 'b')));
-        assert_eq!(Dance::from_str("s1,x3/4,pe/b"), Ok(Dance { moves: vec![Move::Spin(1), Move::Exchange(3, 4), Move::Partner('e', 'b')] }));
+        assert!(Move::from_str("x3/4a").is_err());
+        assert_eq!(Move::from_str("pe/b"), Ok(Move::Partner("e".to_string(), "b".to_string())));
+        assert_eq!(Dance::from_str("s1,x3/4,pe/b"), Ok(Dance { moves: vec![Move::Spin(1), Move::Exchange(3, 4), Move::Partner("e".to_string(), "b".to_string())] }));
+    }
+
+    #[test]
+    fn parsing_whitespace() {
+        assert_eq!(Dance::from_str("s1, x3/4,\npe/b\n"), Dance::from_str("s1,x3/4,pe/b"));
+        assert_eq!(Dance::from_str("s1,x3/4,pe/b,"), Dance::from_str("s1,x3/4,pe/b"));
+        let error = Dance::from_str("s1,x16/3a,pe/b").unwrap_err();
+        assert_eq!(error, ParseError { index: 1, token: "x16/3a".to_string() });
+        assert_eq!(error.to_string(), "move 1: could not parse 'x16/3a'");
+        assert_eq!(Dance::from_str("s1,,pe/b").unwrap_err(), ParseError { index: 1, token: "".to_string() });
+    }
+
+    #[test]
+    fn displaying() {
+        assert_eq!(Dance::from_str("s1,x3/4,pe/b").unwrap().to_string(), "s1,x3/4,pe/b");
+        assert_eq!(Dance::from_str(" s1, x3/4,\npe/b\n").unwrap().to_string(), "s1,x3/4,pe/b");
+        assert_eq!(Dance::new(vec![]).to_string(), "");
+        let mut dance = Dance::new(vec![Move::Spin(3)]);
+        dance.push(Move::Exchange(10, 2));
+        dance.push(Move::Partner("p03".to_string(), "p12".to_string()));
+        assert_eq!(dance.to_string(), "s3,x10/2,pp03/p12");
+        assert_eq!(Dance::from_str(&dance.to_string()), Ok(dance));
+    }
+
+    #[test]
+    fn validating() {
+        let dance = Dance::from_str("s1,x3/4,pe/b").unwrap();
+        assert_eq!(dance.validate(5), Ok(()));
+        assert_eq!(dance.validate(4), Err(DanceError::InvalidMove(1, MoveError::PositionOutOfRange(4))));
+        assert_eq!(Dance::from_str("s6").unwrap().validate(5), Err(DanceError::InvalidMove(0, MoveError::SpinTooLarge(6))));
+        assert_eq!(Dance::from_str("s1,pa/q").unwrap().validate(5), Err(DanceError::InvalidMove(1, MoveError::UnknownPartner("q".to_string()))));
     }
 
     #[test]
     fn samples() {
         let dance = Dance::from_str("s1,x3/4,pe/b").unwrap();
-        assert_eq!(dance.perform(5, 1), "baedc");
-        assert_eq!(dance.perform(5, 2), "ceadb");
+        assert_eq!(dance.perform(5, 1).unwrap(), "baedc");
+        assert_eq!(dance.perform(5, 2).unwrap(), "ceadb");
+    }
+
+    #[test]
+    fn large_groups() {
+        assert_eq!(Alphabet::new(16).render(&(0..16).collect::<Vec<_>>()), "abcdefghijklmnop");
+        assert_eq!(Alphabet::new(62).names[61], "9");
+        assert_eq!(Alphabet::new(63).names[..2], ["p00", "p01"]);
+        assert_eq!(Alphabet::new(1000).names[999], "p999");
+        let dance = Dance::from_str("s1,x0/39,x1/30").unwrap();
+        assert_eq!(dance.perform(40, 1).unwrap(), "MDbcdefghijklmnopqrstuvwxyzABCaEFGHIJKLN");
+        assert_eq!(dance.compile(40).unwrap().apply(1), "MDbcdefghijklmnopqrstuvwxyzABCaEFGHIJKLN");
+        assert_eq!(Move::from_str("pp03/p12"), Ok(Move::Partner("p03".to_string(), "p12".to_string())));
+        let dance = Dance::from_str("pp03/p12,s1").unwrap();
+        let order = dance.perform(100, 1).unwrap();
+        assert_eq!(&order[..15], "p99p00p01p02p12");
+        assert_eq!(&order[13 * 3..14 * 3], "p03");
+    }
+
+    #[test]
+    fn iterating_states() {
+        let dance = Dance::from_str("s1,x3/4,pe/b").unwrap();
+        let mut states = dance.states(5);
+        assert_eq!(states.current(), "abcde");
+        assert_eq!(states.next(), Some("baedc".to_string()));
+        assert_eq!(states.current(), "baedc");
+        assert_eq!(states.next(), Some("ceadb".to_string()));
+        assert_eq!(dance.states(5).take_while(|order| order != "abcde").count(), 3);
+        assert_eq!(dance.states(5).nth(999), Some(dance.perform(5, 1000).unwrap()));
+    }
+
+    #[test]
+    fn optimizing() {
+        let dance = Dance::from_str("s1,x3/4,pe/b").unwrap();
+        assert_eq!(dance.optimize(5), dance);
+        assert_eq!(Dance::from_str("x0/1,x1/2,x2/3,x3/4,s1,pe/b").unwrap().optimize(5).to_string(), "pe/b");
+        assert_eq!(Dance::from_str("s1,s1,s1,x1/3,pa/b").unwrap().optimize(5).to_string(), "x0/2,x1/2,x2/4,pa/b");
+        assert_eq!(Dance::from_str("s2,s3,x0/1,x1/0").unwrap().optimize(5).to_string(), "");
+        // Simple xorshift generator to get reproducible pseudo-random dances
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut random = move |n: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % n as u64) as usize
+        };
+        for _ in 0..50 {
+            let group_size = 1 + random(20);
+            let alphabet = Alphabet::new(group_size);
+            let moves = (0..random(100)).map(|_| match random(3) {
+                0 => Move::Spin(random(group_size + 1)),
+                1 => Move::Exchange(random(group_size), random(group_size)),
+                _ => Move::Partner(alphabet.names[random(group_size)].clone(), alphabet.names[random(group_size)].clone()),
+            }).collect();
+            let dance = Dance::new(moves);
+            let optimized = dance.optimize(group_size);
+            assert!(optimized.moves.len() <= dance.moves.len());
+            for &n in &[0, 1, 2, 3, 10, 1_000_000_000] {
+                assert_eq!(optimized.perform(group_size, n), dance.perform(group_size, n));
+            }
+        }
+    }
+
+    #[test]
+    fn stepping() {
+        let dance = Dance::from_str("s1,x3/4,pe/b").unwrap();
+        let steps: Vec<_> = dance.steps(5).collect();
+        assert_eq!(steps, [
+            (0, &dance.moves[0], "eabcd".to_string()),
+            (1, &dance.moves[1], "eabdc".to_string()),
+            (2, &dance.moves[2], "baedc".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn performing_from() {
+        let dance = Dance::from_str("s1,x3/4,pe/b").unwrap();
+        assert_eq!(dance.perform_from("abcde", 2), Ok("ceadb".to_string()));
+        assert_eq!(dance.perform_from("baedc", 1), Ok("ceadb".to_string()));
+        assert_eq!(dance.perform_from("baedc", 0), Ok("baedc".to_string()));
+        assert_eq!(dance.perform_from("baedc", 1_000_000_000), dance.perform(5, 1_000_000_001));
+        assert_eq!(dance.perform_from("abcda", 1), Err(DanceError::DuplicateDancer("a".to_string())));
+        assert_eq!(dance.perform_from("abcdz", 1), Err(DanceError::InvalidMove(2, MoveError::UnknownPartner("e".to_string()))));
+        assert_eq!(dance.perform_from("abcd", 1), Err(DanceError::InvalidMove(1, MoveError::PositionOutOfRange(4))));
+    }
+
+    #[test]
+    fn failing_moves() {
+        let alphabet = Alphabet::new(5);
+        let mut dancers: Vec<usize> = (0..5).collect();
+        assert_eq!(Move::from_str("x9/1").unwrap().try_apply(&mut dancers, &alphabet), Err(MoveError::PositionOutOfRange(9)));
+        assert_eq!(Move::from_str("s6").unwrap().try_apply(&mut dancers, &alphabet), Err(MoveError::SpinTooLarge(6)));
+        assert_eq!(Move::from_str("pz/a").unwrap().try_apply(&mut dancers, &alphabet), Err(MoveError::UnknownPartner("z".to_string())));
+        assert_eq!(Move::from_str("s1").unwrap().try_apply(&mut dancers, &alphabet), Ok(()));
+        assert_eq!(alphabet.render(&dancers), "eabcd");
+        let dance = Dance::from_str("s1,x9/1").unwrap();
+        assert_eq!(dance.perform(5, 1), Err(DanceError::InvalidMove(1, MoveError::PositionOutOfRange(9))));
+        assert_eq!(dance.compile(5).unwrap_err(), DanceError::InvalidMove(1, MoveError::PositionOutOfRange(9)));
+        let dance = Dance::from_str("s1,pz/a").unwrap();
+        assert_eq!(dance.perform_from("abcde", 1), Err(DanceError::InvalidMove(1, MoveError::UnknownPartner("z".to_string()))));
+    }
+
+    #[test]
+    fn repeating() {
+        let brute_force = |dance: &Dance, group_size: usize, iterations: usize| -> String {
+            let alphabet = Alphabet::new(group_size);
+            let mut dancers: Vec<usize> = (0..group_size).collect();
+            for _ in 0..iterations {
+                for moove in &dance.moves {
+                    moove.apply(&mut dancers, &alphabet);
+                }
+            }
+            alphabet.render(&dancers)
+        };
+        for &(s, group_size) in &[("s1,pa/b", 3), ("s1,x3/4,pe/b", 5), ("x0/1,s2,pa/c,pb/d,x2/3", 5)] {
+            let dance = Dance::from_str(s).unwrap();
+            for n in 0..30 {
+                assert_eq!(dance.perform(group_size, n).unwrap(), brute_force(&dance, group_size, n));
+            }
+        }
+    }
+
+    #[test]
+    fn compiling() {
+        let dance = Dance::from_str("s1,x3/4,pe/b").unwrap();
+        let compiled = dance.compile(5).unwrap();
+        for n in 1..50 {
+            assert_eq!(compiled.apply(n), dance.perform(5, n).unwrap());
+        }
+        let dance: Dance = include_str!("day16.txt").parse().unwrap();
+        assert_eq!(dance.validate(16), Ok(()));
+        let compiled = dance.compile(16).unwrap();
+        for n in 1..50 {
+            assert_eq!(compiled.apply(n), dance.perform(16, n).unwrap());
+        }
+        assert_eq!(compiled.apply(1_000_000_000), dance.perform(16, 1_000_000_000).unwrap());
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_compiled_dance(b: &mut test::Bencher) {
+        let dance: Dance = include_str!("day16.txt").parse().unwrap();
+        b.iter(|| {
+            dance.compile(16).unwrap().apply(1_000_000_000)
+        })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_single_dance(b: &mut test::Bencher) {
+        let dance: Dance = include_str!("day16.txt").parse().unwrap();
+        b.iter(|| {
+            dance.perform(16, 1)
+        })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_single_optimized_dance(b: &mut test::Bencher) {
+        let dance: Dance = include_str!("day16.txt").parse().unwrap();
+        let dance = dance.optimize(16);
+        b.iter(|| {
+            dance.perform(16, 1)
+        })
     }
 
     #[cfg(feature = "nightly")]
@@ -124,7 +661,7 @@ mod tests {
     fn benchmark_simple_dance(b: &mut test::Bencher) {
         let dance: Dance = include_str!("day16.txt").parse().unwrap();
         b.iter(|| {
-            dance.perform(16, 1_000_000)
+            dance.perform(16, 1_000_000).unwrap()
         })
     }
 }
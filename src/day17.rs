@@ -1,53 +1,679 @@
-/// Create a spinlock's ring buffer and return the value to
-/// short-circuit (value after last inserted value)
-fn spinlock_short_circuit(stepsize: usize, iterations: usize) -> u32 {
-    let mut buffer: Vec<u32> = vec![0];
-    let mut position = 0;
-    for i in 1..iterations as u32 + 1 {
-        position = (position + stepsize) % buffer.len();
-        if position == buffer.len()-1 {
-            buffer.push(i);
-        } else {
-            buffer.insert(position+1, i);
+#![cfg_attr(feature = "nightly", feature(test))]
+
+use std::{env, fmt, fs};
+use std::str::FromStr;
+
+
+/// Error parsing the puzzle input
+#[derive(Debug, PartialEq)]
+struct ParseError(String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid step size '{}'", self.0)
+    }
+}
+
+
+/// Puzzle input (step size of the spinlock)
+#[derive(Debug, PartialEq)]
+struct Input {
+    stepsize: u64,
+}
+
+impl FromStr for Input {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        s.parse().map(|stepsize| Input { stepsize: stepsize }).map_err(|_| ParseError(s.to_string()))
+    }
+}
+
+
+/// Description of a value inserted into a spinlock's ring buffer
+#[derive(Debug, PartialEq)]
+struct Insertion {
+    /// Inserted value
+    value: u64,
+    /// Position the value was inserted at
+    position: u64,
+    /// Length of the buffer after inserting
+    buffer_len: u64,
+}
+
+
+/// Data structure to store a spinlock's ring buffer in
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(dead_code)]
+enum Backend {
+    /// Plain vector, O(n) per insertion
+    Vec,
+    /// Doubly linked list over indices, O(stepsize) per insertion plus
+    /// updating the position of every value after the inserted one
+    Linked,
+    /// No buffer at all, only tracks the value after zero, O(1) per insertion
+    Tracking,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Buffer {
+    /// Values in order of the ring buffer and the index of each value
+    Vec(Vec<u64>, Vec<usize>),
+    /// Value following each value, value preceding each value, the position
+    /// of each value and the value at the current position
+    Linked(Vec<u64>, Vec<u64>, Vec<u64>, u64),
+    /// Number of values in the ring buffer
+    Tracking(u64),
+}
+
+
+/// Iterator over the values of a spinlock's ring buffer, starting with zero
+#[derive(Debug)]
+struct Values<'a> {
+    buffer: &'a Buffer,
+    /// Number of values returned so far
+    index: usize,
+    /// Next value to return if using the linked list backend
+    value: u64,
+}
+
+impl<'a> Iterator for Values<'a> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let value = match *self.buffer {
+            Buffer::Vec(ref buffer, _) => match buffer.get(self.index) {
+                Some(&value) => value,
+                None => return None,
+            },
+            Buffer::Linked(ref next, _, _, _) => {
+                if self.index >= next.len() { return None; }
+                let value = self.value;
+                self.value = next[value as usize];
+                value
+            }
+            Buffer::Tracking(_) => return None,
+        };
+        self.index += 1;
+        Some(value)
+    }
+}
+
+
+/// A spinlock's ring buffer
+#[derive(Debug)]
+struct Spinlock {
+    stepsize: u64,
+    buffer: Buffer,
+    position: u64,
+    after_zero: u64,
+}
+
+impl Iterator for Spinlock {
+    type Item = Insertion;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let len = self.len();
+        // Stop before the position or the number of values would overflow
+        if self.position.checked_add(self.stepsize).is_none() || len == u64::MAX { return None; }
+        let value = len;
+        self.position = (self.position + self.stepsize) % len + 1;
+        match self.buffer {
+            Buffer::Vec(ref mut buffer, ref mut indices) => {
+                let position = self.position as usize;
+                buffer.insert(position, value);
+                indices.push(position);
+                for &v in &buffer[position + 1..] {
+                    indices[v as usize] += 1;
+                }
+            }
+            Buffer::Linked(ref mut next, ref mut prev, ref mut positions, ref mut current) => {
+                for _ in 0..self.stepsize % len {
+                    *current = next[*current as usize];
+                }
+                let following = next[*current as usize];
+                next.push(following);
+                prev.push(*current);
+                positions.push(self.position);
+                next[*current as usize] = value;
+                prev[following as usize] = value;
+                *current = value;
+                // Values after the inserted one (up to zero) move one position further
+                let mut v = following;
+                while v != 0 {
+                    positions[v as usize] += 1;
+                    v = next[v as usize];
+                }
+            }
+            Buffer::Tracking(ref mut len) => *len += 1,
         }
-        position += 1;
+        if self.position == 1 { self.after_zero = value; }
+        Some(Insertion { value: value, position: self.position, buffer_len: len + 1 })
+    }
+}
+
+impl Spinlock {
+    /// Create new spinlock with the given step size
+    fn new(stepsize: u64) -> Spinlock {
+        Spinlock::with_backend(stepsize, Backend::Vec)
+    }
+
+    /// Create new spinlock with the given step size that stores its
+    /// ring buffer using the given backend
+    fn with_backend(stepsize: u64, backend: Backend) -> Spinlock {
+        let buffer = match backend {
+            Backend::Vec => Buffer::Vec(vec![0], vec![0]),
+            Backend::Linked => Buffer::Linked(vec![0], vec![0], vec![0], 0),
+            Backend::Tracking => Buffer::Tracking(1),
+        };
+        Spinlock { stepsize: stepsize, buffer: buffer, position: 0, after_zero: 0 }
+    }
+
+    /// Save the current state of the spinlock. Includes the whole ring
+    /// buffer unless only tracking the value after zero.
+    #[allow(dead_code)]
+    fn snapshot(&self) -> SpinlockState {
+        SpinlockState {
+            stepsize: self.stepsize,
+            position: self.position,
+            insertions: self.len() - 1,
+            after_zero: self.after_zero,
+            buffer: self.buffer.clone(),
+        }
+    }
+
+    /// Create spinlock resuming from the given state
+    #[allow(dead_code)]
+    fn resume(state: SpinlockState) -> Spinlock {
+        Spinlock {
+            stepsize: state.stepsize,
+            buffer: state.buffer,
+            position: state.position,
+            after_zero: state.after_zero,
+        }
+    }
+
+    /// Number of values in the ring buffer
+    fn len(&self) -> u64 {
+        match self.buffer {
+            Buffer::Vec(ref buffer, _) => buffer.len() as u64,
+            Buffer::Linked(ref next, _, _, _) => next.len() as u64,
+            Buffer::Tracking(len) => len,
+        }
+    }
+
+    /// Value after zero, i.e. the second value
+    #[allow(dead_code)]
+    fn value_after_zero(&self) -> u64 {
+        self.after_zero
+    }
+
+    /// Value after the current position. Panics if only tracking the value
+    /// after zero.
+    fn value_after_current(&self) -> u64 {
+        match self.buffer {
+            Buffer::Vec(ref buffer, _) => buffer[(self.position as usize + 1) % buffer.len()],
+            Buffer::Linked(ref next, _, _, current) => next[current as usize],
+            Buffer::Tracking(_) => panic!("Spinlock only tracks the value after zero"),
+        }
+    }
+
+    /// Iterator over the current values of the ring buffer, starting with
+    /// zero. Panics if only tracking the value after zero.
+    #[allow(dead_code)]
+    fn buffer(&self) -> Values<'_> {
+        if let Buffer::Tracking(_) = self.buffer {
+            panic!("Spinlock only tracks the value after zero");
+        }
+        Values { buffer: &self.buffer, index: 0, value: 0 }
+    }
+
+    /// Values from the given number of elements before the current position
+    /// to the given number of elements after the current position. Panics if
+    /// only tracking the value after zero.
+    #[allow(dead_code)]
+    fn window(&self, radius: usize) -> Vec<u64> {
+        match self.buffer {
+            Buffer::Vec(ref buffer, _) => {
+                let start = self.position as usize + buffer.len() - radius % buffer.len();
+                (0..2 * radius + 1).map(|i| buffer[(start + i) % buffer.len()]).collect()
+            }
+            Buffer::Linked(ref next, ref prev, _, current) => {
+                let mut value = current;
+                for _ in 0..radius { value = prev[value as usize]; }
+                (0..2 * radius + 1).map(|_| {
+                    let v = value;
+                    value = next[v as usize];
+                    v
+                }).collect()
+            }
+            Buffer::Tracking(_) => panic!("Spinlock only tracks the value after zero"),
+        }
+    }
+
+    /// Position of the given value in the ring buffer. None if the value
+    /// wasn't inserted yet. Panics if only tracking the value after zero.
+    #[allow(dead_code)]
+    fn position_of(&self, value: u64) -> Option<u64> {
+        if value >= self.len() { return None; }
+        match self.buffer {
+            Buffer::Vec(_, ref indices) => Some(indices[value as usize] as u64),
+            Buffer::Linked(_, _, ref positions, _) => Some(positions[value as usize]),
+            Buffer::Tracking(_) => panic!("Spinlock only tracks the value after zero"),
+        }
+    }
+}
+
+
+/// Saved state of a spinlock
+#[derive(Debug, PartialEq)]
+struct SpinlockState {
+    stepsize: u64,
+    position: u64,
+    insertions: u64,
+    after_zero: u64,
+    buffer: Buffer,
+}
+
+impl SpinlockState {
+    /// Encode state to bytes (little-endian). Takes 33 bytes plus 8 bytes
+    /// per value in the ring buffer (if not only tracking the value after
+    /// zero), i.e. about 400 MB for a 50 million values ring buffer.
+    #[allow(dead_code)]
+    fn to_bytes(&self) -> Vec<u8> {
+        fn write(bytes: &mut Vec<u8>, value: u64) {
+            bytes.extend((0..8).map(|i| (value >> (i * 8)) as u8));
+        }
+        let mut bytes = Vec::new();
+        write(&mut bytes, self.stepsize);
+        write(&mut bytes, self.position);
+        write(&mut bytes, self.insertions);
+        write(&mut bytes, self.after_zero);
+        match self.buffer {
+            Buffer::Vec(ref buffer, _) => {
+                bytes.push(0);
+                for &value in buffer { write(&mut bytes, value); }
+            }
+            Buffer::Linked(ref next, _, _, current) => {
+                bytes.push(1);
+                write(&mut bytes, current);
+                for &value in next { write(&mut bytes, value); }
+            }
+            Buffer::Tracking(_) => bytes.push(2),
+        }
+        bytes
     }
-    buffer[(position + 1) % buffer.len()]
+
+    /// Decode state from bytes (little-endian). None if the bytes are
+    /// truncated or don't describe a valid state.
+    #[allow(dead_code)]
+    fn from_bytes(bytes: &[u8]) -> Option<SpinlockState> {
+        if bytes.len() < 33 { return None; }
+        let count = (bytes.len() - 33) / 8;
+        if 33 + count * 8 != bytes.len() { return None; }
+        let read = |b: &[u8]| b.iter().rev().fold(0u64, |value, &b| value << 8 | b as u64);
+        let read_values = |offset: usize| bytes[offset..].chunks(8).map(&read).collect::<Vec<u64>>();
+        let insertions = read(&bytes[16..24]);
+        if insertions == u64::MAX { return None; }
+        let len = insertions + 1;
+        let position = read(&bytes[8..16]);
+        if position >= len { return None; }
+        let count = count as u64;
+        let buffer = match bytes[32] {
+            0 if count == len => {
+                let buffer = read_values(33);
+                let mut indices = vec![usize::MAX; len as usize];
+                for (i, &value) in buffer.iter().enumerate() {
+                    if value >= len { return None; }
+                    indices[value as usize] = i;
+                }
+                if indices.contains(&usize::MAX) { return None; }
+                Buffer::Vec(buffer, indices)
+            }
+            1 if Some(count) == len.checked_add(1) => {
+                let next = read_values(41);
+                let current = read(&bytes[33..41]);
+                if next.iter().chain(Some(&current)).any(|&value| value >= len) { return None; }
+                let mut prev = vec![u64::MAX; len as usize];
+                for (value, &following) in next.iter().enumerate() {
+                    prev[following as usize] = value as u64;
+                }
+                if prev.contains(&u64::MAX) { return None; }
+                let mut positions = vec![u64::MAX; len as usize];
+                let mut value = 0;
+                for position in 0..len {
+                    if positions[value as usize] != u64::MAX { return None; }
+                    positions[value as usize] = position;
+                    value = next[value as usize];
+                }
+                Buffer::Linked(next, prev, positions, current)
+            }
+            2 if count == 0 => Buffer::Tracking(len),
+            _ => return None,
+        };
+        Some(SpinlockState {
+            stepsize: read(&bytes[0..8]),
+            position: position,
+            insertions: insertions,
+            after_zero: read(&bytes[24..32]),
+            buffer: buffer,
+        })
+    }
+}
+
+
+/// Panics if doing the given number of iterations would overflow positions
+fn check_overflow(stepsize: u64, iterations: u64) {
+    assert!(iterations.checked_add(stepsize).and_then(|n| n.checked_add(1)).is_some(),
+            "{} iterations with step size {} would overflow", iterations, stepsize);
+}
+
+/// Create a spinlock's ring buffer and return the value to
+/// short-circuit (value after last inserted value)
+fn spinlock_short_circuit(stepsize: u64, iterations: u64) -> u64 {
+    check_overflow(stepsize, iterations);
+    let mut spinlock = Spinlock::new(stepsize);
+    for _ in 0..iterations { spinlock.next(); }
+    spinlock.value_after_current()
 }
 
 /// Return the improved value to short-circuit the spinlock
 /// (value after zero, i.e. the second value) without actually
 /// building the whole spinlock
-fn spinlock_short_circuit_improved(stepsize: usize, iterations: usize) -> u32 {
+#[allow(dead_code)]
+fn spinlock_short_circuit_improved(stepsize: u64, iterations: u64) -> u64 {
+    check_overflow(stepsize, iterations);
     let mut value = 0;
     let mut position = 0;
-    for i in 1..iterations as u32 + 1 {
-        position = (position + stepsize) % i as usize;
+    for i in 1..iterations + 1 {
+        position = (position + stepsize) % i;
         if position == 0 { value = i; }
         position += 1;
     }
     value
 }
 
+/// Same as `spinlock_short_circuit_improved`, but skips over batches of
+/// insertions that can't land right after zero since they don't wrap around
+fn spinlock_after_zero_fast(stepsize: u64, iterations: u64) -> u64 {
+    check_overflow(stepsize, iterations);
+    let mut value = 0;
+    let mut position = 0;
+    let mut i = 1;
+    while i <= iterations {
+        position = (position + stepsize) % i;
+        if position == 0 { value = i; }
+        position += 1;
+        i += 1;
+        // Number of following insertions that don't wrap around
+        let skip = (i - position - 1).checked_div(stepsize).unwrap_or(iterations).min(iterations + 1 - i);
+        position += skip * (stepsize + 1);
+        i += skip;
+    }
+    value
+}
 
+/// Return the value after the given target value once the spinlock did
+/// the given number of iterations without actually building the whole
+/// spinlock. None if the target value doesn't get inserted.
+#[allow(dead_code)]
+fn value_after(stepsize: u64, iterations: u64, target: u64) -> Option<u64> {
+    check_overflow(stepsize, iterations);
+    if target > iterations { return None; }
+    let mut positions = Vec::with_capacity(target as usize);
+    let mut position = 0;
+    let mut inserted_position = 0;
+    let mut target_position = 0;
+    let mut value = None;
+    for i in 1..iterations + 1 {
+        position = (position + stepsize) % i + 1;
+        if i < target {
+            positions.push(position);
+        } else if i == target {
+            inserted_position = position;
+            target_position = position;
+        } else if position == target_position + 1 {
+            value = Some(i);
+        } else if position <= target_position {
+            target_position += 1;
+        }
+    }
+    // If nothing got inserted after the target, it is still followed by the
+    // value that was at its position right before it got inserted
+    Some(value.unwrap_or_else(|| {
+        let mut index = inserted_position % target.max(1);
+        for (i, &position) in positions.iter().enumerate().rev() {
+            if position == index { return i as u64 + 1; }
+            if position < index { index -= 1; }
+        }
+        0
+    }))
+}
+
+
+/// Usage: day17 [input file] [iterations] [improved iterations]
 fn main() {
-    const INPUT: usize = 371;
-    println!("Spinlock shortcut value: {}", spinlock_short_circuit(INPUT, 2017));
-    println!("Spinlock improved shortcut value: {}", spinlock_short_circuit_improved(INPUT, 50_000_000));
+    let args: Vec<String> = env::args().skip(1).collect();
+    let input: Input = match args.first() {
+        Some(path) => fs::read_to_string(path).expect("Unable to read input file").parse(),
+        None => include_str!("day17.txt").parse(),
+    }.unwrap_or_else(|e| panic!("{}", e));
+    let iterations = args.get(1).map_or(2017, |s| s.parse().expect("Invalid number of iterations"));
+    let improved_iterations = args.get(2).map_or(50_000_000, |s| s.parse().expect("Invalid number of iterations"));
+    println!("Spinlock shortcut value: {}", spinlock_short_circuit(input.stepsize, iterations));
+    println!("Spinlock improved shortcut value: {}", spinlock_after_zero_fast(input.stepsize, improved_iterations));
 }
 
 
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "nightly")]
+    extern crate test;
+
     use super::*;
 
+    #[test]
+    fn parsing() {
+        assert_eq!(Input::from_str("371\n"), Ok(Input { stepsize: 371 }));
+        assert_eq!(Input::from_str(" 3 "), Ok(Input { stepsize: 3 }));
+        assert_eq!(Input::from_str("37a"), Err(ParseError("37a".to_string())));
+        assert_eq!(Input::from_str("").unwrap_err().to_string(), "invalid step size ''");
+    }
+
+    #[test]
+    fn spinning() {
+        let mut spinlock = Spinlock::new(3);
+        assert_eq!(spinlock.next(), Some(Insertion { value: 1, position: 1, buffer_len: 2 }));
+        assert_eq!(spinlock.next(), Some(Insertion { value: 2, position: 1, buffer_len: 3 }));
+        assert_eq!(spinlock.next(), Some(Insertion { value: 3, position: 2, buffer_len: 4 }));
+        assert_eq!(spinlock.buffer().collect::<Vec<_>>(), [0, 2, 3, 1]);
+        for _ in spinlock.by_ref().take(6) {}
+        assert_eq!(spinlock.buffer().collect::<Vec<_>>(), [0, 9, 5, 7, 2, 4, 3, 8, 6, 1]);
+        assert_eq!(spinlock.value_after_current(), 5);
+        for _ in spinlock.by_ref().take(2017 - 9) {}
+        assert_eq!(spinlock.value_after_current(), 638);
+    }
+
+    #[test]
+    fn inspecting() {
+        let mut spinlock = Spinlock::new(3);
+        for _ in spinlock.by_ref().take(2017) {}
+        assert_eq!(spinlock.window(3), [1512, 1134, 151, 2017, 638, 1513, 851]);
+        assert_eq!(spinlock.position_of(2017), Some(spinlock.position));
+        assert_eq!(spinlock.position_of(0), Some(0));
+        assert_eq!(spinlock.position_of(2018), None);
+        for (i, value) in spinlock.buffer().enumerate() {
+            assert_eq!(spinlock.position_of(value), Some(i as u64));
+        }
+        let mut spinlock = Spinlock::new(3);
+        assert_eq!(spinlock.window(1), [0, 0, 0]);
+        spinlock.next();
+        assert_eq!(spinlock.window(2), [1, 0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn resuming() {
+        let mut spinlock = Spinlock::with_backend(3, Backend::Tracking);
+        for _ in spinlock.by_ref().take(1_000_000) {}
+        let bytes = spinlock.snapshot().to_bytes();
+        assert_eq!(bytes.len(), 33);
+        let mut spinlock = Spinlock::resume(SpinlockState::from_bytes(&bytes).unwrap());
+        for _ in spinlock.by_ref().take(1_000_000) {}
+        let mut uninterrupted = Spinlock::with_backend(3, Backend::Tracking);
+        for _ in uninterrupted.by_ref().take(2_000_000) {}
+        assert_eq!(spinlock.value_after_zero(), uninterrupted.value_after_zero());
+        assert_eq!(spinlock.value_after_zero(), spinlock_after_zero_fast(3, 2_000_000));
+        assert_eq!(spinlock.len(), 2_000_001);
+    }
+
+    #[test]
+    fn resuming_buffer() {
+        for &backend in &[Backend::Vec, Backend::Linked] {
+            let mut spinlock = Spinlock::with_backend(3, backend);
+            for _ in spinlock.by_ref().take(1000) {}
+            let state = spinlock.snapshot();
+            let bytes = state.to_bytes();
+            assert_eq!(SpinlockState::from_bytes(&bytes), Some(state));
+            assert_eq!(SpinlockState::from_bytes(&bytes[..bytes.len() - 1]), None);
+            let mut spinlock = Spinlock::resume(SpinlockState::from_bytes(&bytes).unwrap());
+            for _ in spinlock.by_ref().take(1017) {}
+            assert_eq!(spinlock.value_after_current(), 638);
+            assert_eq!(spinlock.value_after_zero(), 1226);
+        }
+        assert_eq!(SpinlockState::from_bytes(&[0; 32]), None);
+    }
+
+    #[test]
+    fn backends() {
+        for &stepsize in &[1, 3, 7, 371, 2017, 5000] {
+            let mut vec = Spinlock::with_backend(stepsize, Backend::Vec);
+            let mut linked = Spinlock::with_backend(stepsize, Backend::Linked);
+            for _ in 0..2017 {
+                assert_eq!(vec.next(), linked.next());
+                assert_eq!(vec.value_after_current(), linked.value_after_current());
+            }
+            assert!(vec.buffer().eq(linked.buffer()));
+            assert_eq!(vec.window(5), linked.window(5));
+            assert_eq!(vec.window(2500), linked.window(2500));
+            for value in 0..2019 {
+                assert_eq!(vec.position_of(value), linked.position_of(value));
+            }
+            vec.next();
+            linked.next();
+            assert_eq!(vec.position_of(2018), linked.position_of(2018));
+        }
+    }
+
     #[test]
     fn samples1() {
-        assert_eq!(spinlock_short_circuit(3, 2017), 638);
+        let input: Input = "3\n".parse().unwrap();
+        assert_eq!(spinlock_short_circuit(input.stepsize, 2017), 638);
     }
 
     #[test]
     fn samples2() {
         assert_eq!(spinlock_short_circuit_improved(3, 2017), 1226);
+        assert_eq!(spinlock_after_zero_fast(3, 2017), 1226);
+        assert_eq!(value_after(3, 2017, 0), Some(1226));
+    }
+
+    #[test]
+    fn after_zero_fast() {
+        for &stepsize in &[0, 1, 3, 7, 371] {
+            for iterations in 0..10_001 {
+                assert_eq!(spinlock_after_zero_fast(stepsize, iterations), spinlock_short_circuit_improved(stepsize, iterations));
+            }
+        }
+    }
+
+    #[test]
+    fn large_counts() {
+        assert_eq!(spinlock_after_zero_fast(371, 50_000_000), 39170601);
+        assert_eq!(spinlock_after_zero_fast(3, 5_000_000_000), 2_887_164_914);
+        let mut spinlock = Spinlock::with_backend(3, Backend::Tracking);
+        spinlock.position = u64::MAX - 5;
+        spinlock.buffer = Buffer::Tracking(u64::MAX - 1);
+        assert!(spinlock.next().is_some());
+        assert_eq!(spinlock.next(), None);
+    }
+
+    // Takes about half a minute even in release mode
+    #[test]
+    #[ignore]
+    fn large_counts_improved() {
+        assert_eq!(spinlock_short_circuit_improved(3, 5_000_000_000), spinlock_after_zero_fast(3, 5_000_000_000));
+    }
+
+    #[test]
+    #[should_panic]
+    fn overflowing_counts() {
+        spinlock_after_zero_fast(3, u64::MAX - 3);
+    }
+
+    #[test]
+    fn value_after_target() {
+        for &stepsize in &[3, 371] {
+            let mut spinlock = Spinlock::new(stepsize);
+            for _ in spinlock.by_ref().take(2017) {}
+            let buffer: Vec<u64> = spinlock.buffer().collect();
+            for &target in &[0, 1, 500, 2017] {
+                let index = buffer.iter().position(|&v| v == target).unwrap();
+                assert_eq!(value_after(stepsize, 2017, target), Some(buffer[(index + 1) % buffer.len()]));
+            }
+            assert_eq!(value_after(stepsize, 2017, 0), Some(spinlock_short_circuit_improved(stepsize, 2017)));
+            assert_eq!(value_after(stepsize, 2017, 2018), None);
+        }
+        assert_eq!(value_after(3, 0, 0), Some(0));
+        let mut spinlock = Spinlock::new(7);
+        for _ in spinlock.by_ref().take(300) {}
+        let buffer: Vec<u64> = spinlock.buffer().collect();
+        for (index, &target) in buffer.iter().enumerate() {
+            assert_eq!(value_after(7, 300, target), Some(buffer[(index + 1) % buffer.len()]));
+        }
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_vec(b: &mut test::Bencher) {
+        b.iter(|| {
+            let mut spinlock = Spinlock::with_backend(371, Backend::Vec);
+            for _ in spinlock.by_ref().take(1_000_000) {}
+            spinlock.value_after_current()
+        })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_linked(b: &mut test::Bencher) {
+        b.iter(|| {
+            let mut spinlock = Spinlock::with_backend(371, Backend::Linked);
+            for _ in spinlock.by_ref().take(1_000_000) {}
+            spinlock.value_after_current()
+        })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_improved(b: &mut test::Bencher) {
+        b.iter(|| {
+            spinlock_short_circuit_improved(371, 50_000_000)
+        })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_after_zero_fast(b: &mut test::Bencher) {
+        b.iter(|| {
+            spinlock_after_zero_fast(371, 50_000_000)
+        })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_after_zero_fast_large(b: &mut test::Bencher) {
+        b.iter(|| {
+            spinlock_after_zero_fast(3, 5_000_000_000)
+        })
     }
 }
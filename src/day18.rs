@@ -1,27 +1,48 @@
+#![cfg_attr(feature = "nightly", feature(test))]
+
 #[macro_use]
 extern crate nom;
 
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
 use std::str::FromStr;
-use nom::digit;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::thread;
+use std::time::Duration;
+use nom::{digit, multispace};
 
 
-#[derive(Debug, Clone)]
+/// Registers a to z. Register names must be lowercase ASCII letters,
+/// which is ensured when parsing code.
+#[derive(Clone)]
 struct RegisterSet {
-    regs: HashMap<char, i64>,
+    regs: [i64; 26],
+}
+
+impl fmt::Debug for RegisterSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.nonzero()).finish()
+    }
 }
 
 impl RegisterSet {
     fn new() -> RegisterSet {
-        RegisterSet { regs: HashMap::new() }
+        RegisterSet { regs: [0; 26] }
     }
 
     fn get(&self, r: char) -> i64 {
-        self.regs.get(&r).cloned().unwrap_or(0)
+        self.regs[r as usize - 'a' as usize]
     }
 
     fn set(&mut self, r: char, v: i64) {
-        self.regs.insert(r, v);
+        self.regs[r as usize - 'a' as usize] = v;
+    }
+
+    /// Registers with non-zero values, ordered by name
+    fn nonzero(&self) -> Vec<(char, i64)> {
+        (b'a'..b'z' + 1).map(|r| r as char).zip(self.regs.iter().cloned()).filter(|&(_, v)| v != 0).collect()
     }
 }
 
@@ -41,6 +62,15 @@ impl Value {
     }
 }
 
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Register(r) => write!(f, "{}", r),
+            Value::Number(n) => write!(f, "{}", n),
+        }
+    }
+}
+
 
 #[derive(Debug, Clone)]
 enum Instruction {
@@ -53,8 +83,25 @@ enum Instruction {
     Jgz(Value, Value)
 }
 
+/// Error parsing code
+#[derive(Debug, Clone, PartialEq)]
+struct ParseError {
+    /// Line number (1-based)
+    line: usize,
+    /// Text of the line
+    text: String,
+    reason: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {} ('{}')", self.line, self.reason, self.text)
+    }
+}
+
+
 impl FromStr for Instruction {
-    type Err = nom::ErrorKind;
+    type Err = ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         named!(register<&str, char>, one_of!("abcdefghijklmnopqrstuvwxyz"));
@@ -67,15 +114,68 @@ impl FromStr for Instruction {
             register => { |ch| Value::Register(ch) } |
             number   => {  |n| Value::Number(n) }
         ));
-        complete!(s, alt!(
-            do_parse!(tag!("snd") >> x: ws!(value) >> (Instruction::Snd(x))) |
-            do_parse!(tag!("set") >> x: ws!(register) >> y: ws!(value) >> (Instruction::Set(x, y))) |
-            do_parse!(tag!("add") >> x: ws!(register) >> y: ws!(value) >> (Instruction::Add(x, y))) |
-            do_parse!(tag!("mul") >> x: ws!(register) >> y: ws!(value) >> (Instruction::Mul(x, y))) |
-            do_parse!(tag!("mod") >> x: ws!(register) >> y: ws!(value) >> (Instruction::Mod(x, y))) |
-            do_parse!(tag!("rcv") >> x: ws!(register) >> (Instruction::Rcv(x))) |
-            do_parse!(tag!("jgz") >> x: ws!(value) >> y: ws!(value) >> (Instruction::Jgz(x, y)))
-        )).to_result()
+        complete!(s, terminated!(alt!(
+            do_parse!(tag!("snd") >> multispace >> x: ws!(value) >> (Instruction::Snd(x))) |
+            do_parse!(tag!("set") >> multispace >> x: ws!(register) >> y: ws!(value) >> (Instruction::Set(x, y))) |
+            do_parse!(tag!("add") >> multispace >> x: ws!(register) >> y: ws!(value) >> (Instruction::Add(x, y))) |
+            do_parse!(tag!("mul") >> multispace >> x: ws!(register) >> y: ws!(value) >> (Instruction::Mul(x, y))) |
+            do_parse!(tag!("mod") >> multispace >> x: ws!(register) >> y: ws!(value) >> (Instruction::Mod(x, y))) |
+            do_parse!(tag!("rcv") >> multispace >> x: ws!(register) >> (Instruction::Rcv(x))) |
+            do_parse!(tag!("jgz") >> multispace >> x: ws!(value) >> y: ws!(value) >> (Instruction::Jgz(x, y)))
+        ), eof!())).to_result().map_err(|_| ParseError { line: 1, text: s.to_string(), reason: Instruction::diagnose(s) })
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Snd(ref x) => write!(f, "snd {}", x),
+            Instruction::Set(x, ref y) => write!(f, "set {} {}", x, y),
+            Instruction::Add(x, ref y) => write!(f, "add {} {}", x, y),
+            Instruction::Mul(x, ref y) => write!(f, "mul {} {}", x, y),
+            Instruction::Mod(x, ref y) => write!(f, "mod {} {}", x, y),
+            Instruction::Rcv(x) => write!(f, "rcv {}", x),
+            Instruction::Jgz(ref x, ref y) => write!(f, "jgz {} {}", x, y),
+        }
+    }
+}
+
+impl Instruction {
+    /// Best-effort reason why the given line can't be parsed
+    fn diagnose(s: &str) -> String {
+        let mut tokens = s.split_whitespace();
+        let operands: &[bool] = match tokens.next() {
+            // true for a register, false for a value
+            Some("snd") => &[false],
+            Some("set") | Some("add") | Some("mul") | Some("mod") => &[true, false],
+            Some("rcv") => &[true],
+            Some("jgz") => &[false, false],
+            Some(mnemonic) => return format!("unknown instruction '{}'", mnemonic),
+            None => return "missing instruction".to_string(),
+        };
+        let is_register = |token: &str| token.len() == 1 && token.chars().all(|ch| ch.is_ascii_lowercase());
+        let is_number = |token: &str| token.trim_start_matches('-').parse::<u64>().is_ok();
+        for &register in operands {
+            let expected = if register { "register" } else { "value" };
+            match tokens.next() {
+                Some(token) if is_register(token) || (!register && is_number(token)) => (),
+                Some(token) => return format!("expected {}, found '{}'", expected, token),
+                None => return format!("expected {}, found end of line", expected),
+            }
+        }
+        match tokens.next() {
+            Some(_) => "trailing characters".to_string(),
+            None => "malformed instruction".to_string(),
+        }
+    }
+
+    /// Register the instruction writes to, if any
+    fn target(&self) -> Option<char> {
+        match *self {
+            Instruction::Set(r, _) | Instruction::Add(r, _) | Instruction::Mul(r, _) |
+            Instruction::Mod(r, _) | Instruction::Rcv(r) => Some(r),
+            Instruction::Snd(_) | Instruction::Jgz(_, _) => None,
+        }
     }
 }
 
@@ -84,6 +184,317 @@ impl FromStr for Instruction {
 enum CoreError {
     OutOfInstructions,
     Deadlock,
+    /// Jump from the given instruction to a target outside of the code (saturated
+    /// if not representable). Terminates the core like running out of instructions.
+    JumpedOutOfBounds { from: usize, target: isize },
+    /// Arithmetic error in the instruction at pc
+    Arithmetic { pc: usize, kind: ArithmeticError },
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum ArithmeticError {
+    Overflow,
+    DivisionByZero,
+}
+
+
+/// Behavior of arithmetic instructions on overflow. Taking the
+/// modulus of zero is an error regardless of the policy.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(dead_code)]
+enum Arithmetic {
+    /// Wrap around on overflow (default)
+    Wrapping,
+    /// Saturate at the numeric bounds on overflow
+    Saturating,
+    /// Fail on overflow
+    Checked,
+}
+
+impl Arithmetic {
+    fn add(self, a: i64, b: i64) -> Result<i64, ArithmeticError> {
+        match self {
+            Arithmetic::Wrapping => Ok(a.wrapping_add(b)),
+            Arithmetic::Saturating => Ok(a.saturating_add(b)),
+            Arithmetic::Checked => a.checked_add(b).ok_or(ArithmeticError::Overflow),
+        }
+    }
+
+    fn mul(self, a: i64, b: i64) -> Result<i64, ArithmeticError> {
+        match self {
+            Arithmetic::Wrapping => Ok(a.wrapping_mul(b)),
+            Arithmetic::Saturating => Ok(a.saturating_mul(b)),
+            Arithmetic::Checked => a.checked_mul(b).ok_or(ArithmeticError::Overflow),
+        }
+    }
+
+    fn rem(self, a: i64, b: i64) -> Result<i64, ArithmeticError> {
+        match (self, b) {
+            (_, 0) => Err(ArithmeticError::DivisionByZero),
+            (Arithmetic::Checked, _) => a.checked_rem(b).ok_or(ArithmeticError::Overflow),
+            (_, _) => Ok(a.wrapping_rem(b)),
+        }
+    }
+}
+
+
+/// Change of a register's value
+#[derive(Debug, Clone, PartialEq)]
+struct RegisterChange {
+    register: char,
+    old: i64,
+    new: i64,
+}
+
+
+/// Trace of an executed instruction
+#[derive(Debug, Clone)]
+struct TraceEvent {
+    /// Id of the program that executed the instruction
+    program: usize,
+    /// Address of the executed instruction
+    pc: usize,
+    instruction: Instruction,
+    /// Register changed by the instruction, if any
+    change: Option<RegisterChange>,
+}
+
+impl fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "[{}] {:3}: {}", self.program, self.pc, self.instruction));
+        if let Some(ref change) = self.change {
+            try!(write!(f, " ({}: {} -> {})", change.register, change.old, change.new));
+        }
+        Ok(())
+    }
+}
+
+
+/// Reason for a core to stop running
+#[derive(Debug, Clone, PartialEq)]
+enum StopReason {
+    /// Program reached a breakpoint, i.e. is about to execute the instruction at pc
+    Breakpoint { program: usize, pc: usize },
+    /// Frequency of a recovered sound
+    Recovered(i64),
+    /// Core can't run any further
+    Stopped(CoreError),
+}
+
+
+/// Outcome of running with a limited number of steps, including the
+/// progress made
+#[derive(Debug, Clone, PartialEq)]
+enum BoundedOutcome<T> {
+    /// Finished running (terminated or deadlocked)
+    Completed(T),
+    /// Stopped after running the maximum number of steps
+    BudgetExhausted(T),
+    /// Stopped by a watchdog
+    Cancelled(T),
+}
+
+
+/// Result of receiving a value
+#[derive(Debug, Clone, PartialEq)]
+enum RecvResult {
+    /// Value to store in the register
+    Value(i64),
+    /// No value available yet, core needs to wait
+    WouldBlock,
+    /// Recover the last sound played (if the register is non-zero)
+    Recover(Option<i64>),
+}
+
+
+/// Input/output semantics of snd and rcv instructions
+trait Io {
+    /// Send a value (snd instruction)
+    fn send(&mut self, value: i64);
+
+    /// Receive a value (rcv instruction)
+    fn recv(&mut self) -> RecvResult;
+}
+
+
+/// Sound card semantics: snd plays a sound, rcv recovers it
+#[derive(Debug, Default)]
+struct SoundIo {
+    sound: Option<i64>,
+}
+
+impl Io for SoundIo {
+    fn send(&mut self, value: i64) {
+        self.sound = Some(value);
+    }
+
+    fn recv(&mut self) -> RecvResult {
+        RecvResult::Recover(self.sound)
+    }
+}
+
+
+/// Queue semantics: snd sends a value to other cores, rcv waits for
+/// a value sent by another core
+#[derive(Debug)]
+struct QueueIo<'a> {
+    /// Inbound queues of all programs
+    queues: &'a mut [VecDeque<i64>],
+    program: usize,
+    topology: Topology,
+    /// Statistics of all programs
+    reports: &'a mut [ProgramReport],
+}
+
+impl<'a> Io for QueueIo<'a> {
+    fn send(&mut self, value: i64) {
+        for target in self.topology.targets(self.program, self.queues.len()) {
+            self.queues[target].push_back(value);
+            let report = &mut self.reports[target];
+            report.max_queue_len = report.max_queue_len.max(self.queues[target].len());
+        }
+        self.reports[self.program].sent += 1;
+    }
+
+    fn recv(&mut self) -> RecvResult {
+        match self.queues[self.program].pop_front() {
+            Some(n) => {
+                self.reports[self.program].received += 1;
+                RecvResult::Value(n)
+            }
+            None => RecvResult::WouldBlock,
+        }
+    }
+}
+
+
+/// Blocking state shared by all threads communicating over channels
+#[derive(Debug, Default)]
+struct ChannelState {
+    /// Number of threads that didn't terminate yet
+    running: AtomicUsize,
+    /// Number of threads waiting for a value
+    blocked: AtomicUsize,
+    /// Number of values sent by all threads
+    sequence: AtomicUsize,
+}
+
+
+/// Channel semantics: snd sends a value to other threads, rcv waits for a
+/// value sent by another thread. All running threads waiting longer than
+/// the timeout with nothing sent meanwhile, or waiting for a value when all
+/// senders are gone is a deadlock.
+#[derive(Debug)]
+struct ChannelIo {
+    rx: Receiver<i64>,
+    tx: Vec<Sender<i64>>,
+    timeout: Duration,
+    sent: usize,
+    state: Arc<ChannelState>,
+}
+
+impl ChannelIo {
+    /// Create connected I/O for the given number of threads
+    fn network(n: usize, topology: Topology, timeout: Duration) -> Vec<ChannelIo> {
+        let state = Arc::new(ChannelState::default());
+        state.running.store(n, Ordering::SeqCst);
+        let (txs, rxs): (Vec<Sender<i64>>, Vec<Receiver<i64>>) = (0..n).map(|_| mpsc::channel()).unzip();
+        rxs.into_iter().enumerate().map(|(program, rx)| {
+            let tx = topology.targets(program, n).into_iter().map(|target| txs[target].clone()).collect();
+            ChannelIo { rx: rx, tx: tx, timeout: timeout, sent: 0, state: state.clone() }
+        }).collect()
+    }
+}
+
+impl Io for ChannelIo {
+    fn send(&mut self, value: i64) {
+        for tx in &self.tx {
+            // Sending fails if the receiver terminated already, which is fine
+            let _ = tx.send(value);
+        }
+        self.sent += 1;
+        self.state.sequence.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn recv(&mut self) -> RecvResult {
+        match self.rx.try_recv() {
+            Ok(n) => return RecvResult::Value(n),
+            Err(TryRecvError::Disconnected) => return RecvResult::WouldBlock,
+            Err(TryRecvError::Empty) => (),
+        }
+        self.state.blocked.fetch_add(1, Ordering::SeqCst);
+        let mut sequence = self.state.sequence.load(Ordering::SeqCst);
+        let result = loop {
+            match self.rx.recv_timeout(self.timeout) {
+                Ok(n) => break RecvResult::Value(n),
+                Err(RecvTimeoutError::Disconnected) => break RecvResult::WouldBlock,
+                Err(RecvTimeoutError::Timeout) => {
+                    let current = self.state.sequence.load(Ordering::SeqCst);
+                    if current == sequence &&
+                        self.state.blocked.load(Ordering::SeqCst) >= self.state.running.load(Ordering::SeqCst) {
+                        break RecvResult::WouldBlock;
+                    }
+                    sequence = current;
+                }
+            }
+        };
+        self.state.blocked.fetch_sub(1, Ordering::SeqCst);
+        result
+    }
+}
+
+impl Drop for ChannelIo {
+    fn drop(&mut self) {
+        self.state.running.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+
+/// Error running programs in threads
+#[derive(Debug, Clone, PartialEq)]
+enum RunError {
+    /// Thread of the given program panicked
+    Panicked(usize),
+    /// Program failed with an error other than terminating or deadlocking
+    Failed { program: usize, error: CoreError },
+}
+
+
+/// Statistics of a program running on a dual core
+#[derive(Debug, Clone, Default, PartialEq)]
+struct ProgramReport {
+    /// Number of values sent
+    sent: usize,
+    /// Number of values received
+    received: usize,
+    /// Number of instructions executed
+    steps: usize,
+    /// Maximum length of the inbound queue
+    max_queue_len: usize,
+    /// Why the program stopped, None if it's still able to run
+    stopped: Option<CoreError>,
+}
+
+
+impl ProgramReport {
+    /// Record the result of a step
+    fn record(&mut self, result: &Result<Option<i64>, CoreError>) {
+        match *result {
+            Ok(_) => {
+                self.steps += 1;
+                self.stopped = None;
+            }
+            Err(ref e) => self.stopped = Some(e.clone()),
+        }
+    }
+}
+
+
+/// Statistics of all programs running on a multi core
+#[derive(Debug, Clone, Default, PartialEq)]
+struct RunReport {
+    programs: Vec<ProgramReport>,
 }
 
 
@@ -92,143 +503,505 @@ struct Core {
     code: Vec<Instruction>,
     pc: usize,
     regs: RegisterSet,
-    freq: Option<i64>,
+    arithmetic: Arithmetic,
 }
 
 impl FromStr for Core {
-    type Err = nom::ErrorKind;
+    type Err = ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code = s.lines().enumerate().map(|(i, line)| {
+            line.parse().map_err(|e| ParseError { line: i + 1, ..e })
+        });
         Ok(Core {
-            code: try!(s.lines().map(str::parse).collect()),
+            code: try!(code.collect()),
             pc: 0,
             regs: RegisterSet::new(),
-            freq: None,
+            arithmetic: Arithmetic::Wrapping,
         })
     }
 }
 
 impl Core {
-    fn step(&mut self) -> Result<(), CoreError> {
+    /// Use the given arithmetic policy
+    #[allow(dead_code)]
+    fn with_arithmetic(mut self, arithmetic: Arithmetic) -> Core {
+        self.arithmetic = arithmetic;
+        self
+    }
+
+    /// Execute a single instruction using the given I/O. Returns the
+    /// frequency if a sound got recovered.
+    fn step<I: Io>(&mut self, io: &mut I) -> Result<Option<i64>, CoreError> {
+        let mut recovered = None;
+        let pc = self.pc;
+        let arithmetic_error = |kind| CoreError::Arithmetic { pc: pc, kind: kind };
         match self.code.get(self.pc) {
             Some(ins) => {
                 match ins {
                     &Instruction::Snd(ref v) => {
-                        let n = v.get(&self.regs);
-                        self.freq = Some(n);
+                        io.send(v.get(&self.regs));
                     },
                     &Instruction::Set(r, ref v) => {
                         let n = v.get(&self.regs);
                         self.regs.set(r, n)
                     },
                     &Instruction::Add(r, ref v) => {
-                        let n = self.regs.get(r) + v.get(&self.regs);
+                        let n = try!(self.arithmetic.add(self.regs.get(r), v.get(&self.regs)).map_err(arithmetic_error));
                         self.regs.set(r, n);
                     },
                     &Instruction::Mul(r, ref v) => {
-                        let n = self.regs.get(r) * v.get(&self.regs);
+                        let n = try!(self.arithmetic.mul(self.regs.get(r), v.get(&self.regs)).map_err(arithmetic_error));
                         self.regs.set(r, n);
                     },
                     &Instruction::Mod(r, ref v) => {
-                        let n = self.regs.get(r) % v.get(&self.regs);
+                        let n = try!(self.arithmetic.rem(self.regs.get(r), v.get(&self.regs)).map_err(arithmetic_error));
                         self.regs.set(r, n);
                     },
                     &Instruction::Rcv(r) => {
-                        if self.regs.get(r) != 0 {
-                            self.freq = None;
+                        match io.recv() {
+                            RecvResult::Value(n) => self.regs.set(r, n),
+                            RecvResult::WouldBlock => return Err(CoreError::Deadlock),
+                            RecvResult::Recover(freq) => if self.regs.get(r) != 0 { recovered = freq },
                         }
                     }
                     &Instruction::Jgz(ref v, ref ofs) => {
                         if v.get(&self.regs) > 0 {
-                            let ofs = ofs.get(&self.regs);
-                            self.pc = (self.pc as isize + ofs as isize - 1) as usize;
+                            let ofs = ofs.get(&self.regs) as isize;
+                            let target = (self.pc as isize).checked_add(ofs)
+                                .unwrap_or(if ofs < 0 { isize::MIN } else { isize::MAX });
+                            if target < 0 || target >= self.code.len() as isize {
+                                return Err(CoreError::JumpedOutOfBounds { from: self.pc, target: target });
+                            }
+                            self.pc = target as usize;
+                            return Ok(recovered);
                         }
                     },
                 }
                 self.pc += 1;
-                Ok(())
+                Ok(recovered)
             }
             None => Err(CoreError::OutOfInstructions),
         }
     }
 
+    /// Same as `step`, but reports the executed instruction to the given sink
+    fn step_traced<I: Io, F: FnMut(TraceEvent)>(&mut self, io: &mut I, sink: &mut F) -> Result<Option<i64>, CoreError> {
+        let pc = self.pc;
+        let instruction = match self.code.get(pc) {
+            Some(ins) => ins.clone(),
+            None => return Err(CoreError::OutOfInstructions),
+        };
+        let old = instruction.target().map(|r| (r, self.regs.get(r)));
+        let recovered = try!(self.step(io));
+        let change = old.and_then(|(r, old)| {
+            let new = self.regs.get(r);
+            if new != old { Some(RegisterChange { register: r, old: old, new: new }) } else { None }
+        });
+        sink(TraceEvent { program: 0, pc: pc, instruction: instruction, change: change });
+        Ok(recovered)
+    }
+
+    /// Run until reaching one of the given breakpoints, recovering a sound or
+    /// failing. Always executes at least one instruction, so it can be
+    /// called again to resume after a breakpoint.
+    #[allow(dead_code)]
+    fn run_until_pc<I: Io>(&mut self, io: &mut I, pcs: &HashSet<usize>) -> StopReason {
+        loop {
+            match self.step(io) {
+                Ok(Some(freq)) => return StopReason::Recovered(freq),
+                Ok(None) if pcs.contains(&self.pc) => return StopReason::Breakpoint { program: 0, pc: self.pc },
+                Ok(None) => (),
+                Err(e) => return StopReason::Stopped(e),
+            }
+        }
+    }
+
+    /// Frequency of the last sound played before the first sound is recovered.
+    /// None if the core terminates without recovering a sound.
     fn run_until_recv(&mut self) -> Option<i64> {
-        let mut last_freq = None;
-        while let Ok(_) = self.step() {
-            if self.freq.is_none() && last_freq.is_some() {
-                return last_freq;
+        let mut sounds = self.sounds();
+        let last = sounds.by_ref().last();
+        if sounds.recovered { last } else { None }
+    }
+
+    /// Same as `run_until_recv`, but stops after the given number of steps,
+    /// reporting the last sound played so far
+    #[allow(dead_code)]
+    fn run_until_recv_bounded(&mut self, max_steps: usize) -> BoundedOutcome<Option<i64>> {
+        let mut io = SoundIo::default();
+        for _ in 0..max_steps {
+            match self.step(&mut io) {
+                Ok(Some(freq)) => return BoundedOutcome::Completed(Some(freq)),
+                Ok(None) => (),
+                Err(_) => return BoundedOutcome::Completed(None),
+            }
+        }
+        BoundedOutcome::BudgetExhausted(io.sound)
+    }
+
+    /// Iterator over sounds played until the core terminates or the first
+    /// sound is recovered
+    fn sounds(&mut self) -> Sounds<'_> {
+        Sounds { core: self, io: SoundIo::default(), stop_at_recover: true, recovered: false, done: false }
+    }
+
+    /// Iterator over sounds played until the core terminates, ignoring
+    /// recovered sounds
+    #[allow(dead_code)]
+    fn all_sounds(&mut self) -> Sounds<'_> {
+        Sounds { core: self, io: SoundIo::default(), stop_at_recover: false, recovered: false, done: false }
+    }
+}
+
+
+/// Iterator over sounds played by a core
+#[derive(Debug)]
+struct Sounds<'a> {
+    core: &'a mut Core,
+    io: SoundIo,
+    stop_at_recover: bool,
+    /// True if the iterator ended because a sound got recovered
+    recovered: bool,
+    done: bool,
+}
+
+impl<'a> Iterator for Sounds<'a> {
+    type Item = i64;
+
+    fn next(&mut self) -> Option<i64> {
+        while !self.done {
+            let snd = matches!(self.core.code.get(self.core.pc), Some(&Instruction::Snd(_)));
+            match self.core.step(&mut self.io) {
+                Ok(Some(_)) if self.stop_at_recover => {
+                    self.recovered = true;
+                    self.done = true;
+                }
+                Ok(_) if snd => return self.io.sound,
+                Ok(_) => (),
+                Err(_) => self.done = true,
             }
-            last_freq = self.freq;
         }
         None
     }
 }
 
 
+/// Saved state of a program running on a multi core
+#[derive(Debug, Clone, PartialEq)]
+struct ProgramState {
+    pc: usize,
+    /// Registers with non-zero values
+    regs: Vec<(char, i64)>,
+    sent: usize,
+    /// Values in the inbound queue
+    queue: Vec<i64>,
+}
+
+
+/// Error decoding a saved state
+#[derive(Debug, Clone, PartialEq)]
+enum StateError {
+    Truncated,
+    Invalid,
+}
+
+
+/// Saved state of all programs running on a multi core
+#[derive(Debug, Clone, PartialEq)]
+struct DuetState {
+    topology: Topology,
+    programs: Vec<ProgramState>,
+}
+
+impl DuetState {
+    /// Encode state to bytes (little-endian). For each program, the pc and
+    /// number of sent values are followed by length-prefixed registers
+    /// (name and value) and the length-prefixed inbound queue.
+    #[allow(dead_code)]
+    fn to_bytes(&self) -> Vec<u8> {
+        fn write(bytes: &mut Vec<u8>, value: u64, len: usize) {
+            bytes.extend((0..len).map(|i| (value >> (i * 8)) as u8));
+        }
+        let mut bytes = Vec::new();
+        bytes.push(match self.topology { Topology::Ring => 0, Topology::Broadcast => 1 });
+        write(&mut bytes, self.programs.len() as u64, 4);
+        for program in &self.programs {
+            write(&mut bytes, program.pc as u64, 8);
+            write(&mut bytes, program.sent as u64, 8);
+            write(&mut bytes, program.regs.len() as u64, 1);
+            for &(r, v) in &program.regs {
+                bytes.push(r as u8);
+                write(&mut bytes, v as u64, 8);
+            }
+            write(&mut bytes, program.queue.len() as u64, 4);
+            for &v in &program.queue {
+                write(&mut bytes, v as u64, 8);
+            }
+        }
+        bytes
+    }
+
+    /// Decode state from bytes (little-endian)
+    #[allow(dead_code)]
+    fn from_bytes(mut bytes: &[u8]) -> Result<DuetState, StateError> {
+        let mut read = |len: usize| {
+            if bytes.len() < len { return Err(StateError::Truncated); }
+            let value = bytes[..len].iter().rev().fold(0u64, |value, &b| value << 8 | b as u64);
+            bytes = &bytes[len..];
+            Ok(value)
+        };
+        let topology = match try!(read(1)) {
+            0 => Topology::Ring,
+            1 => Topology::Broadcast,
+            _ => return Err(StateError::Invalid),
+        };
+        let mut programs = Vec::new();
+        for _ in 0..try!(read(4)) {
+            let pc = try!(read(8)) as usize;
+            let sent = try!(read(8)) as usize;
+            let mut regs = Vec::new();
+            for _ in 0..try!(read(1)) {
+                let r = try!(read(1)) as u8 as char;
+                if !r.is_ascii_lowercase() { return Err(StateError::Invalid); }
+                regs.push((r, try!(read(8)) as i64));
+            }
+            let mut queue = Vec::new();
+            for _ in 0..try!(read(4)) {
+                queue.push(try!(read(8)) as i64);
+            }
+            programs.push(ProgramState { pc: pc, regs: regs, sent: sent, queue: queue });
+        }
+        if !bytes.is_empty() { return Err(StateError::Invalid); }
+        Ok(DuetState { topology: topology, programs: programs })
+    }
+}
+
+
+/// Programs that receive values sent by a program
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(dead_code)]
+enum Topology {
+    /// Each program sends to the next one (the last one to the first one)
+    Ring,
+    /// Each program sends to all other programs
+    Broadcast,
+}
+
+impl Topology {
+    /// Programs receiving values sent by the given program
+    fn targets(self, program: usize, n: usize) -> Vec<usize> {
+        match self {
+            Topology::Ring => vec![(program + 1) % n],
+            Topology::Broadcast => (0..n).filter(|&i| i != program).collect(),
+        }
+    }
+}
+
+
+/// Multiple programs running the same code and communicating with each other
 #[derive(Debug)]
-struct DualCore {
-    core1: Core,
-    core2: Core,
-    queue1: VecDeque<i64>,
-    queue2: VecDeque<i64>,
-    txcount1: usize,
-    txcount2: usize,
+struct MultiCore {
+    cores: Vec<Core>,
+    /// Inbound queue of each program
+    queues: Vec<VecDeque<i64>>,
+    topology: Topology,
+    report: RunReport,
 }
 
-impl FromStr for DualCore {
-    type Err = nom::ErrorKind;
+/// Two programs sending to each other
+type DualCore = MultiCore;
+
+impl FromStr for MultiCore {
+    type Err = ParseError;
 
+    /// Parse code for two programs (i.e. a dual core)
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut core1: Core = try!(s.parse());
-        let mut core2: Core = core1.clone();
-        core1.regs.set('p', 0);
-        core2.regs.set('p', 1);
-        Ok(DualCore {
-            core1: core1,
-            core2: core2,
-            queue1: VecDeque::new(),
-            queue2: VecDeque::new(),
-            txcount1: 0,
-            txcount2: 0,
-        })
+        MultiCore::new(s, 2)
     }
 }
 
-impl DualCore {
-    fn run(&mut self) -> (usize, usize) {
+impl MultiCore {
+    /// Create the given number of programs running the given code, each one
+    /// sending to the next one
+    fn new(source: &str, n: usize) -> Result<MultiCore, ParseError> {
+        MultiCore::with_topology(source, n, Topology::Ring)
+    }
+
+    /// Create the given number of programs running the given code, using
+    /// the given topology
+    fn with_topology(source: &str, n: usize, topology: Topology) -> Result<MultiCore, ParseError> {
+        let core: Core = try!(source.parse());
+        let cores = (0..n).map(|i| {
+            let mut core = core.clone();
+            core.regs.set('p', i as i64);
+            core
+        }).collect();
+        Ok(MultiCore {
+            cores: cores,
+            queues: vec![VecDeque::new(); n],
+            topology: topology,
+            report: RunReport { programs: vec![ProgramReport::default(); n] },
+        })
+    }
+
+    /// Save the state of all programs
+    #[allow(dead_code)]
+    fn snapshot(&self) -> DuetState {
+        DuetState {
+            topology: self.topology,
+            programs: self.cores.iter().zip(&self.queues).enumerate().map(|(program, (core, queue))| {
+                ProgramState {
+                    pc: core.pc,
+                    regs: core.regs.nonzero(),
+                    sent: self.sends_by(program),
+                    queue: queue.iter().cloned().collect(),
+                }
+            }).collect(),
+        }
+    }
+
+    /// Create programs running the given code, resuming from the given state.
+    /// Statistics other than the number of sent values start over.
+    #[allow(dead_code)]
+    fn restore(source: &str, state: DuetState) -> Result<MultiCore, ParseError> {
+        let mut multicore = try!(MultiCore::with_topology(source, state.programs.len(), state.topology));
+        for (i, program) in state.programs.into_iter().enumerate() {
+            let core = &mut multicore.cores[i];
+            core.pc = program.pc;
+            core.regs = RegisterSet::new();
+            for (r, v) in program.regs {
+                core.regs.set(r, v);
+            }
+            multicore.report.programs[i].sent = program.sent;
+            multicore.report.programs[i].max_queue_len = program.queue.len();
+            multicore.queues[i] = program.queue.into_iter().collect();
+        }
+        Ok(multicore)
+    }
+
+    /// Run until no program is able to run anymore and return the number
+    /// of values each program sent
+    fn run(&mut self) -> Vec<usize> {
+        while let Ok(_) = self.step() {}
+        (0..self.cores.len()).map(|i| self.sends_by(i)).collect()
+    }
+
+    /// Run each program in its own thread, communicating over channels, until
+    /// no program is able to run anymore and return the number of values each
+    /// program sent. All running programs waiting for a value longer than the
+    /// given timeout without anything sent are considered deadlocked.
+    #[allow(dead_code)]
+    fn run_threaded(self, timeout: Duration) -> Result<Vec<usize>, RunError> {
+        let ios = ChannelIo::network(self.cores.len(), self.topology, timeout);
+        let threads: Vec<_> = self.cores.into_iter().zip(ios).map(|(mut core, mut io)| {
+            thread::spawn(move || {
+                loop {
+                    match core.step(&mut io) {
+                        Ok(_) => (),
+                        Err(e @ CoreError::Arithmetic { .. }) => return Err(e),
+                        Err(_) => return Ok(io.sent),
+                    }
+                }
+            })
+        }).collect();
+        threads.into_iter().enumerate().map(|(program, thread)| {
+            match thread.join() {
+                Ok(Ok(sent)) => Ok(sent),
+                Ok(Err(error)) => Err(RunError::Failed { program: program, error: error }),
+                Err(_) => Err(RunError::Panicked(program)),
+            }
+        }).collect()
+    }
+
+    /// Run until no program is able to run anymore, but at most the given
+    /// number of steps, and report statistics
+    #[allow(dead_code)]
+    fn run_bounded(&mut self, max_steps: usize) -> BoundedOutcome<RunReport> {
+        self.run_watchdog(max_steps, 0, |_| true)
+    }
+
+    /// Same as `run_bounded`, but additionally calls the given watchdog
+    /// every given number of steps (never if zero), stopping if it returns
+    /// false
+    fn run_watchdog<F: FnMut(&RunReport) -> bool>(&mut self, max_steps: usize, every: usize, mut watchdog: F) -> BoundedOutcome<RunReport> {
+        for steps in 1..max_steps as u64 + 1 {
+            if self.step().is_err() {
+                return BoundedOutcome::Completed(self.report.clone());
+            }
+            if every > 0 && steps % every as u64 == 0 && !watchdog(&self.report) {
+                return BoundedOutcome::Cancelled(self.report.clone());
+            }
+        }
+        BoundedOutcome::BudgetExhausted(self.report.clone())
+    }
+
+    /// Run until no program is able to run anymore and report statistics
+    #[allow(dead_code)]
+    fn run_report(&mut self) -> RunReport {
         while let Ok(_) = self.step() {}
-        (self.txcount1, self.txcount2)
+        self.report.clone()
+    }
+
+    /// Number of values the given program sent so far
+    fn sends_by(&self, program: usize) -> usize {
+        self.report.programs[program].sent
     }
 
+    /// Advance each core that is able to run (i.e. neither terminated nor
+    /// waiting for a value). Fails if no core was able to run.
     fn step(&mut self) -> Result<(), CoreError> {
-        let r1 = Self::step_core(&mut self.core1, &mut self.queue1, &mut self.queue2, &mut self.txcount1);
-        let r2 = Self::step_core(&mut self.core2, &mut self.queue2, &mut self.queue1, &mut self.txcount2);
-        match (r1, r2) {
-            (Err(CoreError::Deadlock), Err(CoreError::Deadlock)) => Err(CoreError::Deadlock),
-            (Err(CoreError::Deadlock), r) => r,
-            (r, Err(CoreError::Deadlock)) => r,
-            (Err(e), _) => Err(e),
-            (_, Err(e)) => Err(e),
-            (Ok(_), Ok(_)) => Ok(()),
-        }
-    }
-
-    fn step_core(core: &mut Core, rx: &mut VecDeque<i64>, tx: &mut VecDeque<i64>, count: &mut usize) -> Result<(), CoreError> {
-        match core.code.get(core.pc) {
-            Some(&Instruction::Snd(ref v)) => {
-                tx.push_back(v.get(&core.regs));
-                *count += 1;
-            },
-            Some(&Instruction::Rcv(r)) => {
-                match rx.pop_front() {
-                    Some(n) => core.regs.set(r, n),
-                    None => return Err(CoreError::Deadlock),
+        self.step_traced(&mut |_| ())
+    }
+
+    /// Same as `step`, but reports executed instructions of all programs
+    /// to the given sink
+    fn step_traced<F: FnMut(TraceEvent)>(&mut self, sink: &mut F) -> Result<(), CoreError> {
+        let results = self.step_cores(sink);
+        Self::combine(results)
+    }
+
+    /// Run until one of the programs reaches one of the given breakpoints
+    /// or no program is able to run anymore. Always executes at least one
+    /// step, so it can be called again to resume after a breakpoint.
+    #[allow(dead_code)]
+    fn run_until_pc(&mut self, pcs: &HashSet<usize>) -> StopReason {
+        loop {
+            let results = self.step_cores(&mut |_| ());
+            for (program, result) in results.iter().enumerate() {
+                if result.is_ok() && pcs.contains(&self.cores[program].pc) {
+                    return StopReason::Breakpoint { program: program, pc: self.cores[program].pc };
                 }
-            },
-            _ => (),
+            }
+            if let Err(e) = Self::combine(results) {
+                return StopReason::Stopped(e);
+            }
         }
-        let res = core.step();
-        res
+    }
+
+    /// Advance each core by one instruction, if possible
+    fn step_cores<F: FnMut(TraceEvent)>(&mut self, sink: &mut F) -> Vec<Result<Option<i64>, CoreError>> {
+        let mut results = Vec::with_capacity(self.cores.len());
+        for (program, core) in self.cores.iter_mut().enumerate() {
+            let mut io = QueueIo {
+                queues: &mut self.queues,
+                program: program,
+                topology: self.topology,
+                reports: &mut self.report.programs,
+            };
+            results.push(core.step_traced(&mut io, &mut |mut event: TraceEvent| { event.program = program; sink(event) }));
+        }
+        for (program, result) in results.iter().enumerate() {
+            self.report.programs[program].record(result);
+        }
+        results
+    }
+
+    /// Overall result of advancing all cores
+    fn combine(results: Vec<Result<Option<i64>, CoreError>>) -> Result<(), CoreError> {
+        if results.iter().any(|r| r.is_ok()) { return Ok(()); }
+        if results.contains(&Err(CoreError::Deadlock)) { return Err(CoreError::Deadlock); }
+        results.into_iter().next().unwrap_or(Err(CoreError::OutOfInstructions)).map(|_| ())
     }
 }
 
@@ -237,12 +1010,16 @@ fn main() {
     let mut core: Core = include_str!("day18.txt").parse().unwrap();
     println!("Value of recovered frequency: {}", core.run_until_recv().unwrap());
     let mut core: DualCore = include_str!("day18.txt").parse().unwrap();
-    println!("Number of values program 1 sent: {}", core.run().1);
+    core.run();
+    println!("Number of values program 1 sent: {}", core.sends_by(1));
 }
 
 
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "nightly")]
+    extern crate test;
+
     use super::*;
 
     #[test]
@@ -250,6 +1027,32 @@ mod tests {
         assert!(Core::from_str(include_str!("day18.txt")).is_ok());
     }
 
+    #[test]
+    fn registers() {
+        let mut regs = RegisterSet::new();
+        regs.set('a', 5);
+        regs.set('z', -1);
+        regs.set('p', 0);
+        assert_eq!(regs.get('a'), 5);
+        assert_eq!(regs.get('b'), 0);
+        assert_eq!(regs.get('z'), -1);
+        assert_eq!(format!("{:?}", regs), "{'a': 5, 'z': -1}");
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |line: usize, text: &str, reason: &str| Err(ParseError { line: line, text: text.to_string(), reason: reason.to_string() });
+        assert_eq!(Core::from_str("set a 1\nsnb a").map(|_| ()), error(2, "snb a", "unknown instruction 'snb'"));
+        assert_eq!(Core::from_str("set a b c").map(|_| ()), error(1, "set a b c", "trailing characters"));
+        assert_eq!(Core::from_str("snd 1\nadd a 1\nset 5 a").map(|_| ()), error(3, "set 5 a", "expected register, found '5'"));
+        assert_eq!(Core::from_str("sndx 1").map(|_| ()), error(1, "sndx 1", "unknown instruction 'sndx'"));
+        assert_eq!(Core::from_str("jgz a").map(|_| ()), error(1, "jgz a", "expected value, found end of line"));
+        assert_eq!(Core::from_str("add a 1x").map(|_| ()), error(1, "add a 1x", "expected value, found '1x'"));
+        assert_eq!(DualCore::from_str("snd 1\n\nrcv a").map(|_| ()), error(2, "", "missing instruction"));
+        assert_eq!(Core::from_str("snb a").unwrap_err().to_string(), "line 1: unknown instruction 'snb' ('snb a')");
+        assert!(Core::from_str("snd  -5\nset a\tb").is_ok());
+    }
+
     #[test]
     fn samples1() {
         let mut core = Core::from_str("set a 1\nadd a 2\nmul a a\nmod a 5\nsnd a\nset a 0\nrcv a\njgz a -1\nset a 1\njgz a -2").unwrap();
@@ -259,8 +1062,273 @@ mod tests {
     #[test]
     fn samples2() {
         let mut core = DualCore::from_str("snd 1\nsnd 2\nsnd p\nrcv a\nrcv b\nrcv c\nrcv d").unwrap();
-        assert_eq!(core.run(), (3, 3));
-        assert_eq!(core.core1.regs.get('c'), 1);
-        assert_eq!(core.core2.regs.get('c'), 0);
+        assert_eq!(core.run(), [3, 3]);
+        assert_eq!(core.cores[0].regs.get('c'), 1);
+        assert_eq!(core.cores[1].regs.get('c'), 0);
+    }
+
+    #[test]
+    fn sounds() {
+        let mut core = Core::from_str("set a 1\nadd a 2\nmul a a\nmod a 5\nsnd a\nset a 0\nrcv a\njgz a -1\nset a 1\njgz a -2").unwrap();
+        {
+            let mut sounds = core.sounds();
+            assert_eq!(sounds.next(), Some(4));
+            assert_eq!(sounds.next(), None);
+            assert!(sounds.recovered);
+        }
+        assert_eq!(core.pc, 7);
+        let mut core = Core::from_str("snd 1\nsnd 2\nset a 3\nsnd a").unwrap();
+        assert_eq!(core.sounds().collect::<Vec<_>>(), [1, 2, 3]);
+        let mut core = Core::from_str("snd 1\nsnd 2\nset a 3\nsnd a").unwrap();
+        assert_eq!(core.run_until_recv(), None);
+        let mut core = Core::from_str("set a 1\nsnd 5\nrcv a\nsnd 6").unwrap();
+        assert_eq!(core.sounds().collect::<Vec<_>>(), [5]);
+        let mut core = Core::from_str("set a 1\nsnd 5\nrcv a\nsnd 6").unwrap();
+        assert_eq!(core.all_sounds().collect::<Vec<_>>(), [5, 6]);
+    }
+
+    #[test]
+    fn tracing() {
+        let mut core = Core::from_str("set a 1\nadd a 2\nmul a a\nmod a 5\nsnd a\nset a 0\nrcv a\njgz a -1\nset a 1\njgz a -2").unwrap();
+        let mut io = SoundIo::default();
+        let mut events = vec![];
+        while let Ok(None) = core.step_traced(&mut io, &mut |event| events.push(event)) {}
+        let changes: Vec<_> = events.iter().filter_map(|event| event.change.clone()).map(|c| (c.register, c.old, c.new)).collect();
+        assert_eq!(changes, [('a', 0, 1), ('a', 1, 3), ('a', 3, 9), ('a', 9, 4), ('a', 4, 0), ('a', 0, 1)]);
+        let pcs: Vec<_> = events.iter().map(|event| event.pc).collect();
+        assert_eq!(pcs, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 7, 6]);
+        assert_eq!(events[2].to_string(), "[0]   2: mul a a (a: 3 -> 9)");
+        assert_eq!(events[9].to_string(), "[0]   9: jgz a -2");
+    }
+
+    #[test]
+    fn tracing_dual() {
+        let mut core = DualCore::from_str("snd 1\nsnd 2\nsnd p\nrcv a\nrcv b\nrcv c\nrcv d").unwrap();
+        let mut events = vec![];
+        while let Ok(_) = core.step_traced(&mut |event| events.push(event)) {}
+        assert_eq!(events.len(), 12);
+        assert_eq!(events.iter().filter(|event| event.program == 1).count(), 6);
+        assert_eq!(events[10].to_string(), "[0]   5: rcv c (c: 0 -> 1)");
+        assert_eq!(events[11].to_string(), "[1]   5: rcv c");
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut core = Core::from_str("set a 1\nadd a 2\nmul a a\nmod a 5\nsnd a\nset a 0\nrcv a\njgz a -1\nset a 1\njgz a -2").unwrap();
+        let mut io = SoundIo::default();
+        let pcs = [6].iter().cloned().collect();
+        assert_eq!(core.run_until_pc(&mut io, &pcs), StopReason::Breakpoint { program: 0, pc: 6 });
+        assert_eq!(core.regs.get('a'), 0);
+        assert_eq!(io.sound, Some(4));
+        assert_eq!(core.run_until_pc(&mut io, &pcs), StopReason::Breakpoint { program: 0, pc: 6 });
+        assert_eq!(core.regs.get('a'), 1);
+        assert_eq!(core.run_until_pc(&mut io, &pcs), StopReason::Recovered(4));
+        let mut core = DualCore::from_str("snd 1\nsnd 2\nsnd p\nrcv a\nrcv b\nrcv c\nrcv d").unwrap();
+        let pcs = [4].iter().cloned().collect();
+        assert_eq!(core.run_until_pc(&pcs), StopReason::Breakpoint { program: 0, pc: 4 });
+        assert_eq!(core.cores[0].regs.get('a'), 1);
+        assert_eq!(core.cores[1].pc, 4);
+        assert_eq!(core.run_until_pc(&pcs), StopReason::Stopped(CoreError::Deadlock));
+        assert_eq!(core.run(), [3, 3]);
+    }
+
+    #[test]
+    fn custom_io() {
+        struct RecordingIo {
+            sent: Vec<i64>,
+        }
+
+        impl Io for RecordingIo {
+            fn send(&mut self, value: i64) {
+                self.sent.push(value);
+            }
+
+            fn recv(&mut self) -> RecvResult {
+                RecvResult::WouldBlock
+            }
+        }
+
+        let mut core = Core::from_str("set a 3\nsnd a\nadd a -1\njgz a -2\nsnd p\nrcv b\nsnd 7").unwrap();
+        let mut io = RecordingIo { sent: vec![] };
+        while let Ok(_) = core.step(&mut io) {}
+        assert_eq!(io.sent, [3, 2, 1, 0]);
+        assert_eq!(core.pc, 5);
+    }
+
+    #[test]
+    fn jumping_out_of_bounds() {
+        let mut core = Core::from_str("jgz 1 -5\nsnd 1").unwrap();
+        assert_eq!(core.step(&mut SoundIo::default()), Err(CoreError::JumpedOutOfBounds { from: 0, target: -5 }));
+        assert_eq!(core.pc, 0);
+        assert_eq!(core.run_until_recv(), None);
+        let mut core = Core::from_str("set a 1\njgz a 2\nsnd a").unwrap();
+        assert_eq!(core.step(&mut SoundIo::default()), Ok(None));
+        assert_eq!(core.step(&mut SoundIo::default()), Err(CoreError::JumpedOutOfBounds { from: 1, target: 3 }));
+        let mut core = DualCore::from_str("jgz p -1\nsnd 1\nrcv a").unwrap();
+        assert_eq!(core.run(), [1, 0]);
+        assert_eq!(core.step(), Err(CoreError::Deadlock));
+        let mut core = DualCore::from_str("jgz 1 -1").unwrap();
+        assert_eq!(core.run(), [0, 0]);
+        assert_eq!(core.step(), Err(CoreError::JumpedOutOfBounds { from: 0, target: -1 }));
+        let mut core = Core::from_str("set a 1\njgz a 9223372036854775807").unwrap();
+        assert_eq!(core.step(&mut SoundIo::default()), Ok(None));
+        assert_eq!(core.step(&mut SoundIo::default()), Err(CoreError::JumpedOutOfBounds { from: 1, target: isize::MAX }));
+        assert_eq!(core.pc, 1);
+        let mut core = Core::from_str("set a -9223372036854775807\nadd a -1\njgz 1 a").unwrap()
+            .with_arithmetic(Arithmetic::Checked);
+        assert_eq!(core.step(&mut SoundIo::default()), Ok(None));
+        assert_eq!(core.step(&mut SoundIo::default()), Ok(None));
+        assert_eq!(core.regs.get('a'), i64::MIN);
+        assert_eq!(core.step(&mut SoundIo::default()), Err(CoreError::JumpedOutOfBounds { from: 2, target: isize::MIN + 2 }));
+    }
+
+    #[test]
+    fn reporting() {
+        let mut core = DualCore::from_str("snd 1\nsnd 2\nsnd p\nrcv a\nrcv b\nrcv c\nrcv d").unwrap();
+        let report = core.run_report();
+        for program in &report.programs {
+            assert_eq!(program, &ProgramReport { sent: 3, received: 3, steps: 6, max_queue_len: 3, stopped: Some(CoreError::Deadlock) });
+        }
+        assert_eq!(core.sends_by(0), 3);
+        assert_eq!(core.sends_by(1), 3);
+        let mut core = DualCore::from_str("jgz p 5\nrcv a\nadd b 1\nsnd a\njgz 1 -3\nsnd 1\nsnd 2\nsnd 3").unwrap();
+        let report = core.run_report();
+        assert_eq!(report.programs[0], ProgramReport { sent: 3, received: 3, steps: 13, max_queue_len: 2, stopped: Some(CoreError::Deadlock) });
+        assert_eq!(report.programs[1], ProgramReport { sent: 3, received: 0, steps: 4, max_queue_len: 3, stopped: Some(CoreError::OutOfInstructions) });
+        // Value sent and received within the same round
+        let mut core = DualCore::from_str("jgz p 2\nsnd 5\nrcv a").unwrap();
+        let report = core.run_report();
+        assert_eq!(report.programs.iter().map(|p| p.max_queue_len).collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(core.cores[1].regs.get('a'), 5);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let code = "set a 9223372036854775807\nmul a 2";
+        let mut core = Core::from_str(code).unwrap();
+        assert_eq!(core.run_until_pc(&mut SoundIo::default(), &HashSet::new()), StopReason::Stopped(CoreError::OutOfInstructions));
+        assert_eq!(core.regs.get('a'), -2);
+        let mut core = Core::from_str(code).unwrap().with_arithmetic(Arithmetic::Saturating);
+        core.run_until_recv();
+        assert_eq!(core.regs.get('a'), i64::MAX);
+        let mut core = Core::from_str(code).unwrap().with_arithmetic(Arithmetic::Checked);
+        assert_eq!(core.run_until_pc(&mut SoundIo::default(), &HashSet::new()), StopReason::Stopped(CoreError::Arithmetic { pc: 1, kind: ArithmeticError::Overflow }));
+        assert_eq!(core.regs.get('a'), i64::MAX);
+        for &arithmetic in &[Arithmetic::Wrapping, Arithmetic::Saturating, Arithmetic::Checked] {
+            let mut core = Core::from_str("set a 5\nmod a 0").unwrap().with_arithmetic(arithmetic);
+            assert_eq!(core.run_until_pc(&mut SoundIo::default(), &HashSet::new()), StopReason::Stopped(CoreError::Arithmetic { pc: 1, kind: ArithmeticError::DivisionByZero }));
+        }
+    }
+
+    #[test]
+    fn multiple_cores() {
+        let code = "snd p\nadd p 1\nrcv a\njgz a 2\nsnd a\nrcv b\njgz p -1";
+        let mut core = MultiCore::new(code, 3).unwrap();
+        assert_eq!(core.run(), [1, 2, 1]);
+        assert_eq!(core.cores.iter().map(|c| c.regs.get('a')).collect::<Vec<_>>(), [2, 0, 1]);
+        let mut core = MultiCore::with_topology(code, 3, Topology::Broadcast).unwrap();
+        assert_eq!(core.run(), [1, 2, 2]);
+        let report = core.run_report();
+        assert_eq!(report.programs.iter().map(|p| p.received).collect::<Vec<_>>(), [4, 3, 3]);
+    }
+
+    #[test]
+    fn threaded() {
+        let timeout = Duration::from_millis(100);
+        for code in &["snd 1\nsnd 2\nsnd p\nrcv a\nrcv b\nrcv c\nrcv d",
+                      "jgz p 5\nrcv a\nadd b 1\nsnd a\njgz 1 -3\nsnd 1\nsnd 2\nsnd 3"] {
+            let mut core = DualCore::from_str(code).unwrap();
+            assert_eq!(DualCore::from_str(code).unwrap().run_threaded(timeout), Ok(core.run()));
+        }
+        let code = "snd p\nadd p 1\nrcv a\njgz a 2\nsnd a\nrcv b\njgz p -1";
+        assert_eq!(MultiCore::new(code, 3).unwrap().run_threaded(timeout), Ok(vec![1, 2, 1]));
+        assert_eq!(DualCore::from_str("set a 1\nmod a 0").unwrap().run_threaded(timeout),
+                   Err(RunError::Failed { program: 0, error: CoreError::Arithmetic { pc: 1, kind: ArithmeticError::DivisionByZero } }));
+    }
+
+    #[test]
+    fn slow_peer() {
+        let timeout = Duration::from_millis(20);
+        let mut ios = ChannelIo::network(2, Topology::Ring, timeout);
+        let mut slow = ios.pop().unwrap();
+        let mut io = ios.pop().unwrap();
+        let thread = thread::spawn(move || {
+            thread::sleep(timeout * 5);
+            slow.send(42);
+            slow.recv()
+        });
+        assert_eq!(io.recv(), RecvResult::Value(42));
+        assert_eq!(io.recv(), RecvResult::WouldBlock);
+        // A deadlocked program terminates, which lets the other one detect the deadlock too
+        drop(io);
+        assert_eq!(thread.join().unwrap(), RecvResult::WouldBlock);
+    }
+
+    #[test]
+    fn snapshots() {
+        let code = "snd 1\nsnd 2\nsnd p\nrcv a\nrcv b\nrcv c\nrcv d";
+        let mut core = DualCore::from_str(code).unwrap();
+        for _ in 0..4 { core.step().unwrap(); }
+        let state = core.snapshot();
+        assert_eq!(state.programs[1], ProgramState { pc: 4, regs: vec![('a', 1), ('p', 1)], sent: 3, queue: vec![2, 0] });
+        let bytes = state.to_bytes();
+        assert_eq!(DuetState::from_bytes(&bytes), Ok(state));
+        assert_eq!(DuetState::from_bytes(&bytes[..bytes.len() - 1]), Err(StateError::Truncated));
+        assert_eq!(DuetState::from_bytes(&[2, 0, 0, 0, 0]), Err(StateError::Invalid));
+        assert_eq!(DuetState::from_bytes(&[0, 0, 0, 0, 0, 0]), Err(StateError::Invalid));
+        let mut core = DualCore::restore(code, DuetState::from_bytes(&bytes).unwrap()).unwrap();
+        assert_eq!(core.run(), [3, 3]);
+        assert_eq!(core.cores[0].regs.get('c'), 1);
+        assert_eq!(core.cores[1].regs.get('c'), 0);
+    }
+
+    #[test]
+    fn bounded() {
+        let code = "snd p\nrcv a\nsnd a\njgz 1 -2";
+        let mut core = DualCore::from_str(code).unwrap();
+        match core.run_bounded(100) {
+            BoundedOutcome::BudgetExhausted(report) => {
+                assert_eq!(report.programs.iter().map(|p| p.sent).collect::<Vec<_>>(), [34, 34]);
+                assert_eq!(report.programs.iter().map(|p| p.steps).collect::<Vec<_>>(), [100, 100]);
+            }
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        }
+        let mut calls = 0;
+        match DualCore::from_str(code).unwrap().run_watchdog(1000, 10, |report| { calls += 1; report.programs[0].sent < 10 }) {
+            BoundedOutcome::Cancelled(report) => assert_eq!(report.programs[0].steps, 30),
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        }
+        assert_eq!(calls, 3);
+        let mut core = DualCore::from_str("snd 1\nsnd 2\nsnd p\nrcv a\nrcv b\nrcv c\nrcv d").unwrap();
+        match core.run_bounded(1_000_000) {
+            BoundedOutcome::Completed(report) => assert_eq!(report.programs.iter().map(|p| p.sent).collect::<Vec<_>>(), [3, 3]),
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        }
+        let mut core = Core::from_str("set a 1\nadd a 2\nmul a a\nmod a 5\nsnd a\nset a 0\nrcv a\njgz a -1\nset a 1\njgz a -2").unwrap();
+        assert_eq!(core.run_until_recv_bounded(5), BoundedOutcome::BudgetExhausted(Some(4)));
+        let mut core = Core::from_str("set a 1\nadd a 2\nmul a a\nmod a 5\nsnd a\nset a 0\nrcv a\njgz a -1\nset a 1\njgz a -2").unwrap();
+        assert_eq!(core.run_until_recv_bounded(1000), BoundedOutcome::Completed(Some(4)));
+        let mut core = Core::from_str("snd 1\njgz 1 0").unwrap();
+        assert_eq!(core.run_until_recv_bounded(1000), BoundedOutcome::BudgetExhausted(Some(1)));
+    }
+
+    #[test]
+    fn one_core_terminates() {
+        let mut core = DualCore::from_str("jgz p 5\nrcv a\nadd b 1\nsnd a\njgz 1 -3\nsnd 1\nsnd 2\nsnd 3").unwrap();
+        assert_eq!(core.run(), [3, 3]);
+        assert_eq!(core.cores[0].regs.get('b'), 3);
+        assert_eq!(core.step(), Err(CoreError::Deadlock));
+        let mut core = DualCore::from_str("snd p\nrcv a").unwrap();
+        assert_eq!(core.run(), [1, 1]);
+        assert_eq!(core.step(), Err(CoreError::OutOfInstructions));
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_dual_core(b: &mut test::Bencher) {
+        b.iter(|| {
+            let mut core: DualCore = include_str!("day18.txt").parse().unwrap();
+            core.run()
+        })
     }
 }
@@ -1,41 +1,506 @@
+#![cfg_attr(feature = "nightly", feature(test))]
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead};
 use std::str::FromStr;
 
 
+/// A walkable field
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Field {
+    /// Straight path with its glyph
+    Path(char),
+    /// Corner where the path turns
+    Corner,
+    /// Path with a letter on it
+    Letter(char),
+}
+
+
+/// Cell value of an empty field
+const EMPTY: u8 = 0;
+
+/// Cell bit marking a waypoint
+const WAYPOINT: u8 = 0x80;
+
+
 /// The world. Consists of a two-dimensional landscape of fields with only some of them being walkable.
 #[derive(Debug)]
 struct World {
-    /// A two-dimensional landscape of fields in the world. A field may either exist (being walkable)
-    /// or not. If it exists, it may optionally contain a letter.
-    fields: Vec<Vec<Option<Option<char>>>>,
+    /// A two-dimensional landscape of fields in the world, stored row by row with one byte
+    /// per field (see `World::field_at`). Shorter lines are padded with empty fields.
+    cells: Vec<u8>,
+    /// Number of fields per row
+    width: usize,
+    /// Original length of every line
+    lines: Vec<usize>,
+}
+
+/// Classification of a character in a map
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Tile {
+    /// Path with a waypoint whose letter is collected when walking by
+    Waypoint(char),
+    /// Plain path
+    Path,
+    /// Not walkable
+    Empty,
+}
+
+impl Tile {
+    /// Default classification, with uppercase letters being waypoints
+    fn classify(ch: char) -> Tile {
+        match ch {
+            'A'...'Z' => Tile::Waypoint(ch),
+            ' '       => Tile::Empty,
+            _         => Tile::Path,
+        }
+    }
+}
+
+
+/// Error when reading a world
+#[allow(dead_code)]
+#[derive(Debug)]
+enum WorldParseError {
+    /// Reading failed
+    Io(io::Error),
+    /// Control character at the given line and column (1-based)
+    ControlCharacter { line: usize, col: usize },
+    /// Walkable field that isn't an ASCII character at the given line and column (1-based)
+    NonAscii { line: usize, col: usize },
+}
+
+impl fmt::Display for WorldParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WorldParseError::Io(ref err) => write!(f, "{}", err),
+            WorldParseError::ControlCharacter { line, col } => write!(f, "line {}, column {}: control character", line, col),
+            WorldParseError::NonAscii { line, col } => write!(f, "line {}, column {}: non-ASCII character", line, col),
+        }
+    }
+}
+
+impl From<io::Error> for WorldParseError {
+    fn from(err: io::Error) -> WorldParseError {
+        WorldParseError::Io(err)
+    }
 }
 
+
 impl FromStr for World {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(World {
-            fields: s.lines().map(|line|
-                line.chars().map(|ch| match ch {
-                    'A'...'Z' => Some(Some(ch)),
-                    ' '       => None,
-                    _         => Some(None),
-                }).collect()
-            ).collect(),
-        })
+        World::from_str_with(s, Tile::classify)
     }
 }
 
 impl World {
+    /// Parses a world using the given function to classify characters
+    /// Walkable fields must be ASCII characters.
+    fn from_str_with<F: Fn(char) -> Tile>(s: &str, classify: F) -> Result<Self, ()> {
+        let lines: Vec<usize> = s.lines().map(|line| line.chars().count()).collect();
+        let width = lines.iter().cloned().max().unwrap_or(0);
+        let mut cells = vec![EMPTY; width * lines.len()];
+        for (row, line) in s.lines().enumerate() {
+            for (col, ch) in line.chars().enumerate() {
+                cells[row * width + col] = match classify(ch) {
+                    Tile::Waypoint(ch) if ch.is_ascii() => ch as u8 | WAYPOINT,
+                    Tile::Path if ch.is_ascii() && ch != '\0' => ch as u8,
+                    Tile::Empty => EMPTY,
+                    _ => return Err(()),
+                };
+            }
+        }
+        Ok(World { cells: cells, width: width, lines: lines })
+    }
+
+    /// Reads a world from the given reader. Tabs are expanded to the given tab
+    /// width and all lines are padded to the same length.
+    #[allow(dead_code)]
+    fn from_reader<R: BufRead>(r: R, tab_width: usize) -> Result<Self, WorldParseError> {
+        let tab_width = cmp::max(tab_width, 1);
+        let mut lines = Vec::new();
+        for (i, line) in r.lines().enumerate() {
+            let line = try!(line);
+            let mut expanded = String::new();
+            for (j, ch) in line.chars().enumerate() {
+                match ch {
+                    '\t' => {
+                        for _ in expanded.len() % tab_width..tab_width {
+                            expanded.push(' ');
+                        }
+                    }
+                    _ if ch.is_control() => return Err(WorldParseError::ControlCharacter { line: i + 1, col: j + 1 }),
+                    _ if !ch.is_ascii() && Tile::classify(ch) != Tile::Empty => return Err(WorldParseError::NonAscii { line: i + 1, col: j + 1 }),
+                    _ => expanded.push(ch),
+                }
+            }
+            lines.push(expanded);
+        }
+        let width = lines.iter().map(|line| line.len()).max().unwrap_or(0);
+        let s: String = lines.into_iter().map(|line| format!("{:1$}\n", line, width)).collect();
+        Ok(World::from_str(&s).expect("Failed to parse expanded world"))
+    }
+
+    /// Returns the field at the given row and column. Cells are stored as the
+    /// glyph's ASCII code, with the high bit set for waypoints and 0 for empty fields.
+    fn field_at(&self, row: usize, col: usize) -> Option<Field> {
+        if row >= self.lines.len() || col >= self.width {
+            return None;
+        }
+        match self.cells[row * self.width + col] {
+            EMPTY => None,
+            b'+' => Some(Field::Corner),
+            b if b & WAYPOINT != 0 => Some(Field::Letter((b & !WAYPOINT) as char)),
+            b => Some(Field::Path(b as char)),
+        }
+    }
+
     /// Returns the field and its optional letter at the given row and column
     fn field(&self, row: usize, col: usize) -> Option<Option<char>> {
-        self.fields.get(row).and_then(|r| r.get(col)).and_then(|f| *f)
+        self.field_at(row, col).map(|f| match f {
+            Field::Letter(ch) => Some(ch),
+            _ => None,
+        })
+    }
+
+    /// Returns the character of the field at the given row and column
+    #[allow(dead_code)]
+    fn glyph(&self, row: usize, col: usize) -> Option<char> {
+        self.field_at(row, col).map(|f| match f {
+            Field::Path(ch) | Field::Letter(ch) => ch,
+            Field::Corner => '+',
+        })
+    }
+
+    /// Returns true if there's a corner at the given row and column
+    fn is_corner(&self, row: usize, col: usize) -> bool {
+        self.field_at(row, col) == Some(Field::Corner)
+    }
+
+    /// Returns true if there's a walkable field at the given row and column
+    fn is_walkable(&self, row: usize, col: usize) -> bool {
+        self.field_at(row, col).is_some()
+    }
+
+    /// Finds all entry points on the border of the world and the direction to
+    /// walk from there. A walkable field on the border is an entry point if it
+    /// crosses the border, i.e. isn't a corner and has no walkable neighbour along
+    /// the border.
+    fn entries(&self) -> Vec<(usize, usize, Direction)> {
+        let last_row = self.lines.iter().rposition(|&len| len > 0).unwrap_or(0);
+        let crosses_row = |row: usize, col: usize| {
+            !self.is_corner(row, col) && (col == 0 || !self.is_walkable(row, col - 1)) && !self.is_walkable(row, col + 1)
+        };
+        let crosses_col = |row: usize, col: usize| {
+            !self.is_corner(row, col) && (row == 0 || !self.is_walkable(row - 1, col)) && !self.is_walkable(row + 1, col)
+        };
+        let mut candidates: Vec<(usize, usize, Direction)> = Vec::new();
+        for &(row, dir) in &[(0, Direction::South), (last_row, Direction::North)] {
+            for col in 0..self.lines.get(row).cloned().unwrap_or(0) {
+                if self.is_walkable(row, col) && crosses_row(row, col) {
+                    candidates.push((row, col, dir));
+                }
+            }
+        }
+        for (row, &len) in self.lines.iter().enumerate() {
+            if len == 0 {
+                continue;
+            }
+            for &(col, dir) in &[(0, Direction::East), (len - 1, Direction::West)] {
+                if self.is_walkable(row, col) && crosses_col(row, col) {
+                    candidates.push((row, col, dir));
+                }
+            }
+        }
+        // Fields in corners of the world may be found on two borders
+        let mut entries: Vec<(usize, usize, Direction)> = Vec::new();
+        for candidate in candidates {
+            if !entries.iter().any(|&(row, col, _)| (row, col) == (candidate.0, candidate.1)) {
+                entries.push(candidate);
+            }
+        }
+        entries
+    }
+
+    /// Finds the single entry point on the border of the world and the direction
+    /// to walk from there. Returns None if there's no or more than one entry point.
+    fn start(&self) -> Option<(usize, usize, Direction)> {
+        let entries = self.entries();
+        match entries.len() {
+            1 => entries.first().cloned(),
+            _ => None,
+        }
+    }
+
+    /// Returns an iterator that can be used to walk the path, or None if there's
+    /// no single entry point
+    #[allow(dead_code)]
+    fn try_path(&self) -> Option<Path<'_>> {
+        self.start().map(|start| Path::new(self, start, false))
     }
 
     /// Returns an iterator that can be used to walk the path
-    fn path(&self) -> Path {
-        let start_col = self.fields[0].iter().position(Option::is_some).expect("Begin of path not found");
-        Path { world: self, row: 0, col: start_col, dir: Direction::South }
+    #[allow(dead_code)]
+    fn path(&self) -> Path<'_> {
+        self.try_path().expect("Begin of path not found")
+    }
+
+    /// Walks the whole path and returns the letters seen, the number of
+    /// steps needed (including the starting field) and why the walk ended
+    fn walk(&self) -> WalkResult {
+        self.walk_with(|_, _, _| ())
+    }
+
+    /// Walks the whole path like `walk`, calling the given function with the
+    /// position and the direction of arrival for every field visited
+    fn walk_with<F: FnMut(usize, usize, Direction)>(&self, f: F) -> WalkResult {
+        self.walk_from(self.start().expect("Begin of path not found"), f)
+    }
+
+    /// Walks the whole path starting at the given position and direction,
+    /// calling the given function for every field visited
+    fn walk_from<F: FnMut(usize, usize, Direction)>(&self, start: (usize, usize, Direction), mut f: F) -> WalkResult {
+        let mut path = Path::new(self, start, true);
+        let mut letters = String::new();
+        let mut steps = 0;
+        while let Some((row, col, ch)) = path.next() {
+            f(row, col, path.events.dir);
+            letters.extend(ch);
+            steps += 1;
+        }
+        WalkResult {
+            letters: letters,
+            steps: steps,
+            row: path.events.row,
+            col: path.events.col,
+            end: path.events.end.expect("Path ended without reason"),
+        }
+    }
+
+    /// Walks the whole path and checks that the glyphs of visited fields match
+    /// the way they're walked. Fields passed in a direction that doesn't match
+    /// their glyph are fine if they're crossings.
+    #[allow(dead_code)]
+    fn validate_walk(&self) -> Result<WalkResult, Vec<MapWarning>> {
+        let mut warnings = Vec::new();
+        let mut prev: Option<(usize, usize, Direction)> = None;
+        let result = self.walk_with(|row, col, dir| {
+            if let Some((prow, pcol, pdir)) = prev {
+                let glyph = self.glyph(prow, pcol).expect("Visited field not walkable");
+                let vertical = pdir == Direction::North || pdir == Direction::South;
+                let crossing = if vertical {
+                    pcol > 0 && self.is_walkable(prow, pcol - 1) && self.is_walkable(prow, pcol + 1)
+                } else {
+                    prow > 0 && self.is_walkable(prow - 1, pcol) && self.is_walkable(prow + 1, pcol)
+                };
+                let expected = match glyph {
+                    '+' if dir == pdir => Some(Expectation::Turn),
+                    '+' => None,
+                    _ if dir != pdir => Some(Expectation::Glyph('+')),
+                    '-' if vertical && !crossing => Some(Expectation::Glyph('|')),
+                    '|' if !vertical && !crossing => Some(Expectation::Glyph('-')),
+                    _ => None,
+                };
+                if let Some(expected) = expected {
+                    warnings.push(MapWarning { row: prow, col: pcol, found: glyph, expected: expected });
+                }
+            }
+            prev = Some((row, col, dir));
+        });
+        if warnings.is_empty() { Ok(result) } else { Err(warnings) }
+    }
+
+    /// Walks the path backwards, starting at the given end position and direction.
+    /// Since walking never prefers turning one way over the other (it stops if
+    /// both are possible), the same traversal is used for both directions.
+    #[allow(dead_code)]
+    fn walk_reverse(&self, end: (usize, usize), initial_dir: Direction) -> WalkResult {
+        self.walk_from((end.0, end.1, initial_dir), |_, _, _| ())
+    }
+
+    /// Walks the path forward and backward from its end and checks that both
+    /// walks end regularly and see the same fields and letters in reverse order
+    #[allow(dead_code)]
+    fn verify_roundtrip(&self) -> bool {
+        let mut dir = None;
+        let forward = self.walk_with(|_, _, d| dir = Some(d));
+        let dir = match dir {
+            Some(dir) if forward.end == WalkEnd::EndOfPath => dir,
+            _ => return false,
+        };
+        let backward = self.walk_reverse((forward.row, forward.col), dir.reverse());
+        backward.end == WalkEnd::EndOfPath &&
+            backward.steps == forward.steps &&
+            backward.letters.chars().eq(forward.letters.chars().rev())
+    }
+
+    /// Returns the positions of all fields visited when walking the path
+    #[allow(dead_code)]
+    fn visited(&self) -> Vec<(usize, usize)> {
+        let mut visited = Vec::new();
+        self.walk_with(|row, col, _| visited.push((row, col)));
+        visited
     }
+
+    /// Renders the map with the given visited fields replaced by a marker.
+    /// Letters are kept and lines keep their original lengths.
+    #[allow(dead_code)]
+    fn annotate(&self, path: &[(usize, usize)], marker: char) -> String {
+        let mut lines: Vec<Vec<char>> = self.lines.iter().enumerate().map(|(row, &len)|
+            (0..len).map(|col| self.glyph(row, col).unwrap_or(' ')).collect()
+        ).collect();
+        for &(row, col) in path {
+            if let Some(field) = self.field_at(row, col) {
+                if let Field::Letter(_) = field {
+                    continue;
+                }
+                lines[row][col] = marker;
+            }
+        }
+        lines.into_iter().map(|line| line.into_iter().collect::<String>() + "\n").collect()
+    }
+
+    /// Walks the path and renders the map with visited fields marked by `*`
+    #[allow(dead_code)]
+    fn walk_annotated(&self) -> String {
+        self.annotate(&self.visited(), '*')
+    }
+
+    /// Walks the paths from all entry points on the border. A path that ends
+    /// at another entry point is only walked once.
+    #[allow(dead_code)]
+    fn routes(&self) -> Vec<WalkResult> {
+        let mut routes: Vec<WalkResult> = Vec::new();
+        for start in self.entries() {
+            if routes.iter().any(|r| (r.row, r.col) == (start.0, start.1)) {
+                continue;
+            }
+            routes.push(self.walk_from(start, |_, _, _| ()));
+        }
+        routes
+    }
+
+    /// Returns the bounds of connected groups of walkable fields that can't be
+    /// reached from any entry point on the border
+    #[allow(dead_code)]
+    fn orphans(&self) -> Vec<Bounds> {
+        let entries = self.entries();
+        let mut seen = vec![false; self.cells.len()];
+        let mut orphans = Vec::new();
+        for row in 0..self.lines.len() {
+            for col in 0..self.width {
+                if seen[row * self.width + col] || !self.is_walkable(row, col) {
+                    continue;
+                }
+                let mut bounds = Bounds { top: row, left: col, bottom: row, right: col };
+                let mut reachable = false;
+                let mut todo = vec![(row, col)];
+                seen[row * self.width + col] = true;
+                while let Some((r, c)) = todo.pop() {
+                    reachable |= entries.iter().any(|&(er, ec, _)| (er, ec) == (r, c));
+                    bounds.top = cmp::min(bounds.top, r);
+                    bounds.left = cmp::min(bounds.left, c);
+                    bounds.bottom = cmp::max(bounds.bottom, r);
+                    bounds.right = cmp::max(bounds.right, c);
+                    let mut neighbours = vec![(r + 1, c), (r, c + 1)];
+                    if r > 0 { neighbours.push((r - 1, c)); }
+                    if c > 0 { neighbours.push((r, c - 1)); }
+                    for (nr, nc) in neighbours {
+                        if self.is_walkable(nr, nc) && !seen[nr * self.width + nc] {
+                            seen[nr * self.width + nc] = true;
+                            todo.push((nr, nc));
+                        }
+                    }
+                }
+                if !reachable {
+                    orphans.push(bounds);
+                }
+            }
+        }
+        orphans
+    }
+
+    /// Returns an iterator over the events when walking the whole path
+    #[allow(dead_code)]
+    fn events(&self) -> impl Iterator<Item = WalkEvent> + '_ {
+        Events::new(self, self.start().expect("Begin of path not found"), true)
+    }
+
+    /// Number of steps needed to walk the path, including the starting field
+    fn steps(&self) -> usize {
+        Path::new(self, self.start().expect("Begin of path not found"), true).count()
+    }
+
+    /// Letters seen when walking the path
+    fn letters(&self) -> String {
+        self.walk().letters
+    }
+}
+
+
+/// Bounding box of a group of fields (inclusive)
+#[allow(dead_code)]
+#[derive(Debug, PartialEq)]
+struct Bounds {
+    top: usize,
+    left: usize,
+    bottom: usize,
+    right: usize,
+}
+
+
+/// Reason for a walk to end
+#[derive(Debug, PartialEq, Clone)]
+enum WalkEnd {
+    /// Reached the end of the path
+    EndOfPath,
+    /// Path continues both to the left and to the right at the given field
+    Ambiguous { row: usize, col: usize },
+    /// Path doesn't continue at the given corner
+    DeadEnd { row: usize, col: usize },
+}
+
+
+/// What a map validation expected to find
+#[allow(dead_code)]
+#[derive(Debug, PartialEq)]
+enum Expectation {
+    /// A field with the given glyph
+    Glyph(char),
+    /// A turn at a corner
+    Turn,
+}
+
+
+/// Warning about a field that doesn't match the way it's walked
+#[allow(dead_code)]
+#[derive(Debug, PartialEq)]
+struct MapWarning {
+    row: usize,
+    col: usize,
+    /// Glyph found at the field
+    found: char,
+    expected: Expectation,
+}
+
+
+/// Result of walking a whole path
+#[derive(Debug, PartialEq)]
+struct WalkResult {
+    /// Letters seen on the path
+    letters: String,
+    /// Number of steps needed, including the starting field
+    steps: usize,
+    /// Final row
+    row: usize,
+    /// Final column
+    col: usize,
+    end: WalkEnd,
 }
 
 
@@ -65,40 +530,136 @@ impl Direction {
             Direction::West  => Direction::North,
         }
     }
+
+    /// Returns the opposite direction
+    #[allow(dead_code)]
+    fn reverse(&self) -> Direction {
+        self.turn_left().turn_left()
+    }
+}
+
+
+/// Event while walking through the world
+#[derive(Debug, PartialEq)]
+enum WalkEvent {
+    /// Entered the given field
+    Enter { row: usize, col: usize },
+    /// Changed direction at the given field
+    Turn { at: (usize, usize), from: Direction, to: Direction },
+    /// Saw a letter at the given field
+    Letter { at: (usize, usize), ch: char },
+    /// Walk ended at the given field
+    End { at: (usize, usize), reason: WalkEnd },
 }
 
 
-/// Path iterator for walking through the world
+/// Event iterator for walking through the world. Ends if the path ends or is
+/// ambiguous (i.e. can't go straight, but can turn both left and right).
 #[derive(Debug)]
-struct Path<'a> {
+struct Events<'a> {
     world: &'a World,
     row: usize,
     col: usize,
     dir: Direction,
+    /// Reason for the path to end, once it ended
+    end: Option<WalkEnd>,
+    /// Whether the starting field still needs to be entered
+    pending_start: bool,
+    /// Events to be yielded before walking on
+    queue: VecDeque<WalkEvent>,
 }
 
-impl<'a> Iterator for Path<'a> {
-    type Item = (usize, usize, Option<char>);
+impl<'a> Events<'a> {
+    /// Creates an event iterator that starts walking at the given position and
+    /// direction. If `include_start` is set, the starting field is entered first.
+    fn new(world: &'a World, start: (usize, usize, Direction), include_start: bool) -> Events<'a> {
+        let (row, col, dir) = start;
+        Events { world: world, row: row, col: col, dir: dir, end: None, pending_start: include_start, queue: VecDeque::new() }
+    }
+
+    /// Queues events for entering the current field
+    fn enter(&mut self) {
+        self.queue.push_back(WalkEvent::Enter { row: self.row, col: self.col });
+        if let Some(Some(ch)) = self.world.field(self.row, self.col) {
+            self.queue.push_back(WalkEvent::Letter { at: (self.row, self.col), ch: ch });
+        }
+    }
+
+    /// Queues the end event with the given reason
+    fn finish(&mut self, reason: WalkEnd) {
+        self.queue.push_back(WalkEvent::End { at: (self.row, self.col), reason: reason.clone() });
+        self.end = Some(reason);
+    }
+}
+
+impl<'a> Iterator for Events<'a> {
+    type Item = WalkEvent;
 
     fn next(&mut self) -> Option<Self::Item> {
-        fn try_field(world: &World, row: usize, col: usize) -> Option<(usize, usize, Option<char>)> {
-            world.field(row, col).map(|f| (row, col, f))
+        fn try_walk(world: &World, row: usize, col: usize, dir: Direction) -> Option<(usize, usize)> {
+            let (row, col) = match dir {
+                Direction::North if row > 0 => (row - 1, col    ),
+                Direction::East             => (row,     col + 1),
+                Direction::South            => (row + 1, col    ),
+                Direction::West  if col > 0 => (row,     col - 1),
+                _                            => return None,
+            };
+            if world.is_walkable(row, col) { Some((row, col)) } else { None }
         }
-        fn try_walk(world: &World, row: usize, col: usize, dir: Direction) -> Option<(usize, usize, Option<char>)> {
-            match dir {
-                Direction::North if row > 0 => try_field(world, row - 1, col    ),
-                Direction::East             => try_field(world, row,     col + 1),
-                Direction::South            => try_field(world, row + 1, col    ),
-                Direction::West  if col > 0 => try_field(world, row,     col - 1),
-                _                            => None,
-            }
+        if let Some(event) = self.queue.pop_front() {
+            return Some(event);
+        }
+        if self.end.is_some() {
+            return None;
         }
-        for &dir in &[self.dir, self.dir.turn_left(), self.dir.turn_right()] {
-            if let Some((row, col, ch)) = try_walk(&self.world, self.row, self.col, dir) {
+        if self.pending_start {
+            self.pending_start = false;
+            self.enter();
+            return self.queue.pop_front();
+        }
+        let straight = try_walk(self.world, self.row, self.col, self.dir).map(|f| (f, self.dir));
+        let left = try_walk(self.world, self.row, self.col, self.dir.turn_left()).map(|f| (f, self.dir.turn_left()));
+        let right = try_walk(self.world, self.row, self.col, self.dir.turn_right()).map(|f| (f, self.dir.turn_right()));
+        match (straight, left, right) {
+            (Some(next), _, _) | (None, Some(next), None) | (None, None, Some(next)) => {
+                let ((row, col), dir) = next;
+                if dir != self.dir {
+                    self.queue.push_back(WalkEvent::Turn { at: (self.row, self.col), from: self.dir, to: dir });
+                }
                 self.row = row;
                 self.col = col;
                 self.dir = dir;
-                return Some((row, col, ch));
+                self.enter();
+            }
+            (None, Some(_), Some(_)) => {
+                let (row, col) = (self.row, self.col);
+                self.finish(WalkEnd::Ambiguous { row: row, col: col });
+            }
+            (None, None, None) if self.world.is_corner(self.row, self.col) => {
+                let (row, col) = (self.row, self.col);
+                self.finish(WalkEnd::DeadEnd { row: row, col: col });
+            }
+            (None, None, None) => self.finish(WalkEnd::EndOfPath),
+        }
+        self.queue.pop_front()
+    }
+}
+
+
+/// Path iterator for walking through the world, yielding every field entered
+#[derive(Debug)]
+struct Path<'a> {
+    events: Events<'a>,
+}
+
+impl<'a> Iterator for Path<'a> {
+    type Item = (usize, usize, Option<char>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let world = self.events.world;
+        for event in self.events.by_ref() {
+            if let WalkEvent::Enter { row, col } = event {
+                return Some((row, col, world.field(row, col).and_then(|f| f)));
             }
         }
         None
@@ -106,50 +667,257 @@ impl<'a> Iterator for Path<'a> {
 }
 
 impl<'a> Path<'a> {
+    /// Creates a path iterator that starts walking at the given position and
+    /// direction. If `include_start` is set, the starting field is yielded first.
+    fn new(world: &'a World, start: (usize, usize, Direction), include_start: bool) -> Path<'a> {
+        Path { events: Events::new(world, start, include_start) }
+    }
+
     /// Consumes the path iterator and returns a letter iterator that yields
     /// the letters on the path
+    #[allow(dead_code)]
     fn letters(self) -> Letters<'a> {
-        Letters { path: self }
+        Letters { events: self.events }
     }
 }
 
 
 /// Letter iterator for collecting letters on a walked path
+#[allow(dead_code)]
 #[derive(Debug)]
 struct Letters<'a> {
-    path: Path<'a>,
+    events: Events<'a>,
 }
 
 impl<'a> Iterator for Letters<'a> {
     type Item = char;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.path.next() {
-            Some((_, _, Some(ch))) => Some(ch),
-            Some(_) => self.next(),
-            None => None,
+        for event in self.events.by_ref() {
+            if let WalkEvent::Letter { ch, .. } = event {
+                return Some(ch);
+            }
         }
+        None
     }
 }
 
 
 fn main() {
     let world: World = include_str!("day19.txt").parse().unwrap();
-    println!("Letters seen on path: {}", world.path().letters().collect::<String>());
-    println!("Steps needed to go: {}", world.path().count() + 1);
+    println!("Letters seen on path: {}", world.letters());
+    println!("Steps needed to go: {}", world.steps());
 }
 
 
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "nightly")]
+    extern crate test;
+
     use super::*;
 
     const INPUT: &str = "     |          \n     |  +--+    \n     A  |  C    \n F---|----E|--+ \n     |  |  |  D \n     +B-+  +--+ \n\n";
 
+    /// Generates a square map with a path spiraling inwards from the top left corner.
+    /// The size needs to be odd for the path to end in the center.
+    fn spiral(size: usize) -> String {
+        let mut map = vec![vec![' '; size]; size];
+        let mut lengths = vec![size - 1; 3];
+        let mut len = size - 1;
+        while len > 2 {
+            len -= 2;
+            lengths.push(len);
+            lengths.push(len);
+        }
+        let (mut row, mut col) = (0, 0);
+        let mut dir = Direction::South;
+        map[0][0] = '|';
+        for len in lengths {
+            let glyph = if dir == Direction::North || dir == Direction::South { '|' } else { '-' };
+            for _ in 0..len {
+                match dir {
+                    Direction::North => row -= 1,
+                    Direction::East  => col += 1,
+                    Direction::South => row += 1,
+                    Direction::West  => col -= 1,
+                }
+                map[row][col] = glyph;
+            }
+            map[row][col] = '+';
+            dir = dir.turn_left();
+        }
+        map[row][col] = 'X';
+        map.into_iter().map(|line| line.into_iter().collect::<String>() + "\n").collect()
+    }
+
     #[test]
     fn samples() {
         let world = World::from_str(INPUT).unwrap();
         assert_eq!(world.path().letters().collect::<String>(), "ABCDEF");
         assert_eq!(world.path().count() + 1, 38);
+        assert_eq!(world.letters(), "ABCDEF");
+        assert_eq!(world.steps(), 38);
+    }
+
+    #[test]
+    fn single_field() {
+        let world = World::from_str("|\n").unwrap();
+        assert_eq!(world.steps(), 1);
+        assert_eq!(world.letters(), "");
+        assert_eq!(world.walk().end, WalkEnd::EndOfPath);
+    }
+
+    #[test]
+    fn walking() {
+        let world = World::from_str(INPUT).unwrap();
+        assert_eq!(world.walk(), WalkResult { letters: "ABCDEF".to_string(), steps: 38, row: 3, col: 1, end: WalkEnd::EndOfPath });
+        let world = World::from_str("   |   \n   A   \n --+-- \n").unwrap();
+        assert_eq!(world.walk(), WalkResult { letters: "A".to_string(), steps: 3, row: 2, col: 3, end: WalkEnd::Ambiguous { row: 2, col: 3 } });
+        let world = World::from_str("  |  \n  |  \n -B- \n").unwrap();
+        assert_eq!(world.walk().end, WalkEnd::Ambiguous { row: 2, col: 2 });
+        let world = World::from_str(" | \n + \n").unwrap();
+        assert_eq!(world.walk(), WalkResult { letters: "".to_string(), steps: 2, row: 1, col: 1, end: WalkEnd::DeadEnd { row: 1, col: 1 } });
+    }
+
+    #[test]
+    fn starting() {
+        let world = World::from_str(INPUT).unwrap();
+        assert_eq!(world.start(), Some((0, 5, Direction::South)));
+        // Rotating by 180 degrees moves the entry to the bottom, but doesn't change the route
+        let rotated: String = INPUT.lines().filter(|l| !l.is_empty()).rev().map(|l| l.chars().rev().collect::<String>() + "\n").collect();
+        let world = World::from_str(&rotated).unwrap();
+        assert_eq!(world.start(), Some((5, 10, Direction::North)));
+        assert_eq!(world.walk().letters, "ABCDEF");
+        // Entering from the left at F walks the route backwards
+        let world = World::from_str("        +--+    \n     A  |  C    \n-F---|----E|--+ \n     |  |  |  D \n     +B-+  +--+ \n").unwrap();
+        assert_eq!(world.start(), Some((2, 0, Direction::East)));
+        assert_eq!(world.walk().letters, "FEDCBA");
+        let world = World::from_str(" |  | \n |  | \n").unwrap();
+        assert_eq!(world.start(), None);
+        assert!(world.try_path().is_none());
+    }
+
+    #[test]
+    fn waypoints() {
+        const INPUT: &str = " |    \n a--7 \n    | \n    C \n      \n";
+        let world = World::from_str_with(INPUT, |ch| match ch {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => Tile::Waypoint(ch),
+            ' ' => Tile::Empty,
+            _ => Tile::Path,
+        }).unwrap();
+        assert_eq!(world.letters(), "a7C");
+        assert_eq!(world.path().letters().collect::<String>(), "a7C");
+        let world = World::from_str(INPUT).unwrap();
+        assert_eq!(world.letters(), "C");
+        assert_eq!(world.steps(), 7);
+    }
+
+    #[test]
+    fn validating() {
+        let world = World::from_str(INPUT).unwrap();
+        assert_eq!(world.validate_walk(), Ok(world.walk()));
+        let world = World::from_str(&INPUT.replacen("+--+ ", "---+ ", 1)).unwrap();
+        assert_eq!(world.validate_walk(), Err(vec![MapWarning { row: 1, col: 8, found: '-', expected: Expectation::Glyph('+') }]));
+        let world = World::from_str(&INPUT.replacen("+B-+", "+B--", 1)).unwrap();
+        assert_eq!(world.validate_walk(), Err(vec![MapWarning { row: 5, col: 8, found: '-', expected: Expectation::Glyph('+') }]));
+        let world = World::from_str(&INPUT.replacen("+--+ ", "++-+ ", 1)).unwrap();
+        assert_eq!(world.validate_walk(), Err(vec![MapWarning { row: 1, col: 9, found: '+', expected: Expectation::Turn }]));
+        let world = World::from_str(&INPUT.replacen("F---|", "F-|-|", 1)).unwrap();
+        assert_eq!(world.validate_walk(), Err(vec![MapWarning { row: 3, col: 3, found: '|', expected: Expectation::Glyph('-') }]));
+    }
+
+    #[test]
+    fn annotating() {
+        let world = World::from_str(INPUT).unwrap();
+        let annotated = world.walk_annotated();
+        assert_eq!(annotated, "     *          \n     *  ****    \n     A  *  C    \n F********E**** \n     *  *  *  D \n     *B**  **** \n\n");
+        // 38 steps, but three crossings are visited twice
+        assert_eq!(world.visited().len(), 38);
+        assert_eq!(annotated.chars().filter(|&ch| ch == '*' || ch.is_alphabetic()).count(), 35);
+        let world = World::from_str(&annotated).unwrap();
+        assert_eq!(world.letters(), "ABCDEF");
+        let world = World::from_str(INPUT).unwrap();
+        assert_eq!(world.annotate(&[(0, 5), (2, 5), (3, 7)], '#'), INPUT.replacen("|", "#", 1).replacen("----", "-#--", 1));
+    }
+
+    #[test]
+    fn storage() {
+        let world = World::from_str("  |\n  A  \n\n").unwrap();
+        assert_eq!(world.width, 5);
+        assert_eq!(std::mem::size_of_val(&world.cells[..]), 3 * 5);
+        assert_eq!(world.field(0, 4), None);
+        assert_eq!(world.field(1, 2), Some(Some('A')));
+        assert_eq!(world.field(3, 0), None);
+        assert!(World::from_str_with("|\n", |_| Tile::Waypoint('\u{e4}')).is_err());
+    }
+
+    #[test]
+    fn spiraling() {
+        let world = World::from_str(&spiral(7)).unwrap();
+        assert_eq!(world.walk(), WalkResult { letters: "X".to_string(), steps: 31, row: 2, col: 4, end: WalkEnd::EndOfPath });
+        let world = World::from_str(&spiral(999)).unwrap();
+        assert_eq!(std::mem::size_of_val(&world.cells[..]), 999 * 999);
+        assert_eq!(world.validate_walk().map(|w| w.steps), Ok(3 * 998 + 2 * 498 * 499 + 1));
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_walk_spiral(b: &mut test::Bencher) {
+        let world = World::from_str(&spiral(1999)).unwrap();
+        b.iter(|| {
+            world.walk()
+        })
+    }
+
+    #[test]
+    fn events() {
+        let world = World::from_str(INPUT).unwrap();
+        let events: Vec<WalkEvent> = world.events().collect();
+        assert_eq!(events.iter().filter(|e| matches!(e, WalkEvent::Enter { .. })).count(), 38);
+        assert_eq!(events.iter().filter(|e| matches!(e, WalkEvent::Turn { .. })).count(), 7);
+        assert_eq!(events.iter().filter_map(|e| match *e { WalkEvent::Letter { ch, .. } => Some(ch), _ => None }).collect::<String>(), "ABCDEF");
+        assert_eq!(events[0], WalkEvent::Enter { row: 0, col: 5 });
+        assert!(events.contains(&WalkEvent::Turn { at: (5, 5), from: Direction::South, to: Direction::East }));
+        assert_eq!(events.last(), Some(&WalkEvent::End { at: (3, 1), reason: WalkEnd::EndOfPath }));
+    }
+
+    #[test]
+    fn routes() {
+        let sheet = INPUT.to_string() + "-G-H      \n          \n     ++   \n          \n";
+        let world = World::from_str(&sheet).unwrap();
+        assert_eq!(world.start(), None);
+        let letters: Vec<String> = world.routes().into_iter().map(|r| r.letters).collect();
+        assert_eq!(letters, vec!["ABCDEF", "GH"]);
+        assert_eq!(world.orphans(), vec![Bounds { top: 9, left: 5, bottom: 9, right: 6 }]);
+        let world = World::from_str(" |   \n +-+ \n   | \n").unwrap();
+        assert_eq!(world.routes().len(), 1);
+        assert_eq!(World::from_str(INPUT).unwrap().orphans(), vec![]);
+    }
+
+    #[test]
+    fn reversing() {
+        let world = World::from_str(INPUT).unwrap();
+        let backward = world.walk_reverse((3, 1), Direction::East);
+        assert_eq!(backward, WalkResult { letters: "FEDCBA".to_string(), steps: 38, row: 0, col: 5, end: WalkEnd::EndOfPath });
+        assert!(world.verify_roundtrip());
+        let world = World::from_str(&INPUT.replacen("F---|", "+---|", 1)).unwrap();
+        assert!(!world.verify_roundtrip());
+        let world = World::from_str("   |   \n   A   \n --+-- \n").unwrap();
+        assert!(!world.verify_roundtrip());
+    }
+
+    #[test]
+    fn reading() {
+        let tabbed: String = INPUT.lines().map(|l| l.replacen("    ", "\t", 1).trim_end().to_string() + "\n").collect();
+        assert!(tabbed.contains('\t'));
+        let world = World::from_reader(tabbed.as_bytes(), 4).unwrap();
+        assert_eq!(world.lines, vec![15; 7]);
+        assert_eq!(world.walk(), World::from_str(INPUT).unwrap().walk());
+        match World::from_reader("  |\n  \u{7} \n".as_bytes(), 4) {
+            Err(WorldParseError::ControlCharacter { line: 2, col: 3 }) => (),
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert_eq!(World::from_reader("  |\n \u{e4}\n".as_bytes(), 4).unwrap_err().to_string(), "line 2, column 2: non-ASCII character");
     }
 }
@@ -1,44 +1,64 @@
+#![cfg_attr(feature = "nightly", feature(test))]
+
 #[macro_use]
 extern crate nom;
 
-use std::collections::HashSet;
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::iter;
+use std::ops::Range;
+use std::io::{self, Write};
 use std::str::FromStr;
+use std::thread;
 use nom::{space, digit};
 
 
 /// A particle in space
 #[derive(Debug, PartialEq, Clone)]
 struct Particle {
-    pos: (i32, i32, i32),
-    vel: (i32, i32, i32),
-    acc: (i32, i32, i32),
+    pos: (i64, i64, i64),
+    vel: (i64, i64, i64),
+    acc: (i64, i64, i64),
 }
 
 impl FromStr for Particle {
     type Err = nom::ErrorKind;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        named!(integer<&str, u32>, map_res!(digit, str::parse));
-        named!(number<&str, i32>, alt!(
-            preceded!(tag!("-"), integer) => { |n| -(n as i32) } |
-                                 integer  => { |n|   n as i32  }
+        named!(integer<&str, i64>, map_res!(digit, str::parse));
+        named!(number<&str, i64>, alt!(
+            preceded!(tag!("-"), integer) => { |n: i64| -n } |
+            preceded!(tag!("+"), integer) |
+                                 integer
         ));
-        named!(triple<&str, (i32, i32, i32)>, do_parse!(
-            tag!("<") >> a: number >> tag!(",") >> b: number >> tag!(",") >> c: number >> tag!(">") >> ((a, b, c))
+        named!(component<&str, i64>, delimited!(opt!(space), number, opt!(space)));
+        named!(triple<&str, (i64, i64, i64)>, do_parse!(
+            tag!("<") >> a: component >> tag!(",") >> b: component >> tag!(",") >> c: component >> tag!(">") >> ((a, b, c))
         ));
         complete!(s, do_parse!(
             tag!("p=") >> p: triple >>
-            tag!(",") >> space >>
+            tag!(",") >> opt!(space) >>
             tag!("v=") >> v: triple >>
-            tag!(",") >> space >>
+            tag!(",") >> opt!(space) >>
             tag!("a=") >> a: triple >>
             (Particle { pos: p, vel: v, acc: a })
         )).to_result()
     }
 }
 
+impl fmt::Display for Particle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "p=<{},{},{}>, v=<{},{},{}>, a=<{},{},{}>",
+            self.pos.0, self.pos.1, self.pos.2,
+            self.vel.0, self.vel.1, self.vel.2,
+            self.acc.0, self.acc.1, self.acc.2)
+    }
+}
+
 impl Particle {
-    /// Returns a new particle that advanced t ticks in time
+    /// Returns a new particle that advanced t ticks in time by simulating every tick
+    #[allow(dead_code)]
     fn tick(&self, t: usize) -> Particle {
         let mut pos = self.pos;
         let mut vel = self.vel;
@@ -49,22 +69,183 @@ impl Particle {
         Particle { pos: pos, vel: vel, acc: self.acc }
     }
 
+    /// Position after t ticks. Since velocity is updated before position in every
+    /// tick, the acceleration adds up to t * (t + 1) / 2 times its value.
+    /// Positions beyond the range of i64 saturate.
+    fn position_at(&self, t: u64) -> (i64, i64, i64) {
+        let f = (t as i128).checked_mul(t as i128 + 1).map(|x| x / 2);
+        let axis = |p: i64, v: i64, a: i64| {
+            let x = if a == 0 { Some(0) } else { f.and_then(|f| f.checked_mul(a as i128)) }
+                .and_then(|x| x.checked_add(v as i128 * t as i128))
+                .and_then(|x| x.checked_add(p as i128));
+            saturate(x, if a != 0 { a } else { v })
+        };
+        (axis(self.pos.0, self.vel.0, self.acc.0), axis(self.pos.1, self.vel.1, self.acc.1), axis(self.pos.2, self.vel.2, self.acc.2))
+    }
+
+    /// Velocity after t ticks. Velocities beyond the range of i64 saturate.
+    fn velocity_at(&self, t: u64) -> (i64, i64, i64) {
+        let axis = |v: i64, a: i64| saturate((a as i128 * t as i128).checked_add(v as i128), a);
+        (axis(self.vel.0, self.acc.0), axis(self.vel.1, self.acc.1), axis(self.vel.2, self.acc.2))
+    }
+
+    /// Coefficients (a, b, c) of twice the position on every axis as a polynomial
+    /// a * t² + b * t + c of the tick t
+    fn polynomials(&self) -> [(i64, i64, i64); 3] {
+        let axis = |p: i64, v: i64, a: i64| (a, 2 * v + a, 2 * p);
+        [
+            axis(self.pos.0, self.vel.0, self.acc.0),
+            axis(self.pos.1, self.vel.1, self.acc.1),
+            axis(self.pos.2, self.vel.2, self.acc.2),
+        ]
+    }
+
+    /// Coefficients (a, b, c) of twice the distance to origin as a polynomial
+    /// a * t² + b * t + c of the tick t, once no axis changes its sign anymore.
+    /// The eventual sign of every axis is the sign of its acceleration, or of
+    /// its velocity if there's no acceleration, or of its position if neither.
+    /// So a is the Manhattan length of acceleration, b is essentially velocity
+    /// projected onto that sign vector and c the position projected onto it.
+    fn long_term_distance(&self) -> (i64, i64, i64) {
+        let mut distance = (0, 0, 0);
+        for &(a, b, c) in &self.polynomials() {
+            let sign = [a, b, c].iter().cloned().find(|&x| x != 0).unwrap_or(0).signum();
+            distance = (distance.0 + sign * a, distance.1 + sign * b, distance.2 + sign * c);
+        }
+        distance
+    }
+
+    /// Earliest tick at which both particles are at the same position. None if they never meet.
+    fn collision_time(&self, other: &Particle) -> Option<u64> {
+        let (p, q) = (self.polynomials(), other.polynomials());
+        let mut times: Option<Vec<u64>> = None;
+        for k in 0..3 {
+            let (a, b, c) = (p[k].0 - q[k].0, p[k].1 - q[k].1, p[k].2 - q[k].2);
+            if (a, b, c) == (0, 0, 0) {
+                continue;
+            }
+            let roots = integer_roots(a, b, c);
+            times = Some(match times {
+                None => roots,
+                Some(times) => times.into_iter().filter(|t| roots.contains(t)).collect(),
+            });
+        }
+        match times {
+            None => Some(0),
+            Some(times) => times.into_iter().min(),
+        }
+    }
+
     /// Manhattan distance to origin
-    fn distance(&self) -> i32 {
+    #[allow(dead_code)]
+    fn distance(&self) -> i64 {
         self.pos.0.abs() + self.pos.1.abs() + self.pos.2.abs()
     }
 }
 
 
+/// Converts to i64, saturating values out of range or unknown due to overflow
+/// (None) in the direction given by the sign of `dir`
+fn saturate(x: Option<i128>, dir: i64) -> i64 {
+    match x {
+        Some(x) if x > i64::MAX as i128 => i64::MAX,
+        Some(x) if x < i64::MIN as i128 => i64::MIN,
+        Some(x) => x as i64,
+        None if dir < 0 => i64::MIN,
+        None => i64::MAX,
+    }
+}
+
+/// Integer square root (rounded down) of a non-negative number
+fn isqrt(n: i128) -> i128 {
+    if n < 2 {
+        return n;
+    }
+    // Newton's method, starting above the root and decreasing monotonically
+    let mut x = n;
+    let mut y = (x + 1) / 2;
+    while y < x {
+        x = y;
+        y = (x + n / x) / 2;
+    }
+    x
+}
+
+/// Non-negative integer roots of a * t² + b * t + c
+fn integer_roots(a: i64, b: i64, c: i64) -> Vec<u64> {
+    if a == 0 {
+        if b != 0 && c % b == 0 && -c / b >= 0 { vec![(-c / b) as u64] } else { vec![] }
+    } else {
+        // Coefficients of i32 range components overflow i64 when squared
+        let (a, b, c) = (a as i128, b as i128, c as i128);
+        let d = b * b - 4 * a * c;
+        if d < 0 {
+            return vec![];
+        }
+        let s = isqrt(d);
+        if s * s != d {
+            return vec![];
+        }
+        [-b - s, -b + s].iter()
+            .filter(|&&n| n % (2 * a) == 0 && n / (2 * a) >= 0 && n / (2 * a) <= u64::MAX as i128)
+            .map(|&n| (n / (2 * a)) as u64)
+            .collect()
+    }
+}
+
+/// First tick after which a * t² + b * t + c doesn't change its sign anymore
+fn stable_after(a: i64, b: i64, c: i64) -> u64 {
+    let (a, b, c) = (a as f64, b as f64, c as f64);
+    let root = if a == 0.0 {
+        if b == 0.0 { return 0; }
+        -c / b
+    } else {
+        let d = b * b - 4.0 * a * c;
+        if d < 0.0 { return 0; }
+        ((-b - d.sqrt()) / (2.0 * a)).max((-b + d.sqrt()) / (2.0 * a))
+    };
+    if root < 0.0 { 0 } else { root.floor() as u64 + 1 }
+}
+
+
 /// A cloud of particles in space
 #[derive(Debug, Clone)]
 struct Cloud(Vec<Option<Particle>>);
 
+/// Error when parsing a cloud
+#[derive(Debug, PartialEq)]
+struct ParseError {
+    /// Line number (1-based)
+    line: usize,
+    /// Text of the line
+    text: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: invalid particle '{}'", self.line, self.text)
+    }
+}
+
+
 impl FromStr for Cloud {
-    type Err = nom::ErrorKind;
+    type Err = ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Cloud(try!(s.lines().map(str::parse).map(|r| r.map(|p| Some(p))).collect())))
+        Ok(Cloud(try!(s.lines().enumerate().map(|(i, line)|
+            line.parse().map(Some).map_err(|_| ParseError { line: i + 1, text: line.to_string() })
+        ).collect())))
+    }
+}
+
+impl fmt::Display for Cloud {
+    /// Writes one particle per line. Removed particles are skipped, so line
+    /// numbers only match particle indices as long as nothing collided.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for p in self.0.iter().filter_map(|o| o.as_ref()) {
+            try!(writeln!(f, "{}", p));
+        }
+        Ok(())
     }
 }
 
@@ -74,70 +255,399 @@ impl Cloud {
         self.0.iter().filter(|o| o.is_some()).count()
     }
 
-    /// Returns a new cloud with colliding particles removed
-    fn collision(&self) -> Cloud {
-        let mut collisioned: HashSet<usize> = HashSet::new();
-        for i in 1..self.0.len() {
-            for j in 0..i {
-                if let Some(ref p1) = self.0[i] {
-                    if let Some(ref p2) = self.0[j] {
-                        if p1.pos == p2.pos {
-                            collisioned.insert(i);
-                            collisioned.insert(j);
-                        }
-                    }
-                }
+    /// Removes colliding particles
+    fn collide_mut(&mut self) {
+        let mut positions: HashMap<(i64, i64, i64), Vec<usize>> = HashMap::new();
+        for (i, o) in self.0.iter().enumerate() {
+            if let Some(ref p) = *o {
+                positions.entry(p.pos).or_default().push(i);
             }
         }
-        Cloud(self.0.iter()
-            .enumerate()
-            .map(|(i, o)|
-                if !collisioned.contains(&i) { o.clone() } else { None }
-            )
-            .collect()
-        )
+        for indices in positions.values().filter(|indices| indices.len() > 1) {
+            for &i in indices {
+                self.0[i] = None;
+            }
+        }
+    }
+
+    /// Advances t ticks in time
+    fn tick_mut(&mut self, t: usize) {
+        for p in self.0.iter_mut().filter_map(|o| o.as_mut()) {
+            p.pos = p.position_at(t as u64);
+            p.vel = p.velocity_at(t as u64);
+        }
+    }
+
+    /// Advances t ticks in time, removing colliding particles
+    fn simulate_mut(&mut self, t: usize) {
+        for _ in 0..t {
+            self.collide_mut();
+            self.tick_mut(1);
+        }
+    }
+
+    /// Returns a new cloud with colliding particles removed
+    #[allow(dead_code)]
+    fn collision(&self) -> Cloud {
+        let mut cloud = self.clone();
+        cloud.collide_mut();
+        cloud
     }
 
     /// Returns a new cloud that advanced t ticks in time
     fn tick(&self, t: usize) -> Cloud {
-        Cloud(self.0.iter()
-            .map(|o| match *o {
-                Some(ref p) => Some(p.tick(t)),
-                None => None
-            })
-            .collect()
-        )
+        let mut cloud = self.clone();
+        cloud.tick_mut(t);
+        cloud
     }
 
     /// Returns a new cloud that advanced t ticks in time, removing colliding particles
+    #[allow(dead_code)]
     fn tick_with_collision(&self, t: usize) -> Cloud {
-        (0..t).fold(self.clone(), |c, _| c.collision().tick(1))
+        let mut cloud = self.clone();
+        cloud.simulate_mut(t);
+        cloud
+    }
+
+    /// Splits particles into chunks for the given number of threads
+    #[allow(dead_code)]
+    fn chunks(&self, threads: usize) -> Vec<(usize, Vec<Option<Particle>>)> {
+        let size = cmp::max(self.0.len().div_ceil(threads), 1);
+        self.0.chunks(size).enumerate().map(|(i, chunk)| (i * size, chunk.to_vec())).collect()
+    }
+
+    /// Returns a new cloud that advanced t ticks in time, using the given number
+    /// of threads
+    #[allow(dead_code)]
+    fn tick_parallel(&self, t: u64, threads: usize) -> Cloud {
+        if threads <= 1 {
+            return self.tick(t as usize);
+        }
+        let workers: Vec<_> = self.chunks(threads).into_iter().map(|(_, chunk)| {
+            thread::spawn(move || Cloud(chunk).tick(t as usize).0)
+        }).collect();
+        Cloud(workers.into_iter().flat_map(|worker| worker.join().expect("Worker thread panicked")).collect())
+    }
+
+    /// Returns a new cloud with colliding particles removed, using the given number
+    /// of threads to collect positions
+    #[allow(dead_code)]
+    fn collision_parallel(&self, threads: usize) -> Cloud {
+        if threads <= 1 {
+            return self.collision();
+        }
+        let workers: Vec<_> = self.chunks(threads).into_iter().map(|(offset, chunk)| {
+            thread::spawn(move || {
+                let mut positions: HashMap<(i64, i64, i64), Vec<usize>> = HashMap::new();
+                for (i, o) in chunk.into_iter().enumerate() {
+                    if let Some(p) = o {
+                        positions.entry(p.pos).or_default().push(offset + i);
+                    }
+                }
+                positions
+            })
+        }).collect();
+        let mut positions: HashMap<(i64, i64, i64), Vec<usize>> = HashMap::new();
+        for worker in workers {
+            for (pos, indices) in worker.join().expect("Worker thread panicked") {
+                positions.entry(pos).or_default().extend(indices);
+            }
+        }
+        let mut cloud = self.clone();
+        for indices in positions.values().filter(|indices| indices.len() > 1) {
+            for &i in indices {
+                cloud.0[i] = None;
+            }
+        }
+        cloud
+    }
+
+    /// Returns an iterator over snapshots of the cloud after 0, 1, 2, ... ticks,
+    /// removing colliding particles
+    #[allow(dead_code)]
+    fn iter_ticks(self) -> impl Iterator<Item = Cloud> {
+        iter::successors(Some(self), |cloud| Some(cloud.tick_with_collision(1)))
+    }
+
+    /// Particle with the given index, if it still exists
+    #[allow(dead_code)]
+    fn particle(&self, idx: usize) -> Option<&Particle> {
+        self.0.get(idx).and_then(|o| o.as_ref())
     }
 
     /// Index of particle with smallest distance to origin
+    #[allow(dead_code)]
     fn nearest(&self) -> Option<usize> {
         self.0.iter()
-            .filter_map(|o| match *o {
-                Some(ref p) => Some(p.distance()),
-                None => None
-            })
             .enumerate()
+            .filter_map(|(i, o)| o.as_ref().map(|p| (i, p.distance())))
             .min_by_key(|&(_, d)| d)
             .map(|(i, _)| i)
     }
+
+    /// Writes positions of all particles at every `every` ticks up to `ticks` as
+    /// CSV with columns tick, index, x, y and z (without collisions)
+    #[allow(dead_code)]
+    fn write_trajectories_csv<W: Write>(&self, mut w: W, ticks: u64, every: u64) -> io::Result<()> {
+        try!(writeln!(w, "tick,index,x,y,z"));
+        for t in (0..ticks + 1).step_by(cmp::max(every, 1) as usize) {
+            for (i, o) in self.0.iter().enumerate() {
+                if let Some(ref p) = *o {
+                    let pos = p.position_at(t);
+                    try!(writeln!(w, "{},{},{},{},{}", t, i, pos.0, pos.1, pos.2));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Bounding box (minimum and maximum coordinates) of all particles after t
+    /// ticks (without collisions). For an empty cloud, minimums are i64::MAX and
+    /// maximums are i64::MIN.
+    #[allow(dead_code)]
+    fn bounding_box_at(&self, t: u64) -> ((i64, i64, i64), (i64, i64, i64)) {
+        let mut min = (i64::MAX, i64::MAX, i64::MAX);
+        let mut max = (i64::MIN, i64::MIN, i64::MIN);
+        for p in self.0.iter().filter_map(|o| o.as_ref()) {
+            let pos = p.position_at(t);
+            min = (cmp::min(min.0, pos.0), cmp::min(min.1, pos.1), cmp::min(min.2, pos.2));
+            max = (cmp::max(max.0, pos.0), cmp::max(max.1, pos.1), cmp::max(max.2, pos.2));
+        }
+        (min, max)
+    }
+
+    /// Bounding box of all particles at every `step` ticks in the given range
+    #[allow(dead_code)]
+    fn bounding_box_over(&self, ticks: Range<u64>, step: u64) -> ((i64, i64, i64), (i64, i64, i64)) {
+        let mut min = (i64::MAX, i64::MAX, i64::MAX);
+        let mut max = (i64::MIN, i64::MIN, i64::MIN);
+        for t in ticks.step_by(cmp::max(step, 1) as usize) {
+            let (lo, hi) = self.bounding_box_at(t);
+            min = (cmp::min(min.0, lo.0), cmp::min(min.1, lo.1), cmp::min(min.2, lo.2));
+            max = (cmp::max(max.0, hi.0), cmp::max(max.1, hi.1), cmp::max(max.2, hi.2));
+        }
+        (min, max)
+    }
+
+    /// Indices of particles whose distance to origin doesn't exceed r at any
+    /// tick up to the horizon (without collisions)
+    #[allow(dead_code)]
+    fn within_radius(&self, r: i64, horizon: u64) -> Vec<usize> {
+        self.0.iter()
+            .enumerate()
+            .filter_map(|(i, o)| o.as_ref().map(|p| (i, p)))
+            .filter(|&(_, p)| {
+                let mut bound: i64 = 0;
+                for (k, &(a, b, _)) in p.polynomials().iter().enumerate() {
+                    // The largest absolute value on an axis is at either end or next to the vertex.
+                    // Division rounds down or up depending on the sign of a, so check both ways.
+                    let mut ticks = vec![0, horizon];
+                    if a != 0 {
+                        let vertex = (-b).div_euclid(2 * a);
+                        for t in vertex - 1..vertex + 2 {
+                            if t > 0 && (t as u64) < horizon {
+                                ticks.push(t as u64);
+                            }
+                        }
+                    }
+                    let extreme = ticks.into_iter().map(|t| {
+                        let pos = p.position_at(t);
+                        [pos.0, pos.1, pos.2][k].saturating_abs()
+                    }).max().unwrap_or(0);
+                    if extreme > r {
+                        return false;
+                    }
+                    bound = bound.saturating_add(extreme);
+                }
+                // Extremes of different axes may be at different ticks, so check every tick if needed
+                bound <= r || (0..horizon + 1).all(|t| {
+                    let pos = p.position_at(t);
+                    pos.0.saturating_abs().saturating_add(pos.1.saturating_abs()).saturating_add(pos.2.saturating_abs()) <= r
+                })
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Index of particle that stays closest to origin in the long run (without
+    /// collisions). Particles are ordered by their long term distance polynomial
+    /// (see `Particle::long_term_distance`), i.e. by
+    /// 1. Manhattan length of acceleration,
+    /// 2. velocity (plus half the acceleration, as velocity is updated first)
+    ///    projected onto the eventual signs of the axes,
+    /// 3. position projected onto the eventual signs of the axes,
+    /// 4. index, if particles keep the same distance forever.
+    fn nearest_long_term(&self) -> Option<usize> {
+        self.0.iter()
+            .enumerate()
+            .filter_map(|(i, o)| o.as_ref().map(|p| (p.long_term_distance(), i)))
+            .min()
+            .map(|(_, i)| i)
+    }
+
+    /// Number of ticks after which the particle nearest to origin doesn't change
+    /// anymore (without collisions). Once no position changes its sign anymore,
+    /// distances are quadratic polynomials of the tick, so the nearest particle in
+    /// the long run is the one with the smallest polynomial.
+    fn ranking_time(&self) -> u64 {
+        let mut ticks = 0;
+        let mut distances = Vec::new();
+        for (i, o) in self.0.iter().enumerate() {
+            if let Some(ref p) = *o {
+                for &(a, b, c) in &p.polynomials() {
+                    ticks = cmp::max(ticks, stable_after(a, b, c));
+                }
+                distances.push((p.long_term_distance(), i));
+            }
+        }
+        if distances.len() < 2 {
+            return 0;
+        }
+        let &(min, _) = distances.iter().min().unwrap();
+        for &(d, _) in &distances {
+            ticks = cmp::max(ticks, stable_after(d.0 - min.0, d.1 - min.1, d.2 - min.2));
+        }
+        ticks
+    }
+
+    /// Finds all possible collisions, sorted by time. Particles that meet at the
+    /// same time and position are combined into one event. Since particles are
+    /// destroyed when colliding, an event may contain particles that were
+    /// already destroyed by an earlier collision.
+    #[allow(dead_code)]
+    fn collisions_exact(&self) -> Vec<CollisionEvent> {
+        let mut groups: BTreeMap<_, BTreeSet<usize>> = BTreeMap::new();
+        for (i, p1) in self.0.iter().enumerate() {
+            for (j, p2) in self.0.iter().enumerate().skip(i + 1) {
+                if let (Some(p1), Some(p2)) = (p1.as_ref(), p2.as_ref()) {
+                    if let Some(t) = p1.collision_time(p2) {
+                        let indices = groups.entry((t, p1.position_at(t))).or_default();
+                        indices.insert(i);
+                        indices.insert(j);
+                    }
+                }
+            }
+        }
+        groups.into_iter()
+            .map(|((time, _), indices)| CollisionEvent { time: time, indices: indices.into_iter().collect() })
+            .collect()
+    }
+
+    /// Number of particles left after all collisions, without simulating ticks
+    #[allow(dead_code)]
+    fn survivors_exact(&self) -> usize {
+        let mut alive: Vec<bool> = self.0.iter().map(Option::is_some).collect();
+        for event in self.collisions_exact() {
+            let colliding: Vec<usize> = event.indices.into_iter().filter(|&i| alive[i]).collect();
+            if colliding.len() > 1 {
+                for i in colliding {
+                    alive[i] = false;
+                }
+            }
+        }
+        alive.into_iter().filter(|&a| a).count()
+    }
+
+    /// Simulates the cloud with collisions until no more collisions can happen and
+    /// the particle nearest to origin doesn't change anymore
+    fn settle(&self) -> Settled {
+        let particles: Vec<&Particle> = self.0.iter().filter_map(|o| o.as_ref()).collect();
+        let mut last_collision = 0;
+        for i in 1..particles.len() {
+            for j in 0..i {
+                if let Some(t) = particles[i].collision_time(particles[j]) {
+                    last_collision = cmp::max(last_collision, t);
+                }
+            }
+        }
+        let mut cloud = self.clone();
+        cloud.collide_mut();
+        for _ in 0..last_collision {
+            cloud.tick_mut(1);
+            cloud.collide_mut();
+        }
+        let ranking_time = cloud.ranking_time();
+        Settled { ticks: last_collision + ranking_time, cloud: cloud.tick(ranking_time as usize) }
+    }
+}
+
+
+/// Particles that meet at the same position at the given time
+#[allow(dead_code)]
+#[derive(Debug, PartialEq)]
+struct CollisionEvent {
+    time: u64,
+    /// Indices of particles, in ascending order
+    indices: Vec<usize>,
+}
+
+
+/// A cloud that doesn't change its collisions and nearest particle anymore
+#[derive(Debug)]
+struct Settled {
+    /// Number of ticks simulated
+    #[allow(dead_code)]
+    ticks: u64,
+    cloud: Cloud,
 }
 
 
 fn main() {
     let cloud: Cloud = include_str!("day20.txt").parse().unwrap();
-    println!("Particle staying closest to origin: {}", cloud.tick(1000).nearest().unwrap());
-    println!("Particles left after collisions: {}", cloud.tick_with_collision(1000).count());
+    println!("Particle staying closest to origin: {}", cloud.nearest_long_term().unwrap());
+    println!("Particles left after collisions: {}", cloud.settle().cloud.count());
 }
 
 
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "nightly")]
+    extern crate test;
+
     use super::*;
+    use std::collections::HashSet;
+
+    /// Returns a pseudo random number between -range and range (xorshift)
+    fn random(seed: &mut u32, range: i64) -> i64 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 17;
+        *seed ^= *seed << 5;
+        (*seed % (2 * range as u32 + 1)) as i64 - range
+    }
+
+    /// Returns a cloud of n pseudo random particles in a small space
+    fn random_cloud(n: usize) -> Cloud {
+        let mut seed: u32 = 42;
+        Cloud((0..n).map(|_| Some(Particle {
+            pos: (random(&mut seed, 20), random(&mut seed, 20), random(&mut seed, 20)),
+            vel: (random(&mut seed, 3), random(&mut seed, 3), random(&mut seed, 3)),
+            acc: (random(&mut seed, 1), random(&mut seed, 1), random(&mut seed, 1)),
+        })).collect())
+    }
+
+    /// Removes colliding particles by comparing every pair of particles
+    fn collision_pairwise(cloud: &Cloud) -> Cloud {
+        let mut collisioned: HashSet<usize> = HashSet::new();
+        for i in 1..cloud.0.len() {
+            for j in 0..i {
+                if let Some(ref p1) = cloud.0[i] {
+                    if let Some(ref p2) = cloud.0[j] {
+                        if p1.pos == p2.pos {
+                            collisioned.insert(i);
+                            collisioned.insert(j);
+                        }
+                    }
+                }
+            }
+        }
+        Cloud(cloud.0.iter()
+            .enumerate()
+            .map(|(i, o)|
+                if !collisioned.contains(&i) { o.clone() } else { None }
+            )
+            .collect()
+        )
+    }
 
     #[test]
     fn samples1() {
@@ -176,4 +686,264 @@ mod tests {
         assert_eq!(cloud.tick_with_collision(3).0[3], Some(Particle { pos: ( 0, 0, 0), vel: (-1, 0, 0), acc: ( 0, 0, 0) }));
         assert_eq!(cloud.tick_with_collision(3).count(), 1);
     }
+
+    #[test]
+    fn closed_form() {
+        let cloud = Cloud::from_str("p=<3,0,0>, v=<2,0,0>, a=<-1,0,0>\np=<4,0,0>, v=<0,0,0>, a=<-2,0,0>\n").unwrap();
+        for p in cloud.0.iter().filter_map(|o| o.as_ref()) {
+            for t in 0..11 {
+                let q = p.tick(t);
+                assert_eq!(p.position_at(t as u64), q.pos);
+                assert_eq!(p.velocity_at(t as u64), q.vel);
+            }
+        }
+        // Pseudo random particles at pseudo random times
+        let mut seed: u32 = 2017;
+        let mut random = |range: i64| random(&mut seed, range);
+        for _ in 0..100 {
+            let p = Particle {
+                pos: (random(1000), random(1000), random(1000)),
+                vel: (random(100), random(100), random(100)),
+                acc: (random(10), random(10), random(10)),
+            };
+            let t = (random(500) + 500) as usize;
+            let q = p.tick(t);
+            assert_eq!(p.position_at(t as u64), q.pos);
+            assert_eq!(p.velocity_at(t as u64), q.vel);
+        }
+    }
+
+    #[test]
+    fn long_horizon() {
+        let p = Particle::from_str("p=<-5,0,7>, v=<1,0,0>, a=<300,-300,0>").unwrap();
+        assert_eq!(p.position_at(100_000), (1_500_015_099_995, -1_500_015_000_000, 7));
+        assert_eq!(p.velocity_at(100_000), (30_000_001, -30_000_000, 0));
+        assert_eq!(p.tick(100_000).pos, p.position_at(100_000));
+        assert_eq!(p.position_at(u64::MAX), (i64::MAX, i64::MIN, 7));
+        assert_eq!(p.velocity_at(u64::MAX), (i64::MAX, i64::MIN, 0));
+    }
+
+    #[test]
+    fn collisions() {
+        let cloud = Cloud::from_str("p=<0,0,0>, v=<1,0,0>, a=<0,0,0>\np=<2,0,0>, v=<-1,0,0>, a=<0,0,0>\np=<1,1,0>, v=<0,-1,0>, a=<0,0,0>\np=<5,0,0>, v=<0,0,0>, a=<0,0,0>\n").unwrap();
+        assert_eq!(cloud.tick(1).collision().count(), 1);
+        let mut cloud: Cloud = include_str!("day20.txt").parse().unwrap();
+        for _ in 0..50 {
+            assert_eq!(cloud.collision().0, collision_pairwise(&cloud).0);
+            cloud = cloud.collision().tick(1);
+        }
+    }
+
+    #[test]
+    fn nearest_after_collision() {
+        let cloud = Cloud::from_str("p=<9,0,0>, v=<0,0,0>, a=<0,0,0>\np=<1,0,0>, v=<1,0,0>, a=<0,0,0>\np=<3,0,0>, v=<-1,0,0>, a=<0,0,0>\np=<5,0,0>, v=<0,0,0>, a=<0,0,0>\n").unwrap();
+        let cloud = cloud.tick(1).collision();
+        assert_eq!(cloud.count(), 2);
+        assert_eq!(cloud.particle(1), None);
+        assert_eq!(cloud.nearest(), Some(3));
+        assert_eq!(cloud.particle(3).map(|p| p.pos), Some((5, 0, 0)));
+    }
+
+    #[test]
+    fn exact_collisions() {
+        let cloud = Cloud::from_str("p=<-6,0,0>, v=<3,0,0>, a=<0,0,0>\np=<-4,0,0>, v=<2,0,0>, a=<0,0,0>\np=<-2,0,0>, v=<1,0,0>, a=<0,0,0>\np=<3,0,0>, v=<-1,0,0>, a=<0,0,0>\n").unwrap();
+        assert_eq!(cloud.collisions_exact(), vec![CollisionEvent { time: 2, indices: vec![0, 1, 2] }]);
+        assert_eq!(cloud.survivors_exact(), 1);
+        let cloud: Cloud = include_str!("day20.txt").parse().unwrap();
+        assert_eq!(cloud.survivors_exact(), cloud.tick_with_collision(1000).count());
+        assert_eq!(integer_roots(1, -5, 6), vec![2, 3]);
+        assert_eq!(integer_roots(0, 2, -8), vec![4]);
+        assert_eq!(integer_roots(0, 0, 3), vec![]);
+        assert_eq!(integer_roots(-4_000_000_000, 0, 4_000_000_000), vec![1]);
+        let cloud = Cloud::from_str("p=<2000000000,0,0>, v=<0,0,0>, a=<-2000000000,0,0>\n\
+                                     p=<-2000000000,0,0>, v=<0,0,0>, a=<2000000000,0,0>\n").unwrap();
+        assert_eq!(cloud.collisions_exact(), vec![CollisionEvent { time: 1, indices: vec![0, 1] }]);
+        assert_eq!(cloud.survivors_exact(), 0);
+        assert_eq!(cloud.settle().cloud.count(), 0);
+        assert_eq!(isqrt(99), 9);
+        assert_eq!(isqrt(100), 10);
+    }
+
+    #[test]
+    fn long_term_ranking() {
+        let cloud = Cloud::from_str("p=<3,0,0>, v=<2,0,0>, a=<-1,0,0>\np=<4,0,0>, v=<0,0,0>, a=<-2,0,0>\n").unwrap();
+        assert_eq!(cloud.nearest_long_term(), Some(0));
+        let cloud: Cloud = include_str!("day20.txt").parse().unwrap();
+        assert_eq!(cloud.nearest_long_term(), cloud.tick(1000).nearest());
+        // Same acceleration, but velocity against it
+        let cloud = Cloud::from_str("p=<0,0,0>, v=<1,0,0>, a=<1,-1,0>\np=<0,0,0>, v=<-1,0,0>, a=<-1,1,0>\np=<0,0,0>, v=<-1,0,0>, a=<1,0,1>\n").unwrap();
+        assert_eq!(cloud.nearest_long_term(), Some(2));
+        assert_eq!(cloud.tick(1000).nearest(), Some(2));
+        // Same acceleration and velocity, but starting closer
+        let cloud = Cloud::from_str("p=<5,0,0>, v=<0,0,0>, a=<1,0,0>\np=<-3,0,0>, v=<0,0,0>, a=<1,0,0>\n").unwrap();
+        assert_eq!(cloud.nearest_long_term(), Some(1));
+        assert_eq!(cloud.tick(1000).nearest(), Some(1));
+    }
+
+    #[test]
+    fn formatting() {
+        let input = "p=<3,0,0>, v=<2,0,0>, a=<-1,0,0>\np=<4,0,0>, v=<0,0,0>, a=<-2,0,0>\n";
+        let cloud = Cloud::from_str(input).unwrap();
+        assert_eq!(cloud.to_string(), input);
+        let p = Particle { pos: (-5, 1_000_000_000_000, 0), vel: (1, -2, 3), acc: (0, 0, -7) };
+        assert_eq!(Particle::from_str(&p.to_string()), Ok(p));
+        let mut csv = Vec::new();
+        cloud.write_trajectories_csv(&mut csv, 3, 1).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "tick,index,x,y,z\n\
+            0,0,3,0,0\n0,1,4,0,0\n\
+            1,0,4,0,0\n1,1,2,0,0\n\
+            2,0,4,0,0\n2,1,-2,0,0\n\
+            3,0,3,0,0\n3,1,-8,0,0\n");
+        let mut csv = Vec::new();
+        cloud.write_trajectories_csv(&mut csv, 1_000_000_000, 500_000_000).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap().lines().count(), 7);
+    }
+
+    #[test]
+    fn parsing() {
+        let p = Particle { pos: (3, 0, 0), vel: (2, 0, 0), acc: (-1, 0, 0) };
+        assert_eq!(Particle::from_str("p=<3,0,0>, v=<2,0,0>, a=<-1,0,0>"), Ok(p.clone()));
+        assert_eq!(Particle::from_str("p=< 3,0,0>, v=<2,0,0>, a=<-1,0,0>"), Ok(p.clone()));
+        assert_eq!(Particle::from_str("p=<3 , 0 ,0>, v=<2,0,0>, a=<-1,0,0>"), Ok(p.clone()));
+        assert_eq!(Particle::from_str("p=<3,0,0 >, v=< 2, 0, 0 >, a=<-1,0,0>"), Ok(p.clone()));
+        assert_eq!(Particle::from_str("p=<3,0,0>,v=<2,0,0>,a=<-1,0,0>"), Ok(p.clone()));
+        assert_eq!(Particle::from_str("p=<+3,0,0>, v=<+2,0,0>, a=<-1,+0,0>"), Ok(p));
+        let mut input: Vec<&str> = include_str!("day20.txt").lines().take(10).collect();
+        input[6] = "p=<1,2>, v=<0,0,0>, a=<0,0,0>";
+        let error = Cloud::from_str(&input.join("\n")).unwrap_err();
+        assert_eq!(error, ParseError { line: 7, text: input[6].to_string() });
+        assert_eq!(error.to_string(), "line 7: invalid particle 'p=<1,2>, v=<0,0,0>, a=<0,0,0>'");
+    }
+
+    #[test]
+    fn in_place() {
+        let cloud = Cloud::from_str("p=<3,0,0>, v=<2,0,0>, a=<-1,0,0>\np=<4,0,0>, v=<0,0,0>, a=<-2,0,0>\n").unwrap();
+        for t in 0..5 {
+            let mut c = cloud.clone();
+            c.tick_mut(t);
+            assert_eq!(c.0, cloud.tick(t).0);
+        }
+        let cloud = Cloud::from_str("p=<-6,0,0>, v=<3,0,0>, a=<0,0,0>\np=<-4,0,0>, v=<2,0,0>, a=<0,0,0>\np=<-2,0,0>, v=<1,0,0>, a=<0,0,0>\np=<3,0,0>, v=<-1,0,0>, a=<0,0,0>\n").unwrap();
+        let mut c = cloud.clone();
+        let snapshots: Vec<Cloud> = cloud.clone().iter_ticks().take(5).collect();
+        for (t, snapshot) in snapshots.iter().enumerate() {
+            assert_eq!(snapshot.0, cloud.tick_with_collision(t).0);
+            assert_eq!(c.0, snapshot.0);
+            c.simulate_mut(1);
+        }
+        let mut c = cloud.tick(2);
+        c.collide_mut();
+        assert_eq!(c.0, cloud.tick(2).collision().0);
+        assert_eq!(c.count(), 1);
+        assert_eq!(c.0.len(), 4);
+    }
+
+    #[test]
+    fn spatial_queries() {
+        let cloud = Cloud::from_str("p=<3,0,0>, v=<2,0,0>, a=<-1,0,0>\np=<4,0,0>, v=<0,0,0>, a=<-2,0,0>\n").unwrap();
+        assert_eq!(cloud.bounding_box_at(0), ((3, 0, 0), (4, 0, 0)));
+        assert_eq!(cloud.bounding_box_at(1), ((2, 0, 0), (4, 0, 0)));
+        assert_eq!(cloud.bounding_box_at(3), ((-8, 0, 0), (3, 0, 0)));
+        assert_eq!(cloud.bounding_box_over(0..4, 1), ((-8, 0, 0), (4, 0, 0)));
+        assert_eq!(cloud.bounding_box_over(0..4, 2), ((-2, 0, 0), (4, 0, 0)));
+        assert_eq!(cloud.within_radius(4, 2), vec![0, 1]);
+        assert_eq!(cloud.within_radius(4, 5), vec![0]);
+        assert_eq!(cloud.within_radius(5, 6), vec![]);
+        let cloud = Cloud::from_str("p=<-3,1,0>, v=<1,0,0>, a=<0,0,0>\np=<2,2,0>, v=<-1,1,0>, a=<0,0,0>\n").unwrap();
+        assert_eq!(cloud.bounding_box_at(6), ((-4, 1, 0), (3, 8, 0)));
+        assert_eq!(cloud.within_radius(4, 6), vec![0]);
+        assert_eq!(cloud.within_radius(4, 7), vec![]);
+        assert_eq!(cloud.within_radius(4, 0), vec![0, 1]);
+        // Moving diagonally, axes have their extremes at different ticks
+        let cloud = Cloud::from_str("p=<3,0,0>, v=<-1,1,0>, a=<0,0,0>\n").unwrap();
+        assert_eq!(cloud.within_radius(3, 3), vec![0]);
+        assert_eq!(cloud.within_radius(3, 4), vec![]);
+        // Decelerating, the extreme is at the tick before the rounded vertex
+        let cloud = Cloud::from_str("p=<0,0,0>, v=<5,0,0>, a=<-3,0,0>\n").unwrap();
+        assert_eq!(cloud.within_radius(1, 2), vec![]);
+        assert_eq!(cloud.within_radius(2, 2), vec![0]);
+    }
+
+    #[test]
+    fn parallel() {
+        let cloud = random_cloud(10_000);
+        for &threads in &[0, 1, 2, 3, 4, 7] {
+            assert_eq!(cloud.tick_parallel(17, threads).0, cloud.tick(17).0);
+            let mut sequential = cloud.clone();
+            let mut parallel = cloud.clone();
+            for _ in 0..5 {
+                sequential = sequential.collision().tick(1);
+                parallel = parallel.collision_parallel(threads).tick_parallel(1, threads);
+                assert_eq!(parallel.0, sequential.0);
+            }
+            assert!(parallel.count() < cloud.count());
+        }
+        assert_eq!(Cloud(vec![]).tick_parallel(1, 4).0, vec![]);
+    }
+
+    #[test]
+    fn settling() {
+        let cloud = Cloud::from_str("p=<-6,0,0>, v=<3,0,0>, a=<0,0,0>\np=<-4,0,0>, v=<2,0,0>, a=<0,0,0>\np=<-2,0,0>, v=<1,0,0>, a=<0,0,0>\np=<3,0,0>, v=<-1,0,0>, a=<0,0,0>\n").unwrap();
+        let settled = cloud.settle();
+        assert!(settled.ticks <= 3);
+        assert_eq!(settled.cloud.count(), 1);
+        let cloud: Cloud = include_str!("day20.txt").parse().unwrap();
+        assert_eq!(cloud.tick(cloud.ranking_time() as usize).nearest(), cloud.tick(1000).nearest());
+        assert_eq!(cloud.settle().cloud.count(), cloud.tick_with_collision(1000).count());
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_tick_with_collision(b: &mut test::Bencher) {
+        let cloud: Cloud = include_str!("day20.txt").parse().unwrap();
+        b.iter(|| {
+            cloud.tick_with_collision(1000)
+        })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_simulate_mut(b: &mut test::Bencher) {
+        let cloud: Cloud = include_str!("day20.txt").parse().unwrap();
+        b.iter(|| {
+            let mut cloud = cloud.clone();
+            cloud.simulate_mut(1000);
+            cloud
+        })
+    }
+
+    #[cfg(feature = "nightly")]
+    fn benchmark_parallel(b: &mut test::Bencher, threads: usize) {
+        let cloud = random_cloud(100_000);
+        b.iter(|| {
+            cloud.collision_parallel(threads).tick_parallel(1, threads)
+        })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_parallel_1(b: &mut test::Bencher) {
+        benchmark_parallel(b, 1)
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_parallel_2(b: &mut test::Bencher) {
+        benchmark_parallel(b, 2)
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_parallel_4(b: &mut test::Bencher) {
+        benchmark_parallel(b, 4)
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_tick_with_collision_pairwise(b: &mut test::Bencher) {
+        let cloud: Cloud = include_str!("day20.txt").parse().unwrap();
+        b.iter(|| {
+            (0..1000).fold(cloud.clone(), |c, _| collision_pairwise(&c).tick(1))
+        })
+    }
 }
@@ -1,21 +1,122 @@
-#[macro_use]
-extern crate nom;
+#![cfg_attr(feature = "nightly", feature(test))]
 
-use std::fmt;
+use std::collections::{HashMap, HashSet};
+use std::{env, fmt, fs, thread};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::OnceLock;
 use std::str::FromStr;
 
 
-#[derive(PartialEq, Clone)]
+#[derive(Debug, PartialEq)]
+enum ParseError {
+    /// No pixels at all
+    Empty,
+    /// Row (1-based) has a different length than the number of rows
+    NotSquare { row: usize, len: usize, size: usize },
+    /// Row and column (1-based) of a character other than `.` or `#`
+    InvalidChar { row: usize, col: usize, ch: char },
+    /// Rule without `=>` separating search and replacement
+    MissingArrow,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::Empty => write!(f, "Empty grid"),
+            ParseError::NotSquare { row, len, size } =>
+                write!(f, "Row {} has {} pixels, but grid has {} rows", row, len, size),
+            ParseError::InvalidChar { row, col, ch } =>
+                write!(f, "Row {}, column {}: invalid pixel {:?}", row, col, ch),
+            ParseError::MissingArrow => write!(f, "Missing => in rule"),
+        }
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+enum PartitionError {
+    /// Grid of the given size can't be divided into subgrids of size 2 or 3
+    NoBlockSize(usize),
+    /// Block size doesn't divide the grid size
+    BlockSize { size: usize, block: usize },
+}
+
+impl fmt::Display for PartitionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PartitionError::NoBlockSize(size) => write!(f, "Couldn't partition grid of size {}", size),
+            PartitionError::BlockSize { size, block } =>
+                write!(f, "Couldn't partition grid of size {} into blocks of size {}", size, block),
+        }
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+enum GridError {
+    /// Zero sized grid requested
+    ZeroSize,
+    /// Area of the given size at the given position exceeds the grid of the given size
+    OutOfBounds { row: usize, col: usize, size: usize, grid_size: usize },
+}
+
+impl fmt::Display for GridError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GridError::ZeroSize => write!(f, "Grid size must not be zero"),
+            GridError::OutOfBounds { row, col, size, grid_size } =>
+                write!(f, "Area of size {} at {},{} exceeds grid of size {}", size, row, col, grid_size),
+        }
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+enum BuildError {
+    /// No subgrids given
+    Empty,
+    /// Number of subgrids isn't a square number
+    NotSquare(usize),
+    /// Subgrid at the given index has a different size than the first one
+    MixedSizes { index: usize, size: usize, expected: usize },
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuildError::Empty => write!(f, "No subgrids to build grid from"),
+            BuildError::NotSquare(count) => write!(f, "Can't arrange {} subgrids in a square", count),
+            BuildError::MixedSizes { index, size, expected } =>
+                write!(f, "Subgrid {} has size {}, expected {}", index, size, expected),
+        }
+    }
+}
+
+
+/// Square grid of pixels, packed as bits with each row starting at a new word
+#[derive(Clone)]
 struct Grid {
-    pixels: Vec<Vec<bool>>,
+    size: usize,
+    /// Number of words per row
+    stride: usize,
+    bits: Vec<u64>,
+    /// Canonical key, computed on demand
+    canonical: OnceLock<Vec<u8>>,
+}
+
+impl PartialEq for Grid {
+    fn eq(&self, other: &Grid) -> bool {
+        self.size == other.size && self.bits == other.bits
+    }
 }
 
 impl fmt::Debug for Grid {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         try!(writeln!(f, "Grid:"));
-        for row in &self.pixels {
-            for &pixel in row {
-               try!(if pixel { write!(f, "#") } else { write!(f, ".") });
+        for r in 0..self.size {
+            for c in 0..self.size {
+               try!(if self.pixel(r, c) { write!(f, "#") } else { write!(f, ".") });
             }
             try!(writeln!(f, ""));
         }
@@ -23,120 +124,285 @@ impl fmt::Debug for Grid {
     }
 }
 
+impl fmt::Display for Grid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let separator = if f.alternate() { '\n' } else { '/' };
+        for r in 0..self.size {
+            if r > 0 { try!(write!(f, "{}", separator)); }
+            for c in 0..self.size {
+                try!(write!(f, "{}", if self.pixel(r, c) { '#' } else { '.' }));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Grid {
+    type Err = ParseError;
+
+    /// Parse grid with rows separated by slashes or newlines
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() { return Err(ParseError::Empty); }
+        let rows: Vec<&str> = if s.contains('/') { s.split('/').collect() } else { s.lines().collect() };
+        let size = rows.len();
+        let mut pixels = Vec::with_capacity(size);
+        for (r, line) in rows.iter().enumerate() {
+            let row: Vec<bool> = try!(line.trim().chars().enumerate().map(|(c, ch)| match ch {
+                '.' => Ok(false),
+                '#' => Ok(true),
+                _ => Err(ParseError::InvalidChar { row: r + 1, col: c + 1, ch: ch }),
+            }).collect());
+            if row.len() != size {
+                return Err(ParseError::NotSquare { row: r + 1, len: row.len(), size: size });
+            }
+            pixels.push(row);
+        }
+        Ok(Grid::from_pixels(pixels))
+    }
+}
+
 impl Grid {
     /// Create new Grid with initial pixels
     fn new() -> Grid {
-        Grid {
-            pixels: vec![vec![false, true, false], vec![false, false, true], vec![true, true, true]],
+        Grid::from_pixels(vec![vec![false, true, false], vec![false, false, true], vec![true, true, true]])
+    }
+
+    /// Create new Grid from a pattern, with rows separated by slashes or newlines
+    fn from_pattern(s: &str) -> Result<Grid, ParseError> {
+        s.parse()
+    }
+
+    /// Create new Grid of the given size with all pixels dark
+    fn empty(size: usize) -> Grid {
+        let stride = size.div_ceil(64);
+        Grid { size: size, stride: stride, bits: vec![0; size * stride], canonical: OnceLock::new() }
+    }
+
+    /// Create new Grid with the given rows of pixels (must be square)
+    fn from_pixels(pixels: Vec<Vec<bool>>) -> Grid {
+        let mut grid = Grid::empty(pixels.len());
+        for (r, row) in pixels.iter().enumerate() {
+            assert_eq!(row.len(), pixels.len());
+            for (c, &pixel) in row.iter().enumerate() {
+                grid.set(r, c, pixel);
+            }
         }
+        grid
+    }
+
+    /// Rows of pixels
+    #[allow(dead_code)]
+    fn pixels(&self) -> Vec<Vec<bool>> {
+        (0..self.size).map(|r| (0..self.size).map(|c| self.pixel(r, c)).collect()).collect()
+    }
+
+    /// Pixel at the given position
+    fn pixel(&self, row: usize, col: usize) -> bool {
+        self.bits[row * self.stride + col / 64] & (1 << (col % 64)) != 0
+    }
+
+    /// Set pixel at the given position
+    fn set(&mut self, row: usize, col: usize, pixel: bool) {
+        let word = &mut self.bits[row * self.stride + col / 64];
+        if pixel { *word |= 1 << (col % 64); } else { *word &= !(1 << (col % 64)); }
     }
 
     /// Size of grid (edge length)
     fn size(&self) -> usize {
-        self.pixels.len()
+        self.size
     }
 
     /// Number of lit pixels
     fn lit_pixels(&self) -> usize {
-        self.pixels.iter().map(|r| r.iter().filter(|&&p| p).count()).sum()
+        self.bits.iter().map(|w| w.count_ones() as usize).sum()
     }
 
     /// Returns the subgrid of the given size and position
     fn subgrid(&self, row: usize, col: usize, size: usize) -> Grid {
-        Grid {
-            pixels: (0..size).map(|r|
-                self.pixels[row+r][col..col+size].to_owned()
-            ).collect()
+        let mut grid = Grid::empty(size);
+        for r in 0..size {
+            for c in 0..size {
+                if self.pixel(row + r, col + c) { grid.set(r, c, true); }
+            }
         }
+        grid
     }
 
-    /// Partition the grid into subgrids with edge size 2 or 3
-    fn subgrids(&self) -> Vec<Grid> {
-        let subsize = if self.size() % 2 == 0 { 2 }
-            else if self.size() % 3 == 0 { 3 }
-            else { panic!("Couldn't partition grid of size {}", self.size()) };
-        let n = self.size() / subsize;
-        let mut grids = vec![];
-        for r in 0..n {
-            for c in 0..n {
-                grids.push(self.subgrid(r*subsize, c*subsize, subsize));
-            }
+    /// Returns the subgrid of the given size and position, checking bounds
+    #[allow(dead_code)]
+    fn crop(&self, row: usize, col: usize, size: usize) -> Result<Grid, GridError> {
+        if size == 0 { return Err(GridError::ZeroSize); }
+        if row + size > self.size || col + size > self.size {
+            return Err(GridError::OutOfBounds { row: row, col: col, size: size, grid_size: self.size });
         }
-        grids
+        Ok(self.subgrid(row, col, size))
     }
 
-    /// Append grid to the right (must be of same height)
-    fn append_right(&mut self, other: &Grid) {
-        assert_eq!(other.pixels.len(), self.pixels.len());
-        for i in 0..self.pixels.len() {
-            self.pixels[i].extend(&other.pixels[i]);
+    /// Returns the grid surrounded by a border of the given width and pixel value
+    #[allow(dead_code)]
+    fn pad(&self, border: usize, value: bool) -> Grid {
+        let size = self.size + 2 * border;
+        let mut grid = Grid::empty(size);
+        for r in 0..size {
+            for c in 0..size {
+                let inside = r >= border && r < border + self.size && c >= border && c < border + self.size;
+                let pixel = if inside { self.pixel(r - border, c - border) } else { value };
+                if pixel { grid.set(r, c, true); }
+            }
         }
+        grid
     }
 
-    /// Append grid to the bottom (must be of same width)
-    fn append_bottom(&mut self, other: &Grid) {
-        assert_eq!(other.pixels[0].len(), self.pixels[0].len());
-        for r in &other.pixels {
-            self.pixels.push(r.clone());
+    /// Partition the grid into subgrids with the given edge size, row by row
+    fn partition(&self, block: usize) -> Result<Vec<Grid>, PartitionError> {
+        if block == 0 || !self.size.is_multiple_of(block) {
+            return Err(PartitionError::BlockSize { size: self.size, block: block });
+        }
+        let n = self.size / block;
+        let mut grids = vec![];
+        for r in 0..n {
+            for c in 0..n {
+                grids.push(self.subgrid(r*block, c*block, block));
+            }
         }
+        Ok(grids)
     }
 
-    /// Build grid from n*n given subgrids
-    fn build(grids: &[Grid]) -> Grid {
-        let mut grid = grids[0].clone();
-        let n = (grids.len() as f32).sqrt() as usize;
-        for j in 1..n {
-            grid.append_right(&grids[j]);
+    /// Partition the grid into subgrids with edge size 2 or 3
+    fn subgrids(&self) -> Result<Vec<Grid>, PartitionError> {
+        let subsize = if self.size() % 2 == 0 { 2 }
+            else if self.size() % 3 == 0 { 3 }
+            else { return Err(PartitionError::NoBlockSize(self.size())) };
+        self.partition(subsize)
+    }
+
+    /// Build grid from n*n given subgrids of same size
+    fn build(grids: &[Grid]) -> Result<Grid, BuildError> {
+        if grids.is_empty() { return Err(BuildError::Empty); }
+        let n = (1..).find(|n| n * n >= grids.len()).unwrap();
+        if n * n != grids.len() { return Err(BuildError::NotSquare(grids.len())); }
+        let subsize = grids[0].size();
+        if let Some((index, g)) = grids.iter().enumerate().find(|&(_, g)| g.size() != subsize) {
+            return Err(BuildError::MixedSizes { index: index, size: g.size(), expected: subsize });
         }
-        for i in 1..n {
-            let mut row = grids[i*n].clone();
-            for j in 1..n {
-                row.append_right(&grids[i*n+j]);
+        let mut grid = Grid::empty(n * subsize);
+        for (i, g) in grids.iter().enumerate() {
+            let (row, col) = (i / n * subsize, i % n * subsize);
+            for r in 0..subsize {
+                for c in 0..subsize {
+                    if g.pixel(r, c) { grid.set(row + r, col + c, true); }
+                }
             }
-            grid.append_bottom(&row);
         }
-        grid
+        Ok(grid)
     }
 
     /// Returns the grid rotated by 90° ccw
     fn rotate(&self) -> Grid {
-        Grid {
-            pixels: (0..self.size()).map(|r|
-                (0..self.size()).map(|c|
-                    self.pixels[c][self.size()-r-1]
-                ).collect()
-            ).collect()
+        let mut grid = Grid::empty(self.size);
+        for r in 0..self.size {
+            for c in 0..self.size {
+                if self.pixel(c, self.size-r-1) { grid.set(r, c, true); }
+            }
         }
+        grid
     }
 
     /// Returns the grid mirrored vertically
     fn mirror(&self) -> Grid {
-        Grid {
-            pixels: self.pixels.iter().map(|row|
-                row.iter().rev().cloned().collect()
-            ).collect()
+        let mut grid = Grid::empty(self.size);
+        for r in 0..self.size {
+            for c in 0..self.size {
+                if self.pixel(r, self.size-c-1) { grid.set(r, c, true); }
+            }
+        }
+        grid
+    }
+
+    /// Returns the grid in all 8 orientations (rotated and mirrored)
+    fn orientations(&self) -> Vec<Grid> {
+        let mut grids = vec![self.clone()];
+        for i in 1..8 {
+            let grid = if i == 4 { grids[3].rotate().mirror() } else { grids[i - 1].rotate() };
+            grids.push(grid);
         }
+        grids
+    }
+
+    /// Compact key of the pixels: edge size followed by pixel bits, row by row
+    fn bits(&self) -> Vec<u8> {
+        let mut bits = vec![self.size() as u8];
+        for i in 0..self.size * self.size {
+            if i % 8 == 0 { bits.push(0); }
+            if self.pixel(i / self.size, i % self.size) { *bits.last_mut().unwrap() |= 0x80 >> (i % 8); }
+        }
+        bits
+    }
+
+    /// Key of the canonical form, identical for all orientations of a grid
+    fn canonical_key(&self) -> Vec<u8> {
+        self.canonical.get_or_init(||
+            self.orientations().iter().map(Grid::bits).min().unwrap()
+        ).clone()
+    }
+
+    /// Canonical form: the lexicographically smallest of all 8 orientations
+    #[allow(dead_code)]
+    fn canonical(&self) -> Grid {
+        let key = self.canonical_key();
+        let size = key[0] as usize;
+        Grid::from_pixels((0..size).map(|r|
+            (0..size).map(|c| {
+                let i = r * size + c;
+                key[1 + i / 8] & (0x80 >> (i % 8)) != 0
+            }).collect()
+        ).collect())
     }
 
     /// Check if the grid matches the given other grid (in any orientation)
+    #[allow(dead_code)]
     fn matches(&self, other: &Grid) -> bool {
-        if self.size() != other.size() { return false; }
-        if self == other { return true; }
-        let other = other.rotate();
-        if self == &other { return true; }
-        let other = other.rotate();
-        if self == &other { return true; }
-        let other = other.rotate();
-        if self == &other { return true; }
-        let other = other.rotate().mirror();
-        if self == &other { return true; }
-        let other = other.rotate();
-        if self == &other { return true; }
-        let other = other.rotate();
-        if self == &other { return true; }
-        let other = other.rotate();
-        if self == &other { return true; }
-        false
+        self.size() == other.size() && self.canonical_key() == other.canonical_key()
+    }
+
+    /// Key identifying the pixels of the grid, with rows separated by slashes
+    fn key(&self) -> String {
+        self.to_string()
+    }
+
+    /// Write grid as plain PBM (P1) bitmap with lit pixels black
+    fn write_pbm<W: Write>(&self, mut w: W) -> io::Result<()> {
+        try!(write!(w, "P1\n{} {}\n", self.size, self.size));
+        for r in 0..self.size {
+            let row: Vec<u8> = (0..self.size).map(|c| if self.pixel(r, c) { b'1' } else { b'0' }).collect();
+            // Lines in PBM files shouldn't be longer than 70 characters
+            for line in row.chunks(70) {
+                try!(w.write_all(line));
+                try!(w.write_all(b"\n"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Render grid with half block characters, two rows per line. For grids with odd
+    /// size, the last line shows the last row in its upper half only.
+    #[allow(dead_code)]
+    fn to_unicode(&self) -> String {
+        let mut s = String::new();
+        for r in (0..self.size).step_by(2) {
+            for c in 0..self.size {
+                let bottom = r + 1 < self.size && self.pixel(r + 1, c);
+                s.push(match (self.pixel(r, c), bottom) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+            s.push('\n');
+        }
+        s
     }
 }
 
@@ -148,67 +414,293 @@ struct Rule {
 }
 
 impl FromStr for Rule {
-    type Err = nom::ErrorKind;
+    type Err = ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        named!(pixel<&str, bool>, alt!(
-            tag!(".") => { |_| false } |
-            tag!("#") => { |_| true }
-        ));
-        named!(row<&str, Vec<bool>>, many1!(pixel));
-        named!(grid<&str, Grid>, map!(separated_list_complete!(tag!("/"), row), |v| Grid { pixels: v }));
-        complete!(s, do_parse!(
-            search: ws!(grid) >> tag!("=>") >> replace: ws!(grid) >>
-            (Rule { search: search, replace: replace })
-        )).to_result()
+        let mut parts = s.splitn(2, "=>");
+        let search = try!(parts.next().unwrap().parse());
+        let replace = try!(try!(parts.next().ok_or(ParseError::MissingArrow)).parse());
+        Ok(Rule { search: search, replace: replace })
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+enum ApplyError {
+    /// No rule matches the subgrid at the given block position
+    NoMatch { pattern: String, size: usize, row: usize, col: usize },
+    /// Grid couldn't be divided into subgrids
+    Partition(PartitionError),
+    /// Replacement grids couldn't be put together
+    Build(BuildError),
+}
+
+impl From<PartitionError> for ApplyError {
+    fn from(err: PartitionError) -> ApplyError {
+        ApplyError::Partition(err)
+    }
+}
+
+impl From<BuildError> for ApplyError {
+    fn from(err: BuildError) -> ApplyError {
+        ApplyError::Build(err)
+    }
+}
+
+impl fmt::Display for ApplyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ApplyError::NoMatch { ref pattern, size, row, col } =>
+                write!(f, "No rule matches {}x{} subgrid {} at block {},{}", size, size, pattern, row, col),
+            ApplyError::Partition(ref err) => write!(f, "{}", err),
+            ApplyError::Build(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+
+/// Problem found when validating a rule book (lines are 1-based)
+#[derive(Debug, PartialEq)]
+enum BookIssue {
+    /// Rule repeats an earlier rule (in some orientation) with the same replacement
+    Duplicate { first: usize, second: usize },
+    /// Rule matches the same pattern as an earlier rule, but with a different replacement
+    Conflict { first: usize, second: usize, pattern: String },
+    /// No rule matches the given canonical pattern
+    Uncovered { pattern: String },
+}
+
+impl fmt::Display for BookIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BookIssue::Duplicate { first, second } =>
+                write!(f, "Rule in line {} duplicates line {}", second, first),
+            BookIssue::Conflict { first, second, ref pattern } =>
+                write!(f, "Rules in lines {} and {} both match {} with different replacements", first, second, pattern),
+            BookIssue::Uncovered { ref pattern } => write!(f, "No rule matches {}", pattern),
+        }
     }
 }
 
 
 #[derive(Debug)]
-struct Book(Vec<Rule>);
+struct Book {
+    #[allow(dead_code)]
+    rules: Vec<Rule>,
+    /// Replacement grids by key of every orientation of the search grids
+    lookup: HashMap<String, Grid>,
+}
 
 impl FromStr for Book {
-    type Err = nom::ErrorKind;
+    type Err = ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Book(try!(s.lines().map(str::parse).collect())))
+        let rules: Vec<Rule> = try!(s.lines().map(str::parse).collect());
+        let mut lookup = HashMap::new();
+        for rule in &rules {
+            for grid in rule.search.orientations() {
+                // Earlier rules take precedence
+                lookup.entry(grid.key()).or_insert_with(|| rule.replace.clone());
+            }
+        }
+        Ok(Book { rules: rules, lookup: lookup })
     }
 }
 
 impl Book {
     /// Find the replacement grid for the given grid
     fn matches(&self, grid: &Grid) -> Option<Grid> {
-        self.0.iter().find(|rule|
-            grid.matches(&rule.search)
-        ).map(|rule|
-            rule.replace.clone()
-        )
+        self.lookup.get(&grid.key()).cloned()
     }
 
     /// Apply rules on all subgrids of the given grid
-    fn apply(&self, grid: &Grid) -> Option<Grid> {
-        grid.subgrids().iter().map(|g|
-            self.matches(g)
-        ).collect::<Option<Vec<Grid>>>().map(|g|
-            Grid::build(&g)
-        )
+    fn apply(&self, grid: &Grid) -> Result<Grid, ApplyError> {
+        let subgrids = try!(grid.subgrids());
+        let n = grid.size() / subgrids[0].size();
+        let grids: Vec<Grid> = try!(subgrids.iter().enumerate().map(|(i, g)|
+            self.replacement(g, i / n, i % n)
+        ).collect());
+        Ok(try!(Grid::build(&grids)))
+    }
+
+    /// Apply rules on all subgrids of the given grid, using the given number of threads
+    #[allow(dead_code)]
+    fn apply_parallel(&self, grid: &Grid, threads: usize) -> Result<Grid, ApplyError> {
+        if threads <= 1 { return self.apply(grid); }
+        let subgrids = try!(grid.subgrids());
+        let n = grid.size() / subgrids[0].size();
+        let chunk_size = subgrids.len().div_ceil(threads);
+        let results: Vec<Result<Vec<Grid>, ApplyError>> = thread::scope(|scope| {
+            let workers: Vec<_> = subgrids.chunks(chunk_size).enumerate().map(|(k, chunk)|
+                scope.spawn(move || chunk.iter().enumerate().map(|(i, g)| {
+                    let i = k * chunk_size + i;
+                    self.replacement(g, i / n, i % n)
+                }).collect())
+            ).collect();
+            workers.into_iter().map(|worker| worker.join().expect("Worker thread panicked")).collect()
+        });
+        let mut grids = Vec::with_capacity(subgrids.len());
+        for result in results {
+            grids.extend(try!(result));
+        }
+        Ok(try!(Grid::build(&grids)))
+    }
+
+    /// Find the replacement grid for the subgrid at the given block position
+    fn replacement(&self, grid: &Grid, row: usize, col: usize) -> Result<Grid, ApplyError> {
+        self.matches(grid).ok_or_else(|| ApplyError::NoMatch {
+            pattern: grid.to_string(), size: grid.size(), row: row, col: col,
+        })
+    }
+
+    /// Check for duplicate and conflicting rules, and for any of the canonical 2x2 and 3x3
+    /// patterns (all of them, not only those reachable from the start) without a rule
+    #[allow(dead_code)]
+    fn validate(&self) -> Vec<BookIssue> {
+        let mut issues = vec![];
+        let mut seen: HashMap<Vec<u8>, usize> = HashMap::new();
+        for (i, rule) in self.rules.iter().enumerate() {
+            let key = rule.search.canonical_key();
+            if let Some(&j) = seen.get(&key) {
+                if self.rules[j].replace == rule.replace {
+                    issues.push(BookIssue::Duplicate { first: j + 1, second: i + 1 });
+                } else {
+                    let pattern = rule.search.canonical().to_string();
+                    issues.push(BookIssue::Conflict { first: j + 1, second: i + 1, pattern: pattern });
+                }
+            } else {
+                seen.insert(key, i);
+            }
+        }
+        let mut checked = HashSet::new();
+        for &size in &[2, 3] {
+            for n in 0..1u32 << (size * size) {
+                let grid = Grid::from_pixels((0..size).map(|r|
+                    (0..size).map(|c| n & (1 << (r * size + c)) != 0).collect()
+                ).collect());
+                if checked.insert(grid.canonical_key()) && self.matches(&grid).is_none() {
+                    issues.push(BookIssue::Uncovered { pattern: grid.canonical().to_string() });
+                }
+            }
+        }
+        issues
+    }
+
+    /// Write the first given number of enhancements of the given grid to files
+    /// iter1.pbm, iter2.pbm, ... in the given directory
+    fn render_iterations(&self, start: &Grid, generations: usize, dir: &Path) -> io::Result<()> {
+        for (n, grid) in self.iterate(start.clone()).take(generations).enumerate() {
+            let grid = try!(grid.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string())));
+            let file = try!(fs::File::create(dir.join(format!("iter{}.pbm", n + 1))));
+            try!(grid.write_pbm(io::BufWriter::new(file)));
+        }
+        Ok(())
+    }
+
+    /// Iterate over successive enhancements of the given grid
+    fn iterate(&self, start: Grid) -> Enhancements<'_> {
+        Enhancements { book: self, grid: Some(start) }
+    }
+
+    /// Count lit pixels after the given number of iterations. Fails with the error of the
+    /// first iteration that failed.
+    #[allow(dead_code)]
+    fn lit_after(&self, start: &Grid, n: usize) -> Result<usize, ApplyError> {
+        let mut grid = start.clone();
+        for result in self.iterate(start.clone()).take(n) {
+            grid = try!(result);
+        }
+        Ok(grid.lit_pixels())
+    }
+
+    /// Count lit pixels after the given number of iterations without building the whole grid.
+    /// Once the grid size is an odd multiple of 3, every 3x3 block evolves independently into
+    /// nine 3x3 blocks over three iterations, so only the number of each block is tracked.
+    fn lit_pixels_after(&self, start: &Grid, iterations: usize) -> Result<u64, ApplyError> {
+        let mut grid = start.clone();
+        let mut iterations = iterations;
+        while iterations > 0 && (grid.size().is_multiple_of(2) || !grid.size().is_multiple_of(3)) {
+            grid = try!(self.apply(&grid));
+            iterations -= 1;
+        }
+        if iterations < 3 {
+            for _ in 0..iterations { grid = try!(self.apply(&grid)); }
+            return Ok(grid.lit_pixels() as u64);
+        }
+        let mut blocks: HashMap<String, (Grid, u64)> = HashMap::new();
+        for block in try!(grid.subgrids()) {
+            blocks.entry(block.key()).or_insert_with(|| (block, 0)).1 += 1;
+        }
+        let mut cache: HashMap<String, Vec<Grid>> = HashMap::new();
+        while iterations >= 3 {
+            let mut next: HashMap<String, (Grid, u64)> = HashMap::new();
+            for (key, (block, count)) in blocks {
+                if !cache.contains_key(&key) {
+                    let mut grid = block;
+                    for _ in 0..3 { grid = try!(self.apply(&grid)); }
+                    cache.insert(key.clone(), try!(grid.subgrids()));
+                }
+                for block in &cache[&key] {
+                    next.entry(block.key()).or_insert_with(|| (block.clone(), 0)).1 += count;
+                }
+            }
+            blocks = next;
+            iterations -= 3;
+        }
+        let mut lit = 0;
+        for (_, (block, count)) in blocks {
+            let mut grid = block;
+            for _ in 0..iterations { grid = try!(self.apply(&grid)); }
+            lit += grid.lit_pixels() as u64 * count;
+        }
+        Ok(lit)
+    }
+}
+
+
+/// Iterator over successive enhancements of a grid, stops after the first error
+struct Enhancements<'a> {
+    book: &'a Book,
+    grid: Option<Grid>,
+}
+
+impl<'a> Iterator for Enhancements<'a> {
+    type Item = Result<Grid, ApplyError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.grid.take().map(|grid| {
+            let result = self.book.apply(&grid);
+            if let Ok(ref next) = result {
+                self.grid = Some(next.clone());
+            }
+            result
+        })
     }
 }
 
 
+/// Usage: day21 [rules file] [start pattern] [number of generations to write as PBM files]
 fn main() {
-    let book: Book = include_str!("day21.txt").parse().unwrap();
-    let mut grid = Grid::new();
-    for _ in 0..5 { grid = book.apply(&grid).unwrap(); }
-    println!("Lit pixels after 5 iterations: {}", grid.lit_pixels());
-    for _ in 5..18 { grid = book.apply(&grid).unwrap(); }
-    println!("Lit pixels after 18 iterations: {}", grid.lit_pixels());
+    let args: Vec<String> = env::args().skip(1).collect();
+    let book: Book = match args.first() {
+        Some(path) => fs::read_to_string(path).expect("Unable to read rules file").parse(),
+        None => include_str!("day21.txt").parse(),
+    }.unwrap_or_else(|e| panic!("{}", e));
+    let grid = args.get(1).map_or_else(|| Ok(Grid::new()), |s| Grid::from_pattern(s))
+        .unwrap_or_else(|e| panic!("Invalid start pattern: {}", e));
+    println!("Lit pixels after 5 iterations: {}", book.lit_pixels_after(&grid, 5).unwrap_or_else(|e| panic!("{}", e)));
+    println!("Lit pixels after 18 iterations: {}", book.lit_pixels_after(&grid, 18).unwrap_or_else(|e| panic!("{}", e)));
+    if let Some(generations) = args.get(2).map(|s| s.parse().expect("Invalid number of generations")) {
+        book.render_iterations(&grid, generations, Path::new(".")).expect("Unable to write PBM files");
+    }
 }
 
 
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "nightly")]
+    extern crate test;
+
     use super::*;
 
     #[test]
@@ -217,16 +709,96 @@ mod tests {
         let _book = Book::from_str("../.# => ##./#../...\n.#./..#/### => #..#/..../..../#..#\n").unwrap();
     }
 
+    #[test]
+    fn grid_notation() {
+        for s in &["#", "../.#", ".#./..#/###", "#..#/..../..../#..#", "##.##./#..#../....../##.##./#..#../......"] {
+            let grid: Grid = s.parse().unwrap();
+            assert_eq!(grid.to_string(), *s);
+            let multiline = format!("{:#}", grid);
+            assert_eq!(multiline, s.replace('/', "\n"));
+            assert_eq!(multiline.parse(), Ok(grid));
+        }
+        assert_eq!(".#.\n..#\n###\n".parse(), Ok(Grid::new()));
+        assert_eq!("".parse::<Grid>(), Err(ParseError::Empty));
+        assert_eq!("../.#/..".parse::<Grid>(), Err(ParseError::NotSquare { row: 1, len: 2, size: 3 }));
+        assert_eq!("../.".parse::<Grid>(), Err(ParseError::NotSquare { row: 2, len: 1, size: 2 }));
+        assert_eq!("../.x".parse::<Grid>(), Err(ParseError::InvalidChar { row: 2, col: 2, ch: 'x' }));
+        assert_eq!(Rule::from_str("../.#").unwrap_err(), ParseError::MissingArrow);
+        assert_eq!(Rule::from_str("../.# => ##./#../..").unwrap_err(), ParseError::NotSquare { row: 3, len: 2, size: 3 });
+    }
+
+    #[test]
+    fn rendering() {
+        let mut pbm = vec![];
+        Grid::new().write_pbm(&mut pbm).unwrap();
+        assert_eq!(pbm, b"P1\n3 3\n010\n001\n111\n");
+        let grid = Grid::from_pattern("#..#/..../..../#..#").unwrap();
+        assert_eq!(grid.to_unicode(), "▀  ▀\n▄  ▄\n");
+        assert_eq!(Grid::new().to_unicode(), " ▀▄\n▀▀▀\n");
+        let mut pbm = vec![];
+        Grid::empty(71).write_pbm(&mut pbm).unwrap();
+        assert!(String::from_utf8(pbm).unwrap().lines().all(|line| line.len() <= 70));
+    }
+
+    #[test]
+    fn render_files() {
+        let book = Book::from_str("../.# => ##./#../...\n.#./..#/### => #..#/..../..../#..#\n").unwrap();
+        let dir = env::temp_dir().join(format!("day21-render-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        book.render_iterations(&Grid::new(), 2, &dir).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("iter1.pbm")).unwrap(), "P1\n4 4\n1001\n0000\n0000\n1001\n");
+        assert!(dir.join("iter2.pbm").exists());
+        assert!(!dir.join("iter3.pbm").exists());
+        assert!(book.render_iterations(&Grid::new(), 3, &dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn composition() {
+        let mut seed = 21;
+        for &size in &[2, 3, 6, 8, 12] {
+            let grid = Grid::from_pixels((0..size).map(|_|
+                (0..size).map(|_| random(&mut seed) & 1 == 0).collect()
+            ).collect());
+            for &block in &[1, 2, 3, 4, 6] {
+                if size % block != 0 {
+                    assert_eq!(grid.partition(block), Err(PartitionError::BlockSize { size: size, block: block }));
+                    continue;
+                }
+                let n = size / block;
+                let crops: Vec<Grid> = (0..n * n).map(|i|
+                    grid.crop(i / n * block, i % n * block, block).unwrap()
+                ).collect();
+                assert_eq!(Grid::build(&crops), Ok(grid.clone()));
+                assert_eq!(grid.partition(block), Ok(crops));
+            }
+            assert_eq!(grid.crop(0, 0, size), Ok(grid.clone()));
+            assert_eq!(grid.crop(1, 0, size), Err(GridError::OutOfBounds { row: 1, col: 0, size: size, grid_size: size }));
+            assert_eq!(grid.crop(0, 0, 0), Err(GridError::ZeroSize));
+            assert_eq!(grid.pad(2, false).crop(2, 2, size), Ok(grid.clone()));
+        }
+        assert_eq!(Grid::new().pad(1, false).to_string(), "...../..#../...#./.###./.....");
+        assert_eq!(Grid::new().pad(1, true).to_string(), "#####/#.#.#/#..##/#####/#####");
+        assert_eq!(Grid::new().pad(0, true), Grid::new());
+        let grid = Grid::from_pattern("##../##../..#./...#").unwrap().pad(2, false);
+        let blocks = grid.partition(4).unwrap();
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(blocks[0].to_string(), "..../..../..##/..##");
+        assert_eq!(blocks[3].to_string(), "#.../.#../..../....");
+        assert_eq!(Grid::build(&blocks), Ok(grid));
+        assert_eq!(Grid::new().partition(0), Err(PartitionError::BlockSize { size: 3, block: 0 }));
+    }
+
     #[test]
     fn divide_and_merge() {
         let grids: Vec<Grid> = (0..4).map(|_| Grid::new()).collect();
-        assert_eq!(grids[0].pixels, vec![
+        assert_eq!(grids[0].pixels(), vec![
             vec![false,  true, false],
             vec![false, false,  true],
             vec![ true,  true,  true]
         ]);
-        let grid = Grid::build(&grids);
-        assert_eq!(grid.pixels, vec![
+        let grid = Grid::build(&grids).unwrap();
+        assert_eq!(grid.pixels(), vec![
             vec![false,  true, false, false,  true, false],
             vec![false, false,  true, false, false,  true],
             vec![ true,  true,  true,  true,  true,  true],
@@ -236,28 +808,39 @@ mod tests {
         ]);
     }
 
+    #[test]
+    fn build_errors() {
+        assert_eq!(Grid::build(&[]), Err(BuildError::Empty));
+        let grids: Vec<Grid> = (0..8).map(|_| Grid::new()).collect();
+        assert_eq!(Grid::build(&grids), Err(BuildError::NotSquare(8)));
+        let mut grids: Vec<Grid> = (0..4).map(|_| Grid::new()).collect();
+        grids[2] = "../.#".parse().unwrap();
+        assert_eq!(Grid::build(&grids), Err(BuildError::MixedSizes { index: 2, size: 2, expected: 3 }));
+        assert_eq!(Grid::build(&grids[..1]), Ok(Grid::new()));
+    }
+
     #[test]
     fn rotation() {
         let grid = Grid::new();
-        assert_eq!(grid.pixels, vec![
+        assert_eq!(grid.pixels(), vec![
             vec![false,  true, false],
             vec![false, false,  true],
             vec![ true,  true,  true]
         ]);
         let grid = grid.rotate();
-        assert_eq!(grid.pixels, vec![
+        assert_eq!(grid.pixels(), vec![
             vec![false,  true,  true],
             vec![ true, false,  true],
             vec![false, false,  true]
         ]);
         let grid = grid.rotate();
-        assert_eq!(grid.pixels, vec![
+        assert_eq!(grid.pixels(), vec![
             vec![ true,  true,  true],
             vec![ true, false, false],
             vec![false,  true, false]
         ]);
         let grid = grid.rotate();
-        assert_eq!(grid.pixels, vec![
+        assert_eq!(grid.pixels(), vec![
             vec![ true, false, false],
             vec![ true, false,  true],
             vec![ true,  true, false]
@@ -267,19 +850,62 @@ mod tests {
     #[test]
     fn mirroring() {
         let grid = Grid::new();
-        assert_eq!(grid.pixels, vec![
+        assert_eq!(grid.pixels(), vec![
             vec![false,  true, false],
             vec![false, false,  true],
             vec![ true,  true,  true]
         ]);
         let grid = grid.mirror();
-        assert_eq!(grid.pixels, vec![
+        assert_eq!(grid.pixels(), vec![
             vec![false,  true, false],
             vec![ true, false, false],
             vec![ true,  true,  true]
         ]);
     }
 
+    #[test]
+    fn orientations() {
+        let grid = Grid::new();
+        let orientations = grid.orientations();
+        assert_eq!(orientations.len(), 8);
+        assert_eq!(orientations[1], grid.rotate());
+        assert!(orientations.contains(&grid.mirror()));
+        assert!(grid.matches(&grid.mirror().rotate()));
+        assert_eq!(grid.key(), ".#./..#/###");
+    }
+
+    /// Pseudo random numbers (xorshift)
+    fn random(seed: &mut u32) -> u32 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 17;
+        *seed ^= *seed << 5;
+        *seed
+    }
+
+    #[test]
+    fn canonical_forms() {
+        let mut seed = 2017;
+        for _ in 0..200 {
+            let size = 2 + random(&mut seed) as usize % 4;
+            let grid = Grid::from_pixels((0..size).map(|_|
+                (0..size).map(|_| random(&mut seed) & 1 == 0).collect()
+            ).collect());
+            let mut other = grid.clone();
+            for _ in 0..random(&mut seed) % 4 { other = other.rotate(); }
+            if random(&mut seed) & 1 == 0 { other = other.mirror(); }
+            assert!(grid.matches(&other));
+            assert!(other.matches(&grid));
+            let canonical = grid.canonical();
+            assert!(grid.matches(&canonical));
+            for orientation in grid.orientations() {
+                assert_eq!(orientation.canonical_key(), grid.canonical_key());
+                assert_eq!(orientation.canonical(), canonical);
+            }
+        }
+        assert!(!Grid::new().matches(&"../.#".parse().unwrap()));
+        assert!(!Grid::new().matches(&"###/.../...".parse().unwrap()));
+    }
+
     #[test]
     fn samples() {
         let book = Book::from_str("../.# => ##./#../...\n.#./..#/### => #..#/..../..../#..#\n").unwrap();
@@ -289,5 +915,173 @@ mod tests {
         let grid = book.apply(&grid).unwrap();
         assert_eq!(grid.size(), 4);
         assert_eq!(grid.lit_pixels(), 4);
+        let grid = book.apply(&grid).unwrap();
+        assert_eq!(grid.size(), 6);
+        assert_eq!(grid.lit_pixels(), 12);
+    }
+
+    #[test]
+    fn start_patterns() {
+        let book = Book::from_str("../.# => ##./#../...\n.#./..#/### => #..#/..../..../#..#\n").unwrap();
+        assert_eq!(Grid::from_pattern(".#./..#/###"), Ok(Grid::new()));
+        assert_eq!(book.lit_pixels_after(&Grid::new(), 2), Ok(12));
+        let grid = Grid::from_pattern("#..#/..../..../#..#").unwrap();
+        let grid = book.apply(&grid).unwrap();
+        assert_eq!(grid.size(), 6);
+        assert_eq!(grid.lit_pixels(), 12);
+        let grid = Grid::from_pattern("#..../...../..#../...../....#").unwrap();
+        assert_eq!(grid.subgrids(), Err(PartitionError::NoBlockSize(5)));
+        assert_eq!(book.apply(&grid), Err(ApplyError::Partition(PartitionError::NoBlockSize(5))));
+        assert_eq!(book.lit_pixels_after(&grid, 1).unwrap_err().to_string(), "Couldn't partition grid of size 5");
+        assert_eq!(book.lit_pixels_after(&grid, 0), Ok(3));
+    }
+
+    #[test]
+    fn enhancements() {
+        let book = Book::from_str("../.# => ##./#../...\n.#./..#/### => #..#/..../..../#..#\n").unwrap();
+        let mut iter = book.iterate(Grid::new());
+        let grid = iter.next().unwrap().unwrap();
+        assert_eq!(grid.size(), 4);
+        assert_eq!(grid.lit_pixels(), 4);
+        assert_eq!(iter.next().unwrap().unwrap().lit_pixels(), 12);
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+        assert_eq!(book.lit_after(&Grid::new(), 0), Ok(5));
+        assert_eq!(book.lit_after(&Grid::new(), 2), Ok(12));
+        assert!(book.lit_after(&Grid::new(), 3).is_err());
+        assert_eq!(book.lit_after(&Grid::new(), 4), book.lit_after(&Grid::new(), 3));
+        let book: Book = include_str!("day21.txt").parse().unwrap();
+        let lit: Vec<_> = book.iterate(Grid::new()).take(5).map(|grid| grid.unwrap().lit_pixels()).collect();
+        assert_eq!(lit[4], 179);
+        assert_eq!(book.lit_after(&Grid::new(), 5), Ok(179));
+    }
+
+    #[test]
+    fn validation() {
+        let book: Book = include_str!("day21.txt").parse().unwrap();
+        assert_eq!(book.validate(), vec![]);
+        let book = Book::from_str("../.# => ##./#../...\n.#./..#/### => #..#/..../..../#..#\n").unwrap();
+        let issues = book.validate();
+        assert_eq!(issues.len(), 6 - 1 + 102 - 1);
+        assert!(issues.iter().all(|issue| matches!(*issue, BookIssue::Uncovered { .. })));
+        assert!(issues.contains(&BookIssue::Uncovered { pattern: ".#/##".to_owned() }));
+        let book = Book::from_str("../.# => ##./#../...\n#./.. => ##./#../...\n.#/.. => ###/#../...\n../.# => ##./#../...\n").unwrap();
+        let issues = book.validate();
+        assert_eq!(issues[0], BookIssue::Duplicate { first: 1, second: 2 });
+        assert_eq!(issues[1], BookIssue::Conflict { first: 1, second: 3, pattern: "../.#".to_owned() });
+        assert_eq!(issues[2], BookIssue::Duplicate { first: 1, second: 4 });
+        assert_eq!(issues[1].to_string(), "Rules in lines 1 and 3 both match ../.# with different replacements");
+    }
+
+    #[test]
+    fn missing_rule() {
+        let book = Book::from_str("../.# => ##./#../...\n.#./..#/### => #..#/..../..../#..#\n").unwrap();
+        let grid = book.apply(&book.apply(&Grid::new()).unwrap()).unwrap();
+        let err = book.apply(&grid).unwrap_err();
+        assert_eq!(err, ApplyError::NoMatch { pattern: "##/#.".to_owned(), size: 2, row: 0, col: 0 });
+        assert_eq!(err.to_string(), "No rule matches 2x2 subgrid ##/#. at block 0,0");
+    }
+
+    #[test]
+    fn counting() {
+        let book = Book::from_str("../.# => ##./#../...\n.#./..#/### => #..#/..../..../#..#\n").unwrap();
+        let mut grid = Grid::new();
+        for i in 0..10 {
+            match book.apply(&grid) {
+                Ok(next) => {
+                    assert_eq!(book.lit_pixels_after(&Grid::new(), i), Ok(grid.lit_pixels() as u64));
+                    grid = next;
+                }
+                Err(err) => {
+                    // The sample book lacks rules for later iterations
+                    assert_eq!(book.lit_pixels_after(&Grid::new(), i + 1), Err(err));
+                    break;
+                }
+            }
+        }
+        let book: Book = include_str!("day21.txt").parse().unwrap();
+        let mut grid = Grid::new();
+        for i in 0..13 {
+            assert_eq!(book.lit_pixels_after(&Grid::new(), i), Ok(grid.lit_pixels() as u64));
+            grid = book.apply(&grid).unwrap();
+        }
+        let grid = Grid::from_pixels(vec![vec![true, false], vec![false, false]]);
+        let mut explicit = grid.clone();
+        for i in 0..10 {
+            assert_eq!(book.lit_pixels_after(&grid, i), Ok(explicit.lit_pixels() as u64));
+            explicit = book.apply(&explicit).unwrap();
+        }
+        assert!(book.lit_pixels_after(&Grid::new(), 45).is_ok());
+    }
+
+    #[test]
+    fn lookup() {
+        let book: Book = include_str!("day21.txt").parse().unwrap();
+        let mut grid = Grid::new();
+        for _ in 0..5 {
+            let subgrids = grid.subgrids().unwrap();
+            let replaced: Vec<Grid> = subgrids.iter().map(|g|
+                book.rules.iter().find(|rule| g.matches(&rule.search)).unwrap().replace.clone()
+            ).collect();
+            assert_eq!(subgrids.iter().map(|g| book.matches(g).unwrap()).collect::<Vec<_>>(), replaced);
+            grid = Grid::build(&replaced).unwrap();
+        }
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_apply(b: &mut test::Bencher) {
+        let book: Book = include_str!("day21.txt").parse().unwrap();
+        b.iter(|| book.lit_after(&Grid::new(), 18).unwrap())
+    }
+
+    #[test]
+    fn parallel() {
+        let book: Book = include_str!("day21.txt").parse().unwrap();
+        let mut grid = Grid::new();
+        for _ in 0..10 {
+            let next = book.apply(&grid).unwrap();
+            for threads in 0..6 {
+                assert_eq!(book.apply_parallel(&grid, threads), Ok(next.clone()));
+            }
+            grid = next;
+        }
+        let book = Book::from_str("../.# => ##./#../...\n.#./..#/### => #..#/..../..../#..#\n").unwrap();
+        let grid = book.apply(&book.apply(&Grid::new()).unwrap()).unwrap();
+        for threads in 1..6 {
+            assert_eq!(book.apply_parallel(&grid, threads), book.apply(&grid));
+        }
+        let grid = Grid::from_pattern("##../##../..../....").unwrap();
+        let err = ApplyError::NoMatch { pattern: "##/##".to_owned(), size: 2, row: 0, col: 0 };
+        assert_eq!(book.apply_parallel(&grid, 4), Err(err));
+        let grid = Grid::from_pattern("#.#./..../#.##/..##").unwrap();
+        let err = ApplyError::NoMatch { pattern: "##/##".to_owned(), size: 2, row: 1, col: 1 };
+        assert_eq!(book.apply_parallel(&grid, 3), Err(err));
+    }
+
+    #[cfg(feature = "nightly")]
+    fn benchmark_parallel(b: &mut test::Bencher, threads: usize) {
+        let book: Book = include_str!("day21.txt").parse().unwrap();
+        let mut grid = Grid::new();
+        for _ in 0..15 { grid = book.apply(&grid).unwrap(); }
+        b.iter(|| book.apply_parallel(&grid, threads).unwrap().lit_pixels())
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_parallel_1(b: &mut test::Bencher) {
+        benchmark_parallel(b, 1)
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_parallel_2(b: &mut test::Bencher) {
+        benchmark_parallel(b, 2)
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_parallel_4(b: &mut test::Bencher) {
+        benchmark_parallel(b, 4)
     }
 }
@@ -1,4 +1,8 @@
-use std::collections::HashMap;
+#![cfg_attr(feature = "nightly", feature(test))]
+
+use std::{cmp, fmt};
+use std::collections::VecDeque;
+use std::io::{self, Write};
 use std::str::FromStr;
 
 
@@ -34,6 +38,34 @@ impl Direction {
             Direction::East => Direction::West,
         }
     }
+
+    /// Position of the next node in this direction
+    fn step(&self, row: isize, col: isize) -> (isize, isize) {
+        match *self {
+            Direction::North => (row - 1, col),
+            Direction::West => (row, col - 1),
+            Direction::South => (row + 1, col),
+            Direction::East => (row, col + 1),
+        }
+    }
+}
+
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Turn {
+    Left, Right, Reverse, Straight,
+}
+
+impl Turn {
+    /// Direction after turning from the given direction
+    fn apply(&self, dir: Direction) -> Direction {
+        match *self {
+            Turn::Left => dir.left(),
+            Turn::Right => dir.right(),
+            Turn::Reverse => dir.reverse(),
+            Turn::Straight => dir,
+        }
+    }
 }
 
 
@@ -42,125 +74,614 @@ enum State {
     Clean, Weakened, Infected, Flagged,
 }
 
+
+/// Turn to take and next state for nodes in each state
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct RuleSet {
+    rules: [(Turn, State); 4],
+}
+
+impl RuleSet {
+    /// Create rule set from the turn and next state for Clean, Weakened, Infected
+    /// and Flagged nodes (in this order)
+    fn new(rules: [(Turn, State); 4]) -> RuleSet {
+        RuleSet { rules: rules }
+    }
+
+    /// Clean nodes become infected and infected nodes become clean. Weakened nodes
+    /// (which these rules never create) are treated like clean nodes and Flagged nodes
+    /// like infected nodes, so clusters left by the evolved rules can be continued.
+    fn basic() -> RuleSet {
+        RuleSet::new([
+            (Turn::Left, State::Infected),
+            (Turn::Left, State::Infected),
+            (Turn::Right, State::Clean),
+            (Turn::Right, State::Clean),
+        ])
+    }
+
+    /// Nodes cycle through Clean, Weakened, Infected and Flagged states
+    fn evolved() -> RuleSet {
+        RuleSet::new([
+            (Turn::Left, State::Weakened),
+            (Turn::Straight, State::Infected),
+            (Turn::Right, State::Flagged),
+            (Turn::Reverse, State::Clean),
+        ])
+    }
+
+    /// Turn to take and next state for a node in the given state
+    fn rule(&self, state: State) -> (Turn, State) {
+        self.rules[state as usize]
+    }
+}
+
+/// Cluster of nodes, stored densely in a square region centered on the origin
+/// that grows when nodes outside of it are set
 #[derive(Debug)]
 struct Cluster {
-    states: HashMap<(isize, isize), State>,
+    states: Vec<State>,
+    /// Distance of the region's edges from the origin
+    radius: isize,
+    /// Number of nodes in the region in each state
+    counts: [usize; 4],
+    /// Smallest row and column and largest row and column of nodes set so far
+    extent: (isize, isize, isize, isize),
+}
+
+#[derive(Debug, PartialEq)]
+enum ParseError {
+    /// No lines at all
+    Empty,
+    /// Line (1-based) with a different length than the first line
+    Ragged { line: usize, len: usize, width: usize },
+    /// Row and column (1-based) of a character other than `.` or `#`
+    InvalidChar { row: usize, col: usize, ch: char },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::Empty => write!(f, "Empty map"),
+            ParseError::Ragged { line, len, width } =>
+                write!(f, "Line {} has {} nodes, expected {}", line, len, width),
+            ParseError::InvalidChar { row, col, ch } =>
+                write!(f, "Row {}, column {}: invalid node {:?}", row, col, ch),
+        }
+    }
 }
 
 impl FromStr for Cluster {
-    type Err = ();
+    type Err = ParseError;
 
+    /// Parse map of infected nodes. The node at row height/2 and column width/2
+    /// (counting from 0) is the origin, i.e. for even sizes the origin is the node
+    /// right below and to the right of the map's center.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let height = s.lines().count();
-        let width = s.lines().next().unwrap().len();
-        let mut states = HashMap::new();
-        for (row, line) in s.lines().enumerate() {
+        let lines: Vec<&str> = s.lines().collect();
+        let height = lines.len();
+        let width = try!(lines.first().ok_or(ParseError::Empty)).chars().count();
+        let mut cluster = Cluster::with_radius(cmp::max(height, width) as isize / 2 + 1);
+        for (row, line) in lines.iter().enumerate() {
+            let len = line.chars().count();
+            if len != width {
+                return Err(ParseError::Ragged { line: row + 1, len: len, width: width });
+            }
             for (col, ch) in line.chars().enumerate() {
-                if ch == '#' {
-                    states.insert((row as isize - height as isize / 2, col as isize - width as isize / 2), State::Infected);
+                match ch {
+                    '#' => cluster.set(row as isize - height as isize / 2, col as isize - width as isize / 2, State::Infected),
+                    '.' => (),
+                    _ => return Err(ParseError::InvalidChar { row: row + 1, col: col + 1, ch: ch }),
                 }
             }
         }
-        Ok(Cluster { states: states })
+        Ok(cluster)
+    }
+}
+
+impl fmt::Display for Cluster {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.render((0, 0), 4, None))
     }
 }
 
 impl Cluster {
+    /// Create clean cluster covering the given distance from the origin
+    fn with_radius(radius: isize) -> Cluster {
+        let side = (2 * radius + 1) as usize;
+        Cluster { states: vec![State::Clean; side * side], radius: radius, counts: [side * side, 0, 0, 0], extent: (0, 0, 0, 0) }
+    }
+
+    /// Index of the given node in the region, if it's covered
+    fn index(&self, row: isize, col: isize) -> Option<usize> {
+        if row.abs() > self.radius || col.abs() > self.radius { return None; }
+        let side = 2 * self.radius + 1;
+        Some(((row + self.radius) * side + col + self.radius) as usize)
+    }
+
+    /// Grow the region to cover at least the given distance from the origin
+    fn grow(&mut self, radius: isize) {
+        let mut cluster = Cluster::with_radius(cmp::max(radius, 2 * self.radius));
+        for row in -self.radius..self.radius + 1 {
+            for col in -self.radius..self.radius + 1 {
+                let state = self.get(row, col);
+                if state != State::Clean { cluster.set(row, col, state); }
+            }
+        }
+        cluster.extent = self.extent;
+        *self = cluster;
+    }
+
     fn get(&self, row: isize, col: isize) -> State {
-        self.states.get(&(row, col)).map_or(State::Clean, |&s| s)
+        self.index(row, col).map_or(State::Clean, |i| self.states[i])
     }
 
     fn set(&mut self, row: isize, col: isize, state: State) {
-        self.states.insert((row, col), state);
+        if self.index(row, col).is_none() {
+            self.grow(cmp::max(row.abs(), col.abs()));
+        }
+        let i = self.index(row, col).unwrap();
+        let (min_row, min_col, max_row, max_col) = self.extent;
+        self.extent = (cmp::min(min_row, row), cmp::min(min_col, col), cmp::max(max_row, row), cmp::max(max_col, col));
+        self.counts[self.states[i] as usize] -= 1;
+        self.counts[state as usize] += 1;
+        self.states[i] = state;
+    }
+
+    /// Smallest row and column and largest row and column of nodes set so far
+    /// (including the origin)
+    fn extent(&self) -> (isize, isize, isize, isize) {
+        self.extent
+    }
+
+    /// Number of nodes in the given state. As there's an infinite number of clean
+    /// nodes, only those in the currently stored region are counted for Clean.
+    #[allow(dead_code)]
+    fn count(&self, state: State) -> usize {
+        self.counts[state as usize]
+    }
+
+    /// Render nodes within the given distance from the center like in the puzzle
+    /// description, with the carrier's node in brackets (its direction isn't shown)
+    fn render(&self, center: (isize, isize), radius: isize, carrier: Option<((isize, isize), Direction)>) -> String {
+        let carrier = carrier.map(|(position, _)| position);
+        let mut s = String::new();
+        for row in center.0 - radius..center.0 + radius + 1 {
+            for col in center.1 - radius..center.1 + radius + 1 {
+                if carrier == Some((row, col)) {
+                    s.push('[');
+                } else if carrier == Some((row, col - 1)) {
+                    s.push(']');
+                } else if col > center.1 - radius {
+                    s.push(' ');
+                }
+                s.push(match self.get(row, col) {
+                    State::Clean => '.',
+                    State::Weakened => 'W',
+                    State::Infected => '#',
+                    State::Flagged => 'F',
+                });
+            }
+            if carrier == Some((row, center.1 + radius)) { s.push(']'); }
+            s.push('\n');
+        }
+        s
     }
 
     fn carrier_mut(&mut self) -> Carrier {
-        Carrier { cluster: self, row: 0, col: 0, dir: Direction::North }
+        self.carrier_with(RuleSet::basic())
     }
 
     fn carrier_advanced_mut(&mut self) -> CarrierAdvanced {
-        CarrierAdvanced { cluster: self, row: 0, col: 0, dir: Direction::North }
+        self.carrier_with(RuleSet::evolved())
+    }
+
+    /// Carrier starting at the origin facing north, using the given rules
+    fn carrier_with(&mut self, rules: RuleSet) -> Carrier<'_> {
+        Carrier { cluster: self, rules: rules, state: CarrierState::new(), trail: None }
+    }
+
+    /// Given number of carriers starting at the origin facing north, using the given rules
+    #[allow(dead_code)]
+    fn multi_carrier(&mut self, n: usize, rules: RuleSet) -> MultiCarrier<'_> {
+        MultiCarrier { cluster: self, rules: rules, carriers: vec![CarrierState::new(); n] }
+    }
+
+    /// Carrier continuing from the given saved state, using the given rules
+    #[allow(dead_code)]
+    fn resume_carrier(&mut self, state: &CarrierState, rules: RuleSet) -> Carrier<'_> {
+        Carrier { cluster: self, rules: rules, state: state.clone(), trail: None }
+    }
+
+    /// Save all nodes that aren't clean
+    #[allow(dead_code)]
+    fn snapshot(&self) -> ClusterState {
+        let mut nodes = vec![];
+        for row in -self.radius..self.radius + 1 {
+            for col in -self.radius..self.radius + 1 {
+                let state = self.get(row, col);
+                if state != State::Clean { nodes.push((row, col, state)); }
+            }
+        }
+        ClusterState { nodes: nodes, carrier: None }
+    }
+
+    /// Create cluster from saved nodes
+    #[allow(dead_code)]
+    fn restore(state: &ClusterState) -> Cluster {
+        let mut cluster = Cluster::with_radius(1);
+        for &(row, col, node) in &state.nodes {
+            cluster.set(row, col, node);
+        }
+        cluster
     }
 }
 
 
-#[derive(Debug)]
-struct Carrier<'a> {
-    cluster: &'a mut Cluster,
+/// Details of a single burst of activity
+#[derive(Debug, PartialEq)]
+struct Burst {
+    /// Position of the node the burst happened on
     row: isize,
     col: isize,
+    /// Direction the carrier turned to
     dir: Direction,
+    /// State of the node before and after the burst
+    old: State,
+    new: State,
+    /// Whether the burst caused the node to become infected
+    infected: bool,
+}
+
+
+/// Position of a burst and the state written there
+#[derive(Debug, PartialEq)]
+struct TrailEntry {
+    row: isize,
+    col: isize,
+    state: State,
+}
+
+/// Ring buffer of the most recent trail entries
+#[derive(Debug)]
+struct Trail {
+    capacity: usize,
+    entries: VecDeque<TrailEntry>,
+}
+
+
+#[derive(Debug)]
+struct Carrier<'a> {
+    cluster: &'a mut Cluster,
+    rules: RuleSet,
+    state: CarrierState,
+    trail: Option<Trail>,
+}
+
+impl<'a> Carrier<'a> {
+    /// Record the given number of most recent bursts
+    #[allow(dead_code)]
+    fn with_trail(mut self, capacity: usize) -> Carrier<'a> {
+        self.trail = Some(Trail { capacity: capacity, entries: VecDeque::with_capacity(capacity) });
+        self
+    }
+
+    /// Recorded bursts, oldest first (empty if not recording)
+    #[allow(dead_code)]
+    fn trail(&self) -> impl Iterator<Item = &TrailEntry> {
+        self.trail.iter().flat_map(|trail| trail.entries.iter())
+    }
+
+    /// Current position of the carrier
+    #[allow(dead_code)]
+    fn position(&self) -> (isize, isize) {
+        (self.state.row, self.state.col)
+    }
+
+    /// Direction the carrier is facing
+    #[allow(dead_code)]
+    fn direction(&self) -> Direction {
+        self.state.dir
+    }
+
+    /// Cluster the carrier is working on
+    #[allow(dead_code)]
+    fn cluster(&self) -> &Cluster {
+        self.cluster
+    }
+
+    /// Number of bursts done so far
+    #[allow(dead_code)]
+    fn bursts_done(&self) -> usize {
+        self.state.bursts
+    }
+
+    /// Number of bursts so far that caused a node to become infected
+    #[allow(dead_code)]
+    fn infections_caused(&self) -> usize {
+        self.state.infections
+    }
+
+    /// Save the cluster together with the carrier's state
+    #[allow(dead_code)]
+    fn snapshot(&self) -> ClusterState {
+        let mut state = self.cluster.snapshot();
+        state.carrier = Some(self.state.clone());
+        state
+    }
+
+    /// Do a single burst of activity
+    fn burst(&mut self) -> Burst {
+        let burst = self.state.burst(self.cluster, &self.rules);
+        if let Some(ref mut trail) = self.trail {
+            if trail.entries.len() == trail.capacity { trail.entries.pop_front(); }
+            if trail.capacity > 0 {
+                trail.entries.push_back(TrailEntry { row: burst.row, col: burst.col, state: burst.new });
+            }
+        }
+        burst
+    }
+
+    /// Do the given number of bursts, taking a sample every given number of bursts
+    /// and after the last one
+    #[allow(dead_code)]
+    fn run_sampled(&mut self, bursts: usize, every: usize) -> Vec<Sample> {
+        let mut samples = vec![];
+        for i in 1..bursts + 1 {
+            self.burst();
+            if i == bursts || (every > 0 && i % every == 0) {
+                samples.push(Sample {
+                    burst: self.state.bursts,
+                    infections_so_far: self.state.infections,
+                    currently_infected: self.cluster.count(State::Infected),
+                    extent: self.cluster.extent(),
+                });
+            }
+        }
+        samples
+    }
+}
+
+
+/// Progress of a carrier after the given number of bursts
+#[derive(Debug, PartialEq)]
+struct Sample {
+    burst: usize,
+    infections_so_far: usize,
+    currently_infected: usize,
+    /// Extent of the cluster's nodes set so far
+    extent: (isize, isize, isize, isize),
+}
+
+/// Write samples as CSV with a header line
+#[allow(dead_code)]
+fn write_series_csv<W: Write>(mut w: W, samples: &[Sample]) -> io::Result<()> {
+    try!(writeln!(w, "burst,infections_so_far,currently_infected,min_row,min_col,max_row,max_col"));
+    for sample in samples {
+        let (min_row, min_col, max_row, max_col) = sample.extent;
+        try!(writeln!(w, "{},{},{},{},{},{},{}", sample.burst, sample.infections_so_far,
+            sample.currently_infected, min_row, min_col, max_row, max_col));
+    }
+    Ok(())
 }
 
 impl<'a> Iterator for Carrier<'a> {
     type Item = bool;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let infected = match self.cluster.get(self.row, self.col) {
-            State::Clean => {
-                self.dir = self.dir.left();
-                self.cluster.set(self.row, self.col, State::Infected);
-                true
-            }
-            State::Infected => {
-                self.dir = self.dir.right();
-                self.cluster.set(self.row, self.col, State::Clean);
-                false
-            },
-            State::Weakened => unreachable!(),
-            State::Flagged => unreachable!(),
-        };
-        match self.dir {
-            Direction::North => self.row -= 1,
-            Direction::West => self.col -= 1,
-            Direction::South => self.row += 1,
-            Direction::East => self.col += 1,
-        }
-        Some(infected)
+        Some(self.burst().infected)
     }
 }
 
 
+/// Carrier using the evolved rules
+type CarrierAdvanced<'a> = Carrier<'a>;
+
+
+/// Multiple carriers working on the same cluster. In every burst, each carrier does
+/// a burst in turn (carrier 0 first), so a carrier on the same node as an earlier one
+/// sees the state the earlier carrier left there. Yields the indices of carriers
+/// that caused an infection.
 #[derive(Debug)]
-struct CarrierAdvanced<'a> {
+struct MultiCarrier<'a> {
     cluster: &'a mut Cluster,
+    rules: RuleSet,
+    carriers: Vec<CarrierState>,
+}
+
+impl<'a> MultiCarrier<'a> {
+    /// State of the carrier with the given index
+    #[allow(dead_code)]
+    fn carrier(&self, i: usize) -> &CarrierState {
+        &self.carriers[i]
+    }
+
+    /// Number of bursts that caused a node to become infected, by the carrier with the given index
+    #[allow(dead_code)]
+    fn infections_caused(&self, i: usize) -> usize {
+        self.carriers[i].infections
+    }
+
+    /// Number of bursts that caused a node to become infected, by all carriers
+    #[allow(dead_code)]
+    fn total_infections(&self) -> usize {
+        self.carriers.iter().map(|c| c.infections).sum()
+    }
+}
+
+impl<'a> Iterator for MultiCarrier<'a> {
+    type Item = Vec<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut infecting = vec![];
+        for (i, carrier) in self.carriers.iter_mut().enumerate() {
+            if carrier.burst(self.cluster, &self.rules).infected {
+                infecting.push(i);
+            }
+        }
+        Some(infecting)
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+enum SnapshotError {
+    /// Snapshot was written in an unknown format version
+    UnknownVersion(u8),
+    /// Snapshot ended unexpectedly
+    Truncated,
+    /// Invalid state or direction at the given byte offset
+    InvalidValue(usize),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SnapshotError::UnknownVersion(version) => write!(f, "Unknown snapshot version {}", version),
+            SnapshotError::Truncated => write!(f, "Snapshot is truncated"),
+            SnapshotError::InvalidValue(offset) => write!(f, "Invalid value at offset {}", offset),
+        }
+    }
+}
+
+
+/// Position, direction and counters of a carrier
+#[derive(Debug, PartialEq, Clone)]
+struct CarrierState {
     row: isize,
     col: isize,
     dir: Direction,
+    bursts: usize,
+    infections: usize,
 }
 
-impl<'a> Iterator for CarrierAdvanced<'a> {
-    type Item = bool;
+impl CarrierState {
+    /// Carrier at the origin facing north
+    fn new() -> CarrierState {
+        CarrierState { row: 0, col: 0, dir: Direction::North, bursts: 0, infections: 0 }
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let infected = match self.cluster.get(self.row, self.col) {
-            State::Clean => {
-                self.dir = self.dir.left();
-                self.cluster.set(self.row, self.col, State::Weakened);
-                false
+    /// Do a single burst of activity on the given cluster
+    fn burst(&mut self, cluster: &mut Cluster, rules: &RuleSet) -> Burst {
+        let (row, col) = (self.row, self.col);
+        let old = cluster.get(row, col);
+        let (turn, new) = rules.rule(old);
+        self.dir = turn.apply(self.dir);
+        cluster.set(row, col, new);
+        let (next_row, next_col) = self.dir.step(row, col);
+        self.row = next_row;
+        self.col = next_col;
+        self.bursts += 1;
+        let infected = old != State::Infected && new == State::Infected;
+        if infected { self.infections += 1; }
+        Burst { row: row, col: col, dir: self.dir, old: old, new: new, infected: infected }
+    }
+}
+
+/// Saved nodes that aren't clean (sorted by row and column), and optionally a carrier
+#[derive(Debug, PartialEq, Clone)]
+struct ClusterState {
+    nodes: Vec<(isize, isize, State)>,
+    carrier: Option<CarrierState>,
+}
+
+impl ClusterState {
+    /// Format version written by `to_bytes`
+    const VERSION: u8 = 1;
+
+    /// Serialize as version byte, node count and nodes (row, col, state), followed by
+    /// a flag byte and the carrier's row, col, direction, bursts and infections.
+    /// Numbers are little endian with 8 bytes, states and directions single bytes.
+    #[allow(dead_code)]
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![ClusterState::VERSION];
+        bytes.extend(&(self.nodes.len() as u64).to_le_bytes());
+        for &(row, col, state) in &self.nodes {
+            bytes.extend(&(row as i64).to_le_bytes());
+            bytes.extend(&(col as i64).to_le_bytes());
+            bytes.push(state as u8);
+        }
+        match self.carrier {
+            Some(ref carrier) => {
+                bytes.push(1);
+                bytes.extend(&(carrier.row as i64).to_le_bytes());
+                bytes.extend(&(carrier.col as i64).to_le_bytes());
+                bytes.push(carrier.dir as u8);
+                bytes.extend(&(carrier.bursts as u64).to_le_bytes());
+                bytes.extend(&(carrier.infections as u64).to_le_bytes());
             }
-            State::Weakened => {
-                self.cluster.set(self.row, self.col, State::Infected);
-                true
-            },
-            State::Infected => {
-                self.dir = self.dir.right();
-                self.cluster.set(self.row, self.col, State::Flagged);
-                false
-            },
-            State::Flagged => {
-                self.dir = self.dir.reverse();
-                self.cluster.set(self.row, self.col, State::Clean);
-                false
-            },
+            None => bytes.push(0),
+        }
+        bytes
+    }
+
+    /// Deserialize from bytes written by `to_bytes`
+    #[allow(dead_code)]
+    fn from_bytes(bytes: &[u8]) -> Result<ClusterState, SnapshotError> {
+        let mut reader = SnapshotReader { bytes: bytes, offset: 0 };
+        let version = try!(reader.byte());
+        if version != ClusterState::VERSION { return Err(SnapshotError::UnknownVersion(version)); }
+        let count = try!(reader.number()) as usize;
+        let mut nodes = Vec::with_capacity(cmp::min(count, bytes.len()));
+        for _ in 0..count {
+            let row = try!(reader.number()) as isize;
+            let col = try!(reader.number()) as isize;
+            nodes.push((row, col, try!(reader.state())));
+        }
+        let carrier = match try!(reader.byte()) {
+            0 => None,
+            1 => Some(CarrierState {
+                row: try!(reader.number()) as isize,
+                col: try!(reader.number()) as isize,
+                dir: try!(reader.direction()),
+                bursts: try!(reader.number()) as usize,
+                infections: try!(reader.number()) as usize,
+            }),
+            _ => return Err(SnapshotError::InvalidValue(reader.offset - 1)),
         };
-        match self.dir {
-            Direction::North => self.row -= 1,
-            Direction::West => self.col -= 1,
-            Direction::South => self.row += 1,
-            Direction::East => self.col += 1,
+        Ok(ClusterState { nodes: nodes, carrier: carrier })
+    }
+}
+
+/// Helper for reading values from a snapshot
+struct SnapshotReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> SnapshotReader<'a> {
+    fn byte(&mut self) -> Result<u8, SnapshotError> {
+        let byte = try!(self.bytes.get(self.offset).ok_or(SnapshotError::Truncated));
+        self.offset += 1;
+        Ok(*byte)
+    }
+
+    fn number(&mut self) -> Result<i64, SnapshotError> {
+        let mut buf = [0; 8];
+        for b in &mut buf {
+            *b = try!(self.byte());
+        }
+        Ok(i64::from_le_bytes(buf))
+    }
+
+    fn state(&mut self) -> Result<State, SnapshotError> {
+        match try!(self.byte()) {
+            0 => Ok(State::Clean),
+            1 => Ok(State::Weakened),
+            2 => Ok(State::Infected),
+            3 => Ok(State::Flagged),
+            _ => Err(SnapshotError::InvalidValue(self.offset - 1)),
+        }
+    }
+
+    fn direction(&mut self) -> Result<Direction, SnapshotError> {
+        match try!(self.byte()) {
+            0 => Ok(Direction::North),
+            1 => Ok(Direction::West),
+            2 => Ok(Direction::South),
+            3 => Ok(Direction::East),
+            _ => Err(SnapshotError::InvalidValue(self.offset - 1)),
         }
-        Some(infected)
     }
 }
 
@@ -178,6 +699,9 @@ fn main() {
 
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "nightly")]
+    extern crate test;
+
     use super::*;
 
     #[test]
@@ -186,6 +710,19 @@ mod tests {
         assert_eq!(cluster.get(-1, 1), State::Infected);
         assert_eq!(cluster.get(0, -1), State::Infected);
         assert_eq!(cluster.get(0, 0), State::Clean);
+        assert_eq!(cluster.count(State::Infected), 2);
+        assert_eq!(Cluster::from_str("").unwrap_err(), ParseError::Empty);
+        assert_eq!(Cluster::from_str("..#\n#.\n...\n").unwrap_err(), ParseError::Ragged { line: 2, len: 2, width: 3 });
+        assert_eq!(Cluster::from_str("..#\n#..\n...#\n").unwrap_err().to_string(), "Line 3 has 4 nodes, expected 3");
+        assert_eq!(Cluster::from_str("..#\n#.x\n...\n").unwrap_err(), ParseError::InvalidChar { row: 2, col: 3, ch: 'x' });
+        // For even sizes, the node right below and to the right of the center is the origin
+        let cluster = Cluster::from_str("#...\n....\n..#.\n...#\n").unwrap();
+        assert_eq!(cluster.get(-2, -2), State::Infected);
+        assert_eq!(cluster.get(0, 0), State::Infected);
+        assert_eq!(cluster.get(1, 1), State::Infected);
+        assert_eq!(cluster.count(State::Infected), 3);
+        let cluster = Cluster::from_str("#.\n..\n").unwrap();
+        assert_eq!(cluster.get(-1, -1), State::Infected);
     }
 
     #[test]
@@ -194,6 +731,260 @@ mod tests {
         assert_eq!(cluster.carrier_mut().take(70).filter(|&i| i).count(), 41);
     }
 
+    #[test]
+    fn bursts() {
+        let mut cluster = Cluster::from_str("..#\n#..\n...\n").unwrap();
+        let mut carrier = cluster.carrier_mut();
+        assert_eq!((carrier.position(), carrier.direction(), carrier.bursts_done()), ((0, 0), Direction::North, 0));
+        let expected = [
+            (0, 0, Direction::West, State::Clean, State::Infected),
+            (0, -1, Direction::North, State::Infected, State::Clean),
+            (-1, -1, Direction::West, State::Clean, State::Infected),
+            (-1, -2, Direction::South, State::Clean, State::Infected),
+            (0, -2, Direction::East, State::Clean, State::Infected),
+            (0, -1, Direction::North, State::Clean, State::Infected),
+            (-1, -1, Direction::East, State::Infected, State::Clean),
+        ];
+        for &(row, col, dir, old, new) in &expected {
+            let burst = carrier.burst();
+            assert_eq!(burst, Burst { row: row, col: col, dir: dir, old: old, new: new, infected: old != State::Infected && new == State::Infected });
+        }
+        assert_eq!((carrier.position(), carrier.direction(), carrier.bursts_done()), ((-1, 0), Direction::East, 7));
+        let mut cluster = Cluster::from_str("..#\n#..\n...\n").unwrap();
+        let mut carrier = cluster.carrier_advanced_mut();
+        let burst = carrier.burst();
+        assert_eq!(burst, Burst { row: 0, col: 0, dir: Direction::West, old: State::Clean, new: State::Weakened, infected: false });
+        let burst = carrier.burst();
+        assert_eq!(burst, Burst { row: 0, col: -1, dir: Direction::North, old: State::Infected, new: State::Flagged, infected: false });
+        assert_eq!((carrier.position(), carrier.direction(), carrier.bursts_done()), ((-1, -1), Direction::North, 2));
+    }
+
+    #[test]
+    fn rule_sets() {
+        let mut cluster = Cluster::from_str("..#\n#..\n...\n").unwrap();
+        assert_eq!(cluster.carrier_with(RuleSet::basic()).take(10_000).filter(|&i| i).count(), 5587);
+        let mut cluster = Cluster::from_str("..#\n#..\n...\n").unwrap();
+        assert_eq!(cluster.carrier_with(RuleSet::evolved()).take(100).filter(|&i| i).count(), 26);
+        let rules = RuleSet::new([
+            (Turn::Right, State::Weakened),
+            (Turn::Left, State::Infected),
+            (Turn::Reverse, State::Clean),
+            (Turn::Straight, State::Flagged),
+        ]);
+        let mut cluster = Cluster::from_str("..#\n#..\n...\n").unwrap();
+        assert_eq!(cluster.carrier_with(rules).take(100).filter(|&i| i).count(), 36);
+        // Infected nodes stay infected, which doesn't count as causing an infection
+        let rules = RuleSet::new([
+            (Turn::Left, State::Infected),
+            (Turn::Left, State::Infected),
+            (Turn::Right, State::Infected),
+            (Turn::Right, State::Infected),
+        ]);
+        let mut cluster = Cluster::from_str("#").unwrap();
+        let infections = {
+            let mut carrier = cluster.carrier_with(rules);
+            assert!(!carrier.burst().infected);
+            for _ in 0..99 { carrier.burst(); }
+            carrier.infections_caused()
+        };
+        assert_eq!(infections, 15);
+        assert_eq!(cluster.count(State::Infected), 1 + infections);
+        assert_eq!(Turn::Straight.apply(Direction::East), Direction::East);
+        assert_eq!(Turn::Reverse.apply(Direction::East), Direction::West);
+    }
+
+    #[test]
+    fn counting() {
+        let mut cluster = Cluster::from_str("..#\n#..\n...\n").unwrap();
+        assert_eq!(cluster.count(State::Infected), 2);
+        {
+            let mut carrier = cluster.carrier_mut();
+            for _ in 0..70 { carrier.burst(); }
+            assert_eq!(carrier.infections_caused(), 41);
+        }
+        // 2 initially infected, 41 bursts infecting and 70 - 41 bursts cleaning a node
+        assert_eq!(cluster.count(State::Infected), 2 + 41 - 29);
+        let mut cluster = Cluster::from_str("..#\n#..\n...\n").unwrap();
+        let basic = cluster.carrier_mut().take(500).filter(|&i| i).count();
+        let evolved = {
+            let mut carrier = cluster.carrier_advanced_mut();
+            for _ in 0..5000 { carrier.burst(); }
+            carrier.infections_caused()
+        };
+        assert!(basic > 0 && evolved > 0);
+        for &state in &[State::Clean, State::Weakened, State::Infected, State::Flagged] {
+            assert_eq!(cluster.count(state), cluster.states.iter().filter(|&&s| s == state).count());
+        }
+        assert_eq!((0..4).map(|i| cluster.counts[i]).sum::<usize>(), cluster.states.len());
+        cluster.set(0, 0, State::Infected);
+        let infected = cluster.count(State::Infected);
+        for _ in 0..10 {
+            cluster.set(0, 0, State::Infected);
+            cluster.set(0, 0, State::Clean);
+        }
+        assert_eq!(cluster.count(State::Infected), infected - 1);
+    }
+
+    #[test]
+    fn rendering() {
+        let mut cluster = Cluster::from_str("..#\n#..\n...\n").unwrap();
+        assert_eq!(cluster.render((0, 0), 4, Some(((0, 0), Direction::North))),
+            ". . . . . . . . .\n\
+             . . . . . . . . .\n\
+             . . . . . . . . .\n\
+             . . . . . # . . .\n\
+             . . . #[.]. . . .\n\
+             . . . . . . . . .\n\
+             . . . . . . . . .\n\
+             . . . . . . . . .\n\
+             . . . . . . . . .\n");
+        {
+            let mut carrier = cluster.carrier_mut();
+            let expected = [
+                (1, ". . . . . # . . .\n. . .[#]# . . . .\n. . . . . . . . .\n"),
+                (1, ". . .[.]. # . . .\n. . . . # . . . .\n. . . . . . . . .\n"),
+                (5, ". . # .[.]# . . .\n. . # # # . . . .\n. . . . . . . . .\n"),
+            ];
+            for &(bursts, rendering) in &expected {
+                for _ in 0..bursts { carrier.burst(); }
+                let view = carrier.cluster().render((0, 0), 4, Some((carrier.position(), carrier.direction())));
+                assert_eq!(view.lines().skip(3).take(3).collect::<Vec<_>>(), rendering.lines().collect::<Vec<_>>());
+            }
+        }
+        assert_eq!(cluster.render((0, 0), 1, Some(((0, 1), Direction::East))), ". . #\n# #[.]\n. . .\n");
+        assert_eq!(cluster.render((-1, -1), 1, Some(((-1, -2), Direction::East))), ". . .\n[#]. .\n# # #\n");
+        cluster.set(1, 1, State::Weakened);
+        cluster.set(1, -1, State::Flagged);
+        assert_eq!(cluster.render((0, 0), 1, None), ". . #\n# # .\nF . W\n");
+        assert_eq!(cluster.to_string(), cluster.render((0, 0), 4, None));
+    }
+
+    #[test]
+    fn snapshots() {
+        let mut cluster = Cluster::from_str("..#\n#..\n...\n").unwrap();
+        let bytes = {
+            let mut carrier = cluster.carrier_mut();
+            for _ in 0..5000 { carrier.burst(); }
+            carrier.snapshot().to_bytes()
+        };
+        let state = ClusterState::from_bytes(&bytes).unwrap();
+        assert_eq!(state.nodes, cluster.snapshot().nodes);
+        let mut sorted = state.nodes.clone();
+        sorted.sort_by_key(|&(row, col, _)| (row, col));
+        assert_eq!(state.nodes, sorted);
+        let mut restored = Cluster::restore(&state);
+        {
+            let mut carrier = restored.resume_carrier(state.carrier.as_ref().unwrap(), RuleSet::basic());
+            for _ in 0..5000 { carrier.burst(); }
+            assert_eq!(carrier.bursts_done(), 10_000);
+            assert_eq!(carrier.infections_caused(), 5587);
+        }
+        let mut cluster = Cluster::from_str("..#\n#..\n...\n").unwrap();
+        cluster.carrier_mut().take(10_000).count();
+        assert_eq!(restored.snapshot(), cluster.snapshot());
+        assert_eq!(ClusterState::from_bytes(&cluster.snapshot().to_bytes()), Ok(cluster.snapshot()));
+        assert_eq!(ClusterState::from_bytes(&bytes[..bytes.len() - 1]), Err(SnapshotError::Truncated));
+        assert_eq!(ClusterState::from_bytes(&[2]), Err(SnapshotError::UnknownVersion(2)));
+        assert_eq!(ClusterState::from_bytes(&[]), Err(SnapshotError::Truncated));
+        let mut bytes = bytes;
+        bytes[1 + 8 + 16] = 7;
+        assert_eq!(ClusterState::from_bytes(&bytes), Err(SnapshotError::InvalidValue(25)));
+    }
+
+    #[test]
+    fn multiple_carriers() {
+        let mut cluster = Cluster::from_str("..#\n#..\n...\n").unwrap();
+        {
+            let mut carriers = cluster.multi_carrier(1, RuleSet::basic());
+            assert_eq!(carriers.by_ref().take(10_000).filter(|infecting| !infecting.is_empty()).count(), 5587);
+            assert_eq!(carriers.infections_caused(0), 5587);
+        }
+        let mut cluster = Cluster::from_str("..#\n#..\n...\n").unwrap();
+        let mut carriers = cluster.multi_carrier(2, RuleSet::basic());
+        // Carrier 0 infects the origin and turns left, carrier 1 then finds it
+        // infected, cleans it and turns right
+        assert_eq!(carriers.next(), Some(vec![0]));
+        assert_eq!((carriers.carrier(0).row, carriers.carrier(0).col, carriers.carrier(0).dir), (0, -1, Direction::West));
+        assert_eq!((carriers.carrier(1).row, carriers.carrier(1).col, carriers.carrier(1).dir), (0, 1, Direction::East));
+        assert_eq!(carriers.cluster.get(0, 0), State::Clean);
+        for _ in carriers.by_ref().take(99) {}
+        assert_eq!((carriers.infections_caused(0), carriers.infections_caused(1)), (57, 61));
+        assert_eq!(carriers.total_infections(), 57 + 61);
+    }
+
+    #[test]
+    fn sampling() {
+        let mut cluster = Cluster::from_str("..#\n#..\n...\n").unwrap();
+        let samples = cluster.carrier_mut().run_sampled(70, 10);
+        assert_eq!(samples.len(), 7);
+        assert_eq!(samples.iter().map(|s| s.burst).collect::<Vec<_>>(), [10, 20, 30, 40, 50, 60, 70]);
+        assert_eq!(samples[6].infections_so_far, 41);
+        assert_eq!(samples[6].currently_infected, 14);
+        assert!(samples.windows(2).all(|w| w[0].infections_so_far <= w[1].infections_so_far));
+        assert_eq!(samples[0].extent, (-2, -2, 0, 1));
+        let mut csv = vec![];
+        write_series_csv(&mut csv, &samples).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 8);
+        assert_eq!(csv.lines().next(), Some("burst,infections_so_far,currently_infected,min_row,min_col,max_row,max_col"));
+        let mut cluster = Cluster::from_str("..#\n#..\n...\n").unwrap();
+        let samples = cluster.carrier_advanced_mut().run_sampled(105, 50);
+        assert_eq!(samples.iter().map(|s| s.burst).collect::<Vec<_>>(), [50, 100, 105]);
+        let mut cluster = Cluster::from_str("..#\n#..\n...\n").unwrap();
+        assert_eq!(samples[1].infections_so_far, cluster.carrier_advanced_mut().take(100).filter(|&i| i).count());
+    }
+
+    #[test]
+    fn trails() {
+        let mut cluster = Cluster::from_str("..#\n#..\n...\n").unwrap();
+        {
+            let mut carrier = cluster.carrier_mut().with_trail(4);
+            for _ in 0..7 { carrier.burst(); }
+            assert_eq!(carrier.trail().collect::<Vec<_>>(), [
+                &TrailEntry { row: -1, col: -2, state: State::Infected },
+                &TrailEntry { row: 0, col: -2, state: State::Infected },
+                &TrailEntry { row: 0, col: -1, state: State::Infected },
+                &TrailEntry { row: -1, col: -1, state: State::Clean },
+            ]);
+        }
+        let mut cluster = Cluster::from_str("..#\n#..\n...\n").unwrap();
+        {
+            let mut carrier = cluster.carrier_mut().with_trail(100);
+            for _ in 0..7 { carrier.burst(); }
+            let positions: Vec<_> = carrier.trail().map(|e| (e.row, e.col)).collect();
+            assert_eq!(positions, [(0, 0), (0, -1), (-1, -1), (-1, -2), (0, -2), (0, -1), (-1, -1)]);
+        }
+        let mut cluster = Cluster::from_str("..#\n#..\n...\n").unwrap();
+        let mut carrier = cluster.carrier_mut().with_trail(0);
+        carrier.burst();
+        assert_eq!(carrier.trail().count(), 0);
+        let mut carrier = cluster.carrier_mut();
+        carrier.burst();
+        assert_eq!(carrier.trail().count(), 0);
+    }
+
+    #[test]
+    fn basic_after_evolved() {
+        let mut cluster = Cluster::from_str("..#\n#..\n...\n").unwrap();
+        cluster.carrier_advanced_mut().take(100).count();
+        assert!(cluster.count(State::Weakened) > 0 && cluster.count(State::Flagged) > 0);
+        let mut state = cluster.snapshot();
+        for node in &mut state.nodes {
+            node.2 = match node.2 {
+                State::Weakened => State::Clean,
+                State::Flagged => State::Infected,
+                other => other,
+            };
+        }
+        let mut mapped = Cluster::restore(&state);
+        let infected = cluster.carrier_mut().take(1000).filter(|&i| i).count();
+        assert_eq!(mapped.carrier_mut().take(1000).filter(|&i| i).count(), infected);
+        assert_eq!(infected, 557);
+        let nodes: Vec<_> = cluster.snapshot().nodes.into_iter()
+            .filter(|&(_, _, state)| state != State::Weakened && state != State::Flagged).collect();
+        assert_eq!(nodes, mapped.snapshot().nodes);
+    }
+
     #[test]
     fn samples1b() {
         let mut cluster = Cluster::from_str("..#\n#..\n...\n").unwrap();
@@ -206,9 +997,33 @@ mod tests {
         assert_eq!(cluster.carrier_advanced_mut().take(100).filter(|&i| i).count(), 26);
     }
 
-    // #[test]
-    // fn samples2b() {
-    //     let mut cluster = Cluster::from_str("..#\n#..\n...\n").unwrap();
-    //     assert_eq!(cluster.carrier_advanced_mut().take(10_000_000).filter(|&i| i).count(), 2511944);
-    // }
+    #[test]
+    fn samples2b() {
+        let mut cluster = Cluster::from_str("..#\n#..\n...\n").unwrap();
+        assert_eq!(cluster.carrier_advanced_mut().take(10_000_000).filter(|&i| i).count(), 2511944);
+    }
+
+    #[test]
+    fn growing() {
+        let mut cluster = Cluster::from_str("..#\n#..\n...\n").unwrap();
+        assert_eq!(cluster.radius, 2);
+        cluster.set(-7, 3, State::Flagged);
+        cluster.set(40, -40, State::Weakened);
+        assert!(cluster.radius >= 40);
+        assert_eq!(cluster.get(-1, 1), State::Infected);
+        assert_eq!(cluster.get(0, -1), State::Infected);
+        assert_eq!(cluster.get(-7, 3), State::Flagged);
+        assert_eq!(cluster.get(40, -40), State::Weakened);
+        assert_eq!(cluster.get(1000, 0), State::Clean);
+        assert_eq!(cluster.states.iter().filter(|&&s| s != State::Clean).count(), 4);
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn benchmark_advanced(b: &mut test::Bencher) {
+        b.iter(|| {
+            let mut cluster: Cluster = include_str!("day22.txt").parse().unwrap();
+            cluster.carrier_advanced_mut().take(10_000_000).filter(|&i| i).count()
+        })
+    }
 }
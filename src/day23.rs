@@ -1,7 +1,8 @@
 #[macro_use]
 extern crate nom;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
 use std::str::FromStr;
 use nom::digit;
 
@@ -30,12 +31,21 @@ impl RegisterSet {
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 enum Value {
 	Register(char),
 	Number(i64),
 }
 
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Register(r) => write!(f, "{}", r),
+            Value::Number(n) => write!(f, "{}", n),
+        }
+    }
+}
+
 impl Value {
     fn get(&self, regs: &RegisterSet) -> i64 {
         match *self {
@@ -46,12 +56,47 @@ impl Value {
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 enum Instruction {
     Set(char, Value),
+    Add(char, Value),
     Sub(char, Value),
     Mul(char, Value),
-    Jnz(Value, Value)
+    Mod(char, Value),
+    Jnz(Value, Value),
+    Jgz(Value, Value),
+    /// Replacement for `set e <start>` at the beginning of the nested loops that search
+    /// for d and e with d * e == b, clearing f if found (only created by `optimize`)
+    DivisorSearch { b: char, d: char, e: char, f: char, g: char, start: i64 },
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}", self.mnemonic()));
+        match *self {
+            Instruction::Set(r, ref v) | Instruction::Add(r, ref v) | Instruction::Sub(r, ref v) |
+            Instruction::Mul(r, ref v) | Instruction::Mod(r, ref v) => write!(f, " {} {}", r, v),
+            Instruction::Jnz(ref v, ref ofs) | Instruction::Jgz(ref v, ref ofs) => write!(f, " {} {}", v, ofs),
+            Instruction::DivisorSearch { b, d, e, f: flag, g, start } =>
+                write!(f, " {} {} {} {} {} {}", b, d, e, flag, g, start),
+        }
+    }
+}
+
+impl Instruction {
+    /// Name of the instruction
+    fn mnemonic(&self) -> &'static str {
+        match *self {
+            Instruction::Set(..) => "set",
+            Instruction::Add(..) => "add",
+            Instruction::Sub(..) => "sub",
+            Instruction::Mul(..) => "mul",
+            Instruction::Mod(..) => "mod",
+            Instruction::Jnz(..) => "jnz",
+            Instruction::Jgz(..) => "jgz",
+            Instruction::DivisorSearch { .. } => "divsearch",
+        }
+    }
 }
 
 impl FromStr for Instruction {
@@ -70,20 +115,255 @@ impl FromStr for Instruction {
         ));
         complete!(s, alt!(
             do_parse!(tag!("set") >> x: ws!(register) >> y: ws!(value) >> (Instruction::Set(x, y))) |
+            do_parse!(tag!("add") >> x: ws!(register) >> y: ws!(value) >> (Instruction::Add(x, y))) |
             do_parse!(tag!("sub") >> x: ws!(register) >> y: ws!(value) >> (Instruction::Sub(x, y))) |
             do_parse!(tag!("mul") >> x: ws!(register) >> y: ws!(value) >> (Instruction::Mul(x, y))) |
-            do_parse!(tag!("jnz") >> x: ws!(value) >> y: ws!(value) >> (Instruction::Jnz(x, y)))
+            do_parse!(tag!("mod") >> x: ws!(register) >> y: ws!(value) >> (Instruction::Mod(x, y))) |
+            do_parse!(tag!("jnz") >> x: ws!(value) >> y: ws!(value) >> (Instruction::Jnz(x, y))) |
+            do_parse!(tag!("jgz") >> x: ws!(value) >> y: ws!(value) >> (Instruction::Jgz(x, y)))
         )).to_result()
     }
 }
 
 
+/// Replace known loop idioms by instructions that do the same without looping
+fn optimize(code: &[Instruction]) -> Vec<Instruction> {
+    let mut code = code.to_vec();
+    for pc in 0..code.len() {
+        if let Some(ins) = divisor_search(&code[pc..]) {
+            code[pc] = ins;
+        }
+    }
+    code
+}
+
+/// Recognize the nested loops of the form
+///
+/// ```text
+/// set e 2
+/// set g d
+/// mul g e
+/// sub g b
+/// jnz g 2
+/// set f 0
+/// sub e -1
+/// set g e
+/// sub g b
+/// jnz g -8
+/// sub d -1
+/// set g d
+/// sub g b
+/// jnz g -13
+/// ```
+fn divisor_search(code: &[Instruction]) -> Option<Instruction> {
+    use Instruction::*;
+    use Value::*;
+    if code.len() < 14 { return None; }
+    let (e, start) = match code[0] { Set(e, Number(n)) => (e, n), _ => return None };
+    let (g, d) = match code[1] { Set(g, Register(d)) => (g, d), _ => return None };
+    let b = match code[3] { Sub(r, Register(b)) if r == g => b, _ => return None };
+    let f = match code[5] { Set(f, Number(0)) => f, _ => return None };
+    let regs = [b, d, e, f, g];
+    if regs.iter().enumerate().any(|(i, r)| regs[i + 1..].contains(r)) { return None; }
+    let expected = [
+        Mul(g, Register(e)),
+        Jnz(Register(g), Number(2)),
+        Sub(e, Number(-1)),
+        Set(g, Register(e)),
+        Sub(g, Register(b)),
+        Jnz(Register(g), Number(-8)),
+        Sub(d, Number(-1)),
+        Set(g, Register(d)),
+        Sub(g, Register(b)),
+        Jnz(Register(g), Number(-13)),
+    ];
+    let actual = [&code[2], &code[4], &code[6], &code[7], &code[8], &code[9], &code[10], &code[11], &code[12], &code[13]];
+    if expected.iter().zip(actual.iter()).any(|(a, b)| a != *b) { return None; }
+    Some(DivisorSearch { b: b, d: d, e: e, f: f, g: g, start: start })
+}
+
+
+/// Execution counts of a program run
+#[derive(Debug, Default, PartialEq)]
+struct Profile {
+    /// Number of executions by program counter
+    counts: Vec<u64>,
+    /// Number of executions by mnemonic
+    mnemonics: BTreeMap<&'static str, u64>,
+    /// Number of taken and not taken jumps by program counter
+    jumps: BTreeMap<usize, (u64, u64)>,
+}
+
+#[allow(dead_code)]
+impl Profile {
+    /// Program counters with the most executions, most executed first
+    fn hottest(&self, n: usize) -> Vec<(usize, u64)> {
+        let mut counts: Vec<(usize, u64)> = self.counts.iter().cloned().enumerate().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        counts.truncate(n);
+        counts
+    }
+
+    /// Listing of the given code with execution counts
+    fn report(&self, code: &[Instruction]) -> String {
+        let mut s = String::new();
+        for (pc, ins) in code.iter().enumerate() {
+            let count = self.counts.get(pc).cloned().unwrap_or(0);
+            s.push_str(&format!("{:>12} {:>4}: {}", count, pc, ins));
+            if let Some(&(taken, not_taken)) = self.jumps.get(&pc) {
+                s.push_str(&format!("  (taken {}, not taken {})", taken, not_taken));
+            }
+            s.push('\n');
+        }
+        for (mnemonic, count) in &self.mnemonics {
+            s.push_str(&format!("{:>12} {}\n", count, mnemonic));
+        }
+        s
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum CoreError {
+    OutOfInstructions,
+    /// Modulus of zero taken in the instruction at pc
+    DivisionByZero { pc: usize },
+    /// Arithmetic overflow in the instruction at pc
+    Overflow { pc: usize },
+    /// Jump from the given instruction to a target outside of the code (saturated
+    /// if not representable). Terminates the core like running out of instructions.
+    JumpedOutOfBounds { from: usize, target: i64 },
+}
+
+
+/// Behavior of arithmetic instructions on overflow
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(dead_code)]
+enum Arithmetic {
+    /// Wrap around on overflow (default)
+    Wrapping,
+    /// Saturate at the numeric bounds on overflow
+    Saturating,
+    /// Fail on overflow
+    Checked,
+}
+
+impl Arithmetic {
+    fn add(self, a: i64, b: i64) -> Option<i64> {
+        match self {
+            Arithmetic::Wrapping => Some(a.wrapping_add(b)),
+            Arithmetic::Saturating => Some(a.saturating_add(b)),
+            Arithmetic::Checked => a.checked_add(b),
+        }
+    }
+
+    fn sub(self, a: i64, b: i64) -> Option<i64> {
+        match self {
+            Arithmetic::Wrapping => Some(a.wrapping_sub(b)),
+            Arithmetic::Saturating => Some(a.saturating_sub(b)),
+            Arithmetic::Checked => a.checked_sub(b),
+        }
+    }
+
+    fn mul(self, a: i64, b: i64) -> Option<i64> {
+        match self {
+            Arithmetic::Wrapping => Some(a.wrapping_mul(b)),
+            Arithmetic::Saturating => Some(a.saturating_mul(b)),
+            Arithmetic::Checked => a.checked_mul(b),
+        }
+    }
+
+    /// Remainder of a non-zero divisor (only `i64::MIN % -1` overflows)
+    fn rem(self, a: i64, b: i64) -> Option<i64> {
+        match self {
+            Arithmetic::Checked => a.checked_rem(b),
+            _ => Some(a.wrapping_rem(b)),
+        }
+    }
+}
+
+
+/// Result of running code for a limited number of steps
+#[derive(Debug, PartialEq)]
+#[allow(dead_code)]
+enum RunOutcome {
+    /// Program ended after the given total number of steps
+    Halted { steps: u64 },
+    /// Program failed with the given error after the given total number of steps
+    Failed { error: CoreError, steps: u64 },
+    /// Step budget was used up, with the given total number of steps done so far
+    Budget { steps: u64 },
+    /// Progress callback stopped execution after the given total number of steps
+    Cancelled { steps: u64 },
+}
+
+
+/// Conditions for stopping a run. Any of the conditions stops execution.
+#[derive(Debug, Clone, Default)]
+#[allow(dead_code)]
+struct StopCondition {
+    /// Stop before executing an instruction at these program counters
+    breakpoints: BTreeSet<usize>,
+    /// Stop after one of these registers changed
+    changes: Vec<char>,
+    /// Stop after one of these registers reached the given value
+    thresholds: Vec<(char, i64)>,
+}
+
+#[allow(dead_code)]
+impl StopCondition {
+    fn new() -> StopCondition {
+        StopCondition::default()
+    }
+
+    /// Also stop before executing the instruction at the given program counter
+    fn breakpoint(mut self, pc: usize) -> StopCondition {
+        self.breakpoints.insert(pc);
+        self
+    }
+
+    /// Also stop whenever the given register changes
+    fn watch(mut self, r: char) -> StopCondition {
+        self.changes.push(r);
+        self
+    }
+
+    /// Also stop when the given register goes from below to at least the given value
+    fn threshold(mut self, r: char, n: i64) -> StopCondition {
+        self.thresholds.push((r, n));
+        self
+    }
+}
+
+
+/// Reason for a stopped run. Program counters refer to the instruction that caused the
+/// stop, steps are the total number of steps done.
+#[derive(Debug, PartialEq)]
+#[allow(dead_code)]
+enum StopReason {
+    /// Breakpoint reached, instruction at pc not executed yet
+    Breakpoint { pc: usize, steps: u64 },
+    /// Watched register changed its value
+    Changed { register: char, old: i64, new: i64, pc: usize, steps: u64 },
+    /// Watched register reached its threshold
+    Threshold { register: char, value: i64, pc: usize, steps: u64 },
+    /// Program ended
+    Halted { steps: u64 },
+    /// Program failed with the given error
+    Failed { error: CoreError, steps: u64 },
+}
+
+
 #[derive(Debug, Clone)]
 struct Core {
     code: Vec<Instruction>,
     pc: usize,
     regs: RegisterSet,
     multiplications: usize,
+    steps: u64,
+    arithmetic: Arithmetic,
+    /// Breakpoint that stopped the last run, not triggering again until executed
+    breakpoint: Option<usize>,
 }
 
 impl FromStr for Core {
@@ -95,18 +375,41 @@ impl FromStr for Core {
             pc: 0,
             regs: RegisterSet::new(),
             multiplications: 0,
+            steps: 0,
+            arithmetic: Arithmetic::Wrapping,
+            breakpoint: None,
         })
     }
 }
 
 impl Core {
+    /// Use the given arithmetic behavior
+    #[allow(dead_code)]
+    fn with_arithmetic(mut self, arithmetic: Arithmetic) -> Core {
+        self.arithmetic = arithmetic;
+        self
+    }
+
     fn reset(&mut self) {
         self.pc = 0;
         self.regs.clear();
         self.multiplications = 0;
+        self.steps = 0;
+        self.breakpoint = None;
     }
 
-    fn step(&mut self) -> Result<(), ()> {
+    /// Target of a jump by the given offset
+    fn jump_target(&self, ofs: i64) -> Result<usize, CoreError> {
+        let target = (self.pc as i64).checked_add(ofs).unwrap_or(if ofs < 0 { i64::MIN } else { i64::MAX });
+        if target < 0 || target >= self.code.len() as i64 {
+            return Err(CoreError::JumpedOutOfBounds { from: self.pc, target: target });
+        }
+        Ok(target as usize)
+    }
+
+    fn step(&mut self) -> Result<(), CoreError> {
+        let overflow = CoreError::Overflow { pc: self.pc };
+        let mut next = self.pc + 1;
         match self.code.get(self.pc) {
             Some(ins) => {
                 match ins {
@@ -114,53 +417,183 @@ impl Core {
                         let n = v.get(&self.regs);
                         self.regs.set(r, n)
                     },
+                    &Instruction::Add(r, ref v) => {
+                        let n = try!(self.arithmetic.add(self.regs.get(r), v.get(&self.regs)).ok_or(overflow));
+                        self.regs.set(r, n);
+                    },
                     &Instruction::Sub(r, ref v) => {
-                        let n = self.regs.get(r) - v.get(&self.regs);
+                        let n = try!(self.arithmetic.sub(self.regs.get(r), v.get(&self.regs)).ok_or(overflow));
                         self.regs.set(r, n);
                     },
                     &Instruction::Mul(r, ref v) => {
-                        let n = self.regs.get(r) * v.get(&self.regs);
+                        let n = try!(self.arithmetic.mul(self.regs.get(r), v.get(&self.regs)).ok_or(overflow));
                         self.regs.set(r, n);
                         self.multiplications += 1;
                     },
+                    &Instruction::Mod(r, ref v) => {
+                        let m = v.get(&self.regs);
+                        if m == 0 { return Err(CoreError::DivisionByZero { pc: self.pc }); }
+                        let n = try!(self.arithmetic.rem(self.regs.get(r), m).ok_or(overflow));
+                        self.regs.set(r, n);
+                    },
                     &Instruction::Jnz(ref v, ref ofs) => {
                         if v.get(&self.regs) != 0 {
-                            let ofs = ofs.get(&self.regs);
-                            self.pc = (self.pc as isize + ofs as isize - 1) as usize;
+                            next = try!(self.jump_target(ofs.get(&self.regs)));
+                        }
+                    },
+                    &Instruction::Jgz(ref v, ref ofs) => {
+                        if v.get(&self.regs) > 0 {
+                            next = try!(self.jump_target(ofs.get(&self.regs)));
+                        }
+                    },
+                    &Instruction::DivisorSearch { b, d, e, f, g, start } => {
+                        let (bv, dv) = (self.regs.get(b), self.regs.get(d));
+                        // Only for positive ranges where d * e can't overflow in the loops
+                        if 2 <= start && 2 <= dv && start < bv && dv < bv && bv.checked_mul(bv).is_some() {
+                            // Both loops run until their counter reaches b
+                            if (1..).take_while(|q| q * q <= bv).filter(|q| bv % q == 0).any(|q|
+                                [(q, bv / q), (bv / q, q)].iter().any(|&(x, y)| x >= dv && x < bv && y >= start && y < bv)
+                            ) {
+                                self.regs.set(f, 0);
+                            }
+                            self.multiplications += ((bv - dv) * (bv - start)) as usize;
+                            self.regs.set(d, bv);
+                            self.regs.set(e, bv);
+                            self.regs.set(g, 0);
+                            next += 13;
+                        } else {
+                            self.regs.set(e, start);
                         }
                     },
                 }
-                self.pc += 1;
+                self.pc = next;
+                self.steps += 1;
+                self.breakpoint = None;
                 Ok(())
             }
-            None => Err(()),
+            None => Err(CoreError::OutOfInstructions),
+        }
+    }
+
+    /// Run until the program ends, i.e. runs out of instructions or jumps outside of the code
+    fn run(&mut self) -> Result<(), CoreError> {
+        loop {
+            match self.step() {
+                Ok(()) => (),
+                Err(CoreError::OutOfInstructions) | Err(CoreError::JumpedOutOfBounds { .. }) => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Run until the program ends or the given number of steps was done
+    #[allow(dead_code)]
+    fn run_bounded(&mut self, max_steps: u64) -> RunOutcome {
+        for _ in 0..max_steps {
+            match self.step() {
+                Ok(()) => (),
+                Err(CoreError::OutOfInstructions) => return RunOutcome::Halted { steps: self.steps },
+                Err(err) => return RunOutcome::Failed { error: err, steps: self.steps },
+            }
+        }
+        if self.pc < self.code.len() {
+            RunOutcome::Budget { steps: self.steps }
+        } else {
+            RunOutcome::Halted { steps: self.steps }
+        }
+    }
+
+    /// Run until the program ends, calling the given function every given number of
+    /// steps with the total number of steps done. Stops if the function returns false.
+    /// Panics if the given number of steps is zero.
+    #[allow(dead_code)]
+    fn run_with_progress<F: FnMut(&Core, u64) -> bool>(&mut self, every: u64, mut f: F) -> RunOutcome {
+        assert!(every > 0, "progress interval must not be zero");
+        loop {
+            match self.run_bounded(every) {
+                RunOutcome::Budget { steps } => if !f(self, steps) { return RunOutcome::Cancelled { steps: steps } },
+                outcome => return outcome,
+            }
+        }
+    }
+
+    /// Run until the program ends, counting executions
+    #[allow(dead_code)]
+    fn run_profiled(&mut self) -> Profile {
+        self.run_profiled_bounded(u64::MAX)
+    }
+
+    /// Run until the program ends or the given number of steps was done, counting executions
+    fn run_profiled_bounded(&mut self, max_steps: u64) -> Profile {
+        let mut profile = Profile { counts: vec![0; self.code.len()], ..Profile::default() };
+        for _ in 0..max_steps {
+            let pc = self.pc;
+            let (mnemonic, taken) = match self.code.get(pc) {
+                Some(Instruction::Jnz(v, _)) => ("jnz", Some(v.get(&self.regs) != 0)),
+                Some(Instruction::Jgz(v, _)) => ("jgz", Some(v.get(&self.regs) > 0)),
+                Some(ins) => (ins.mnemonic(), None),
+                None => break,
+            };
+            if self.step().is_err() { break; }
+            profile.counts[pc] += 1;
+            *profile.mnemonics.entry(mnemonic).or_insert(0) += 1;
+            if let Some(taken) = taken {
+                let jumps = profile.jumps.entry(pc).or_insert((0, 0));
+                if taken { jumps.0 += 1; } else { jumps.1 += 1; }
+            }
+        }
+        profile
+    }
+
+    /// Run until the program ends or one of the given conditions applies. Can be called
+    /// again to resume, which executes the instruction at a breakpoint that just stopped.
+    #[allow(dead_code)]
+    fn run_until(&mut self, stop: &StopCondition) -> StopReason {
+        loop {
+            let pc = self.pc;
+            if stop.breakpoints.contains(&pc) && self.breakpoint != Some(pc) {
+                self.breakpoint = Some(pc);
+                return StopReason::Breakpoint { pc: pc, steps: self.steps };
+            }
+            let old: Vec<i64> = stop.changes.iter().map(|&r| self.regs.get(r)).collect();
+            let below: Vec<bool> = stop.thresholds.iter().map(|&(r, n)| self.regs.get(r) < n).collect();
+            match self.step() {
+                Ok(()) => (),
+                Err(CoreError::OutOfInstructions) => return StopReason::Halted { steps: self.steps },
+                Err(err) => return StopReason::Failed { error: err, steps: self.steps },
+            }
+            for (&r, &old) in stop.changes.iter().zip(old.iter()) {
+                let new = self.regs.get(r);
+                if new != old {
+                    return StopReason::Changed { register: r, old: old, new: new, pc: pc, steps: self.steps };
+                }
+            }
+            for (&(r, n), &below) in stop.thresholds.iter().zip(below.iter()) {
+                let value = self.regs.get(r);
+                if below && value >= n {
+                    return StopReason::Threshold { register: r, value: value, pc: pc, steps: self.steps };
+                }
+            }
         }
     }
 
-    fn run(&mut self) {
-        while self.step().is_ok() {}
+    /// Optimize the code and run it
+    fn run_optimized(&mut self) -> Result<(), CoreError> {
+        self.code = optimize(&self.code);
+        self.run()
     }
 }
 
 
 fn main() {
     let mut core: Core = include_str!("day23.txt").parse().unwrap();
-    core.run();
+    core.run().unwrap();
     println!("Number of invoked mul instructions: {}", core.multiplications);
 
     core.reset();
     core.regs.set('a', 1);
-    // core.run();
-    // println!("Value of register h after completion: {}", core.regs.get('h'));
-
-    // Optimized Rust version:
-    //```
-    // let h = (0..1000+1).filter(|i| {
-    //     let b = 109900 + 17 * i;
-    //     (2..b/2).any(|d| (d..b/2).any(|e| d * e == b))
-    // }).count();
-    // println!("Value of register h after completion: {}", h);
-    //```
+    core.run_optimized().unwrap();
+    println!("Value of register h after completion: {}", core.regs.get('h'));
 }
 
 
@@ -172,4 +605,170 @@ mod tests {
     fn parsing() {
         assert!(Core::from_str(include_str!("day23.txt")).is_ok());
     }
+
+    #[test]
+    fn instructions() {
+        assert_eq!(Core::from_str(include_str!("day23.txt")).unwrap().code.len(), 32);
+        assert!(Instruction::from_str("snd a").is_err());
+        assert!(Instruction::from_str("adx a 1").is_err());
+        assert!(Instruction::from_str("jgz 1").is_err());
+        let mut core: Core = "set a 7\nadd a 5\nmul a 3\nsub a 1\nset b a\nmod b 8\nset c 0\n\
+            add c b\nsub b 1\njgz b -2\njnz c 2\nset d 99\nadd d 1\n".parse().unwrap();
+        assert_eq!(core.run(), Ok(()));
+        assert_eq!((core.regs.get('a'), core.regs.get('b'), core.regs.get('c'), core.regs.get('d')), (35, 0, 6, 1));
+        assert_eq!(core.multiplications, 1);
+        let mut core: Core = "set a -7\nmod a 4\nset b 0\nmod a b\nset c 1\n".parse().unwrap();
+        assert_eq!(core.run(), Err(CoreError::DivisionByZero { pc: 3 }));
+        assert_eq!((core.regs.get('a'), core.regs.get('c'), core.pc), (-3, 0, 3));
+        core.reset();
+        assert_eq!(core.run_bounded(10), RunOutcome::Failed { error: CoreError::DivisionByZero { pc: 3 }, steps: 3 });
+    }
+
+    #[test]
+    fn arithmetic() {
+        let code = "set a 9223372036854775807\nmul a 2\n";
+        let mut core: Core = code.parse().unwrap();
+        assert_eq!(core.run(), Ok(()));
+        assert_eq!(core.regs.get('a'), -2);
+        let mut core = Core::from_str(code).unwrap().with_arithmetic(Arithmetic::Saturating);
+        assert_eq!(core.run(), Ok(()));
+        assert_eq!(core.regs.get('a'), i64::MAX);
+        let mut core = Core::from_str(code).unwrap().with_arithmetic(Arithmetic::Checked);
+        assert_eq!(core.run(), Err(CoreError::Overflow { pc: 1 }));
+        assert_eq!((core.regs.get('a'), core.pc, core.multiplications), (i64::MAX, 1, 0));
+        let mut core = Core::from_str("set a -9223372036854775807\nsub a 1\nmod a -1\n").unwrap()
+            .with_arithmetic(Arithmetic::Checked);
+        assert_eq!(core.run(), Err(CoreError::Overflow { pc: 2 }));
+        let mut core = Core::from_str(include_str!("day23.txt")).unwrap().with_arithmetic(Arithmetic::Checked);
+        assert_eq!(core.run(), Ok(()));
+        assert_eq!(core.multiplications, 9409);
+    }
+
+    #[test]
+    fn debugging() {
+        let mut core: Core = include_str!("day23.txt").parse().unwrap();
+        let stop = StopCondition::new().watch('f');
+        assert_eq!(core.run_until(&stop), StopReason::Changed { register: 'f', old: 0, new: 1, pc: 8, steps: 5 });
+        assert_eq!(core.run_until(&stop), StopReason::Changed { register: 'f', old: 1, new: 0, pc: 15, steps: 1041 });
+        assert_eq!((core.regs.get('d'), core.regs.get('e')), (3, 33));
+        assert_eq!(core.run_until(&stop), StopReason::Failed { error: CoreError::JumpedOutOfBounds { from: 29, target: 32 }, steps: 75772 });
+        assert_eq!(core.run_until(&stop), StopReason::Failed { error: CoreError::JumpedOutOfBounds { from: 29, target: 32 }, steps: 75772 });
+
+        let mut core: Core = include_str!("day23.txt").parse().unwrap();
+        let stop = StopCondition::new().breakpoint(12);
+        assert_eq!(core.run_until(&stop), StopReason::Breakpoint { pc: 12, steps: 8 });
+        assert_eq!(core.multiplications, 0);
+        assert_eq!(core.run_until(&stop), StopReason::Breakpoint { pc: 12, steps: 16 });
+        assert_eq!(core.multiplications, 1);
+        assert_eq!(core.run_until(&stop), StopReason::Breakpoint { pc: 12, steps: 24 });
+        assert_eq!(core.multiplications, 2);
+        core.reset();
+        let stop = StopCondition::new().breakpoint(0).breakpoint(2);
+        assert_eq!(core.run_until(&stop), StopReason::Breakpoint { pc: 0, steps: 0 });
+        assert_eq!(core.run_until(&stop), StopReason::Breakpoint { pc: 2, steps: 2 });
+        assert_eq!(core.run_until(&stop), StopReason::Failed { error: CoreError::JumpedOutOfBounds { from: 29, target: 32 }, steps: 75772 });
+
+        let mut core: Core = include_str!("day23.txt").parse().unwrap();
+        let stop = StopCondition::new().threshold('d', 50).breakpoint(25);
+        assert_eq!(core.run_until(&stop), StopReason::Threshold { register: 'd', value: 50, pc: 20, steps: 37495 });
+        assert_eq!(core.run_until(&stop), StopReason::Breakpoint { pc: 25, steps: 75768 });
+        assert_eq!((core.regs.get('f'), core.regs.get('h')), (0, 0));
+    }
+
+    #[test]
+    fn bounded() {
+        // The program ends by jumping right behind its last instruction
+        let mut core: Core = include_str!("day23.txt").parse().unwrap();
+        assert_eq!(core.run_bounded(1_000_000), RunOutcome::Failed { error: CoreError::JumpedOutOfBounds { from: 29, target: 32 }, steps: 75772 });
+        assert_eq!(core.multiplications, 9409);
+        let mut core: Core = "set a 5\nsub a 1\njnz 1 -1\n".parse().unwrap();
+        assert_eq!(core.run_bounded(1000), RunOutcome::Budget { steps: 1000 });
+        assert_eq!(core.run_bounded(1000), RunOutcome::Budget { steps: 2000 });
+        assert_eq!(core.regs.get('a'), 5 - 1000);
+        let mut looping: Core = "jnz 1 0\n".parse().unwrap();
+        assert_eq!(looping.run_bounded(1000), RunOutcome::Budget { steps: 1000 });
+        assert_eq!(looping.pc, 0);
+        for &(program, target) in &[("jnz 1 -5\n", -5), ("jgz 1 9223372036854775807\n", i64::MAX), ("jnz 1 -9223372036854775807\n", -i64::MAX)] {
+            let mut jumping: Core = program.parse().unwrap();
+            let error = CoreError::JumpedOutOfBounds { from: 0, target: target };
+            assert_eq!(jumping.run_bounded(1000), RunOutcome::Failed { error: error, steps: 0 });
+            assert_eq!(jumping.pc, 0);
+            assert_eq!(jumping.run(), Ok(()));
+        }
+        let mut jumping: Core = "set a 1\njgz a 2\nset b 1\n".parse().unwrap();
+        assert_eq!(jumping.step(), Ok(()));
+        assert_eq!(jumping.step(), Err(CoreError::JumpedOutOfBounds { from: 1, target: 3 }));
+        let mut calls = vec![];
+        let outcome = core.run_with_progress(100, |core, steps| {
+            calls.push((core.steps, steps));
+            calls.len() < 2
+        });
+        assert_eq!(outcome, RunOutcome::Cancelled { steps: 2200 });
+        assert_eq!(calls, [(2100, 2100), (2200, 2200)]);
+        let mut core: Core = include_str!("day23.txt").parse().unwrap();
+        let mut calls = 0;
+        assert_eq!(core.run_with_progress(1000, |_, _| { calls += 1; true }), RunOutcome::Failed { error: CoreError::JumpedOutOfBounds { from: 29, target: 32 }, steps: 75772 });
+        assert_eq!(calls, 75);
+    }
+
+    #[test]
+    #[should_panic(expected = "progress interval")]
+    fn progress_without_interval() {
+        let mut core: Core = include_str!("day23.txt").parse().unwrap();
+        core.run_with_progress(0, |_, _| true);
+    }
+
+    #[test]
+    fn profiling() {
+        let mut core: Core = include_str!("day23.txt").parse().unwrap();
+        let profile = core.run_profiled();
+        assert_eq!(profile.counts[12], core.multiplications as u64);
+        assert_eq!(profile.mnemonics["mul"], 9409);
+        assert_eq!(profile.counts.iter().sum::<u64>(), core.steps);
+        assert_eq!(profile.hottest(1), [(11, 9409)]);
+        assert_eq!(profile.jumps[&19], (9409 - 97, 97));
+        assert_eq!(profile.jumps[&2], (0, 1));
+        let report = profile.report(&core.code);
+        assert!(report.lines().any(|line| line == "        9409   11: set g d"));
+        assert!(report.contains("jnz g -8  (taken 9312, not taken 97)"));
+        let mut core: Core = "set a 3\nsub a 1\njgz a -1\njnz a 2\nset b 1\n".parse().unwrap();
+        let profile = core.run_profiled();
+        assert_eq!(profile.jumps[&2], (2, 1));
+        assert_eq!(profile.jumps[&3], (0, 1));
+        assert_eq!(profile.mnemonics["jgz"], 3);
+        assert!(profile.report(&core.code).contains("jgz a -1  (taken 2, not taken 1)"));
+        let mut core: Core = include_str!("day23.txt").parse().unwrap();
+        let profile = core.run_profiled_bounded(10);
+        assert_eq!(profile.counts.iter().sum::<u64>(), 10);
+        assert_eq!(core.steps, 10);
+    }
+
+    #[test]
+    fn optimizing() {
+        let mut core: Core = include_str!("day23.txt").parse().unwrap();
+        core.run().unwrap();
+        let multiplications = core.multiplications;
+        let mut core: Core = include_str!("day23.txt").parse().unwrap();
+        core.run_optimized().unwrap();
+        assert_eq!(core.multiplications, multiplications);
+        assert!(core.code.iter().any(|ins| matches!(*ins, Instruction::DivisorSearch { .. })));
+        let loops = "set g d\nmul g e\nsub g b\njnz g 2\nset f 0\nsub e -1\nset g e\nsub g b\njnz g -8\n\
+                     sub d -1\nset g d\nsub g b\njnz g -13\njnz f 2\nsub h -1\n";
+        for &(b, d, e) in &[(15, 2, 2), (13, 2, 2), (49, 2, 2), (49, 8, 2), (35, 2, 6), (20, 3, 5), (4, 2, 3), (6, 5, 2), (7, -7, -1), (7, 1, 1)] {
+            let program = format!("set b {}\nset f 1\nset d {}\nset e {}\n{}", b, d, e, loops);
+            let mut plain: Core = program.parse().unwrap();
+            plain.run().unwrap();
+            let mut optimized: Core = program.parse().unwrap();
+            optimized.run_optimized().unwrap();
+            assert_eq!(optimized.code[3], Instruction::DivisorSearch { b: 'b', d: 'd', e: 'e', f: 'f', g: 'g', start: e });
+            for r in "abcdefgh".chars() {
+                assert_eq!(optimized.regs.get(r), plain.regs.get(r), "register {} for b={}, d={}, e={}", r, b, d, e);
+            }
+            assert_eq!(optimized.multiplications, plain.multiplications);
+        }
+        let program = "set e 2\nset g d\nmul g e\nsub g b\njnz g 3\nset f 0\nsub e -1\nset g e\nsub g b\njnz g -8\n\
+                       sub d -1\nset g d\nsub g b\njnz g -13\n";
+        let code: Vec<Instruction> = program.lines().map(|l| l.parse().unwrap()).collect();
+        assert_eq!(optimize(&code), code);
+    }
 }